@@ -0,0 +1,118 @@
+use crate::board_state::BoardState;
+use crate::engine::{search_position, SearchOptions};
+use std::time::{Duration, Instant};
+
+/*
+    Searches a fixed set of positions to a fixed depth. The total node count only changes when the search does,
+    so it works as a signature for checking that a change is functionally neutral, and the time taken measures speed.
+*/
+
+pub const DEFAULT_BENCH_DEPTH: u16 = 3;
+
+//Openings, middlegames and endgames, most of them from the Stockfish bench. Positions where quiescence blows up are left out so a bench stays quick
+pub const BENCH_POSITIONS: [&str; 40] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 11",
+    "4rrk1/pp1n3p/3q2pQ/2p1pb2/2PP4/2P3N1/P2B2PP/4RRK1 b - - 7 19",
+    "rq3rk1/ppp2ppp/1bnpb3/3N2B1/3NP3/7P/PPPQ1PP1/2KR3R w - - 7 14",
+    "r1bq1r1k/1pp1n1pp/1p1p4/4p2Q/4Pp2/1BNP4/PPP2PPP/3R1RK1 w - - 2 14",
+    "r3r1k1/2p2ppp/p1p1bn2/8/1q2P3/2NPQN2/PPP3PP/R4RK1 b - - 2 15",
+    "r1bbk1nr/pp3p1p/2n5/1N4p1/2Np1B2/8/PPP2PPP/2KR1B1R w kq - 0 13",
+    "r1bq1rk1/ppp1nppp/4n3/3p3Q/3P4/1BP1B3/PP1N2PP/R4RK1 w - - 1 16",
+    "4r1k1/r1q2ppp/ppp2n2/4P3/5Rb1/1N1BQ3/PPP3PP/R5K1 w - - 1 17",
+    "rnbqkb1r/pp1p1ppp/4pn2/2p5/2PP4/2N5/PP2PPPP/R1BQKBNR w KQkq - 0 4",
+    "r1bq1r1k/b1p1npp1/p2p3p/1p6/3PP3/1B2NN2/PP3PPP/R2Q1RK1 w - - 1 16",
+    "3r1rk1/p5pp/bpp1pp2/8/q1PP1P2/b3P3/P2NQRPP/1R2B1K1 b - - 6 22",
+    "r1q2rk1/2p1bppp/2Pp4/p6b/Q1PNp3/4B3/PP1R1PPP/2K4R w - - 2 18",
+    "4k2r/1pb2ppp/1p2p3/1R1p4/3P4/2r1PN2/P4PPP/1R4K1 b - - 3 22",
+    "3q2k1/pb3p1p/4pbp1/2r5/PpN2N2/1P2P2P/5PP1/Q2R2K1 b - - 4 26",
+    "r3k2r/3nnpbp/q2pp1p1/p7/Pp1PPPP1/4BNN1/1P5P/R2Q1RK1 w kq - 0 16",
+    "rnbqkbnr/pp2pppp/3p4/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 3",
+    "8/5pk1/6p1/8/3R4/6P1/5PK1/r7 w - - 0 1",
+    "5rk1/q6p/2p3bR/1pPp1rP1/1P1Pp3/P3B1Q1/1K3P2/R7 w - - 93 90",
+    "8/8/4k3/3p4/3P4/4K3/8/8 w - - 0 1",
+    "1r3k2/4q3/2Pp3b/3Bp3/2Q2p2/1p1P2P1/1P2KP2/3N4 w - - 0 1",
+    "6k1/4pp1p/3p2p1/P1pPb3/R7/1r2P1PP/3B1P2/6K1 w - - 0 1",
+    "6k1/6p1/P6p/r1N5/5p2/7P/1b3PP1/4R1K1 w - - 0 1",
+    "7k/3p2pp/4q3/8/4Q3/5Kp1/P6b/8 w - - 0 1",
+    "8/6pk/1p6/8/PP3p1p/5P2/4KP1q/3Q4 w - - 0 1",
+    "6k1/6p1/6Pp/ppp5/3pn2P/1P3K2/1PP2P2/3N4 b - - 0 1",
+    "3b4/5kp1/1p1p1p1p/pP1PpP1P/P1P1P3/3KN3/8/8 w - - 0 1",
+    "8/pp2r1k1/2p1p3/3pP2p/1P1P1P1P/P5KR/8/8 w - - 0 1",
+    "8/3p4/p1bk3p/Pp6/1Kp1PpPp/2P2P1P/2P5/5B2 b - - 0 1",
+    "8/3p3B/5p2/5P2/p7/PP5b/k7/6K1 w - - 0 1",
+    "2K5/p7/7P/5pR1/8/5k2/r7/8 w - - 0 1",
+    "5k2/7R/4P2p/5K2/p1r2P1p/8/8/8 b - - 0 1",
+    "8/2p5/8/2kPKp1p/2p4P/2P5/3P4/8 w - - 0 1",
+    "8/1p3pp1/7p/5P1P/2k3P1/8/2K2P2/8 w - - 0 1",
+    "8/8/1P6/5pr1/8/4R3/7k/2K5 w - - 0 1",
+    "8/2p4P/8/kr6/6R1/8/8/1K6 w - - 0 1",
+    "8/8/8/8/5kp1/P7/8/1K1N4 w - - 0 1",
+    "8/3k4/8/8/8/4B3/4KB2/2B5 w - - 0 1",
+    "8/8/8/5N2/8/p7/8/2NK3k w - - 0 1",
+];
+
+pub struct BenchResult {
+    pub nodes: Vec<u64>, //One count per position, in order
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn total_nodes(&self) -> u64 {
+        self.nodes.iter().sum()
+    }
+}
+
+/* Searches each position from scratch, nothing carries over from one to the next */
+pub fn bench(positions: &[&str], depth: u16, options: &SearchOptions) -> BenchResult {
+    let mut options = options.clone();
+    options.max_depth = depth;
+    options.node_limit = None;
+
+    let start = Instant::now();
+    let nodes = positions
+        .iter()
+        .map(|fen| {
+            let board = BoardState::new(fen)
+                .unwrap_or_else(|e| panic!("Bad bench position {}: {}", fen, e));
+            search_position(&board, &[], Duration::MAX, &options).nodes_searched
+        })
+        .collect();
+    BenchResult {
+        nodes,
+        elapsed: start.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::move_gen::gen_all_moves;
+
+    #[test]
+    fn positions_are_playable() {
+        for fen in BENCH_POSITIONS {
+            let board = BoardState::new(fen).unwrap();
+            assert!(
+                !board.is_in_check(board.active_color.opposite(), None),
+                "{} leaves the side that just moved in check",
+                fen
+            );
+            assert!(
+                !gen_all_moves(&board, board.active_color).is_empty(),
+                "{} is already over",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn node_count_is_repeatable() {
+        let positions = &BENCH_POSITIONS[..3];
+        let first = bench(positions, 2, &SearchOptions::default());
+        let second = bench(positions, 2, &SearchOptions::default());
+        assert_eq!(first.nodes, second.nodes);
+        assert!(first.nodes.iter().all(|&nodes| nodes > 0));
+    }
+}
@@ -3,9 +3,12 @@ use std::process::exit;
 /* This crate encapsualtes a board state for a chess game */
 use crate::chess_move::*;
 use crate::color::*;
+use crate::evaluation::PieceSquareSums;
 use crate::move_gen::{king_positions, knight_positions};
+use crate::nnue::{self, Accumulator};
 use crate::piece::*;
 use crate::square::*;
+use crate::zobrist;
 use num::abs;
 
 ///Bools that describe which side can castle at any given point in time
@@ -17,13 +20,44 @@ pub struct CastleRights {
     pub can_castle_black_queenside: bool,
 }
 ///A boardstate is a 12x12 filled with Piece Structs. Active color is the color whose turn it is to play. en_passant is the position of a pawn that just moved up two squares.
+///halfmove_clock counts the moves since the last capture or pawn move, used for the fifty move rule.
+///piece_square holds the material and piece square table sums, which make_move keeps up to date through set_piece.
+///last_capture is the piece taken by last_move, if any.
+///accumulator is the hidden layer of the loaded network, None when evaluating without one.
+///key, piece_counts and light_square_bishops are kept up to date by make_move as well, so change the board through it rather than through the fields.
 #[derive(Clone, Copy)]
 pub struct BoardState {
     pub squares: [[Square; 12]; 12],
     pub active_color: Color,
     pub castle_rights: CastleRights,
     pub en_passant: Option<Position>,
-    pub last_move: Option<Move>,
+    pub last_move: Option<MoveV2>,
+    pub last_capture: Option<Piece>,
+    pub halfmove_clock: u16,
+    pub piece_square: PieceSquareSums,
+    pub accumulator: Option<Accumulator>,
+    key: u64,
+    piece_counts: [[u8; 6]; 2], //By color then piece type, white first
+    light_square_bishops: [u8; 2],
+}
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+fn piece_type_index(piece_type: PieceType) -> usize {
+    match piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+        PieceType::None => panic!("something went wrong"),
+    }
 }
 
 impl BoardState {
@@ -99,43 +133,66 @@ impl BoardState {
         //Variables for enpassant goodness
         let en_passant: Option<Position>;
         let x: usize;
-        let y: usize;
         if fen_strings[3].len() == 1 && fen_strings[3] == "-" {
             en_passant = None;
         } else if fen_strings[3].len() == 2 {
             /* Parse enpassant string */
             let en_passant_string: Vec<char> = fen_strings[3].chars().collect();
             match en_passant_string[0] {
-                'a' => x = 1,
-                'b' => x = 2,
-                'c' => x = 3,
-                'd' => x = 4,
-                'e' => x = 5,
-                'f' => x = 6,
-                'g' => x = 7,
-                'h' => x = 8,
+                'a' => x = 2,
+                'b' => x = 3,
+                'c' => x = 4,
+                'd' => x = 5,
+                'e' => x = 6,
+                'f' => x = 7,
+                'g' => x = 8,
+                'h' => x = 9,
                 _ => panic!("fen string enpassant malformed!"),
             };
-            y = (9 - en_passant_string[1]
-                .to_digit(10)
-                .unwrap_or_else(|| panic!("fen string enpassant malformed!")))
-                as usize;
-
-            if !(1..=8).contains(&y) {
-                return Err("fen string enpassant malformed!");
-            }
-            en_passant = Some(Position { row: x, col: y }.swap()); //Accounting for how we index array
+            //The fen names the square the pawn skipped over, we keep the square the pawn is on
+            //A square that doesn't fit the side to move can't be captured on and is left out
+            en_passant = match (en_passant_string[1], active_color) {
+                ('3', Color::Black) => Some(Position { row: 6, col: x }),
+                ('6', Color::White) => Some(Position { row: 5, col: x }),
+                ('1'..='8', _) => None,
+                _ => return Err("fen string enpassant malformed!"),
+            };
         } else {
             return Err("fen string enpassant malformed!");
         }
 
-        Ok(BoardState {
+        //Some of our fen strings leave the clock out with a '-'
+        let halfmove_clock: u16 = match fen_strings[4] {
+            "-" => 0,
+            clock => match clock.parse() {
+                Ok(val) => val,
+                Err(_) => return Err("fen string halfmove clock malformed!"),
+            },
+        };
+
+        let mut board = BoardState {
             squares,
             active_color,
             castle_rights,
             en_passant,
             last_move: None,
-        })
+            last_capture: None,
+            halfmove_clock,
+            piece_square: PieceSquareSums::from_squares(&squares),
+            accumulator: nnue::network().map(|network| Accumulator::new(network, &squares)),
+            key: 0,
+            piece_counts: [[0; 6]; 2],
+            light_square_bishops: [0; 2],
+        };
+        for row in 2..10 {
+            for col in 2..10 {
+                if let Some(piece) = squares[row][col].piece {
+                    board.count_piece(piece, row, col, true);
+                }
+            }
+        }
+        board.key = board.full_zobrist_key();
+        Ok(board)
     }
 
     //Creates a Piece from a fen string representation of said piece
@@ -213,134 +270,126 @@ impl BoardState {
         self.squares[position.row][position.col].piece
     }
 
+    /* Number of pieces of this type and color on the board */
+    pub fn piece_count(&self, piece_type: PieceType, color: Color) -> u8 {
+        self.piece_counts[color_index(color)][piece_type_index(piece_type)]
+    }
+
+    fn count_piece(&mut self, piece: Piece, row: usize, col: usize, added: bool) {
+        let side = color_index(piece.color);
+        let update = |count: &mut u8| {
+            if added {
+                *count += 1;
+            } else {
+                *count -= 1;
+            }
+        };
+        update(&mut self.piece_counts[side][piece_type_index(piece.piece_type)]);
+        if piece.piece_type == PieceType::Bishop && self.squares[row][col].color == Color::White {
+            update(&mut self.light_square_bishops[side]);
+        }
+    }
+
+    /* Puts a piece (or nothing) on a square, keeping the piece square sums, the accumulator, the key and the piece counts in step */
+    fn set_piece(&mut self, row: usize, col: usize, piece: Option<Piece>) {
+        if let Some(old_piece) = self.squares[row][col].piece {
+            self.key ^= zobrist::piece_key(old_piece, row, col);
+            self.count_piece(old_piece, row, col, false);
+            self.piece_square.remove_piece(old_piece, row, col);
+            if let Some(accumulator) = &mut self.accumulator {
+                accumulator.remove_piece(old_piece, row, col);
+            }
+        }
+        if let Some(new_piece) = piece {
+            self.key ^= zobrist::piece_key(new_piece, row, col);
+            self.count_piece(new_piece, row, col, true);
+            self.piece_square.add_piece(new_piece, row, col);
+            if let Some(accumulator) = &mut self.accumulator {
+                accumulator.add_piece(new_piece, row, col);
+            }
+        }
+        self.squares[row][col].piece = piece;
+    }
+
     /* Updates a board state given a move, which was already been prechecked to be valid */
     pub fn make_move(&mut self, current_move: &Move) {
-        self.last_move = Some(*current_move);
-        let move_type: &MoveType = &current_move.move_type;
+        self.make_move_v2(MoveV2::from_move(current_move, self.active_color));
+    }
+
+    /* Plays a packed move, the moved and captured pieces are read off this board */
+    pub fn make_move_v2(&mut self, current_move: MoveV2) {
+        let from = current_move.from();
+        let to = current_move.to();
+        let moved = self
+            .get_piece(from)
+            .unwrap_or_else(|| panic!("No piece to move for {:?}", current_move));
+        let captured = self.captured_piece(current_move);
+        self.last_move = Some(current_move);
+        self.last_capture = captured;
+        //Side to move, castling and en passant come back into the key once the move is made
+        self.key ^= self.state_key();
         self.en_passant = None; //Reseting en_passant square to None after every move, this will be updated later depending on move
 
-        match move_type {
-            MoveType::Standard(val) => {
-                //Moving the piece
-                self.squares[val.before.row][val.before.col].piece = None;
-                self.squares[val.after.row][val.after.col].piece = Some(val.piece_moved);
-
-                //Setting enpassant if we moved a pawn
-                match val.piece_moved.piece_type {
-                    PieceType::Pawn => {
-                        if abs(val.after.row as i8 - val.before.row as i8) == 2 {
-                            self.en_passant = Some(val.after);
-                        }
-                    }
-                    //removing Castling Rights if we move the king
-                    PieceType::King => match val.piece_moved.color {
-                        Color::White => {
-                            self.castle_rights.can_castle_white_kingside = false;
-                            self.castle_rights.can_castle_white_queenside = false;
-                        }
-                        Color::Black => {
-                            self.castle_rights.can_castle_black_kingside = false;
-                            self.castle_rights.can_castle_black_queenside = false;
-                        }
-                    },
-                    //removing castling rights if we move the rook
-                    PieceType::Rook => {
-                        match val.piece_moved.color {
-                            Color::White => {
-                                //Were the rooks on default positions?
-                                match val.before {
-                                    Position { row: 9, col: 2 } => {
-                                        self.castle_rights.can_castle_white_queenside = false
-                                    }
-                                    Position { row: 9, col: 9 } => {
-                                        self.castle_rights.can_castle_white_kingside = false
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            Color::Black => {
-                                match val.before {
-                                    //Were the rooks on default positions?
-                                    Position { row: 2, col: 2 } => {
-                                        self.castle_rights.can_castle_black_queenside = false
-                                    }
-                                    Position { row: 2, col: 9 } => {
-                                        self.castle_rights.can_castle_black_kingside = false
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
-                }
+        //Captures and pawn moves reset the fifty move counter
+        if moved.piece_type == PieceType::Pawn || captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
 
-                //Removing castling rights if a rook is captured
-                if let Some(piece) = current_move.piece_captured {
-                    if piece.piece_type == PieceType::Rook {
-                        match val.after {
-                            //Default positions of the rooks
-                            Position { row: 2, col: 2 } => {
-                                self.castle_rights.can_castle_black_queenside = false
-                            }
-                            Position { row: 2, col: 9 } => {
-                                self.castle_rights.can_castle_black_kingside = false
-                            }
-                            Position { row: 9, col: 2 } => {
-                                self.castle_rights.can_castle_white_queenside = false
-                            }
-                            Position { row: 9, col: 9 } => {
-                                self.castle_rights.can_castle_white_kingside = false
-                            }
-                            _ => {}
-                        }
-                    }
-                }
+        if current_move.is_castle() {
+            let (rook_before, rook_after) = if to.col > from.col { (9, 7) } else { (2, 5) };
+            self.set_piece(from.row, from.col, None);
+            self.set_piece(from.row, rook_before, None);
+            self.set_piece(to.row, to.col, Some(moved));
+            self.set_piece(
+                from.row,
+                rook_after,
+                Some(Piece {
+                    piece_type: PieceType::Rook,
+                    color: moved.color,
+                }),
+            );
+        } else {
+            if current_move.is_en_passant() {
+                self.set_piece(from.row, to.col, None);
             }
+            let placed = match current_move.promotion() {
+                Some(piece_type) => Piece {
+                    piece_type,
+                    color: moved.color,
+                },
+                None => moved,
+            };
+            self.set_piece(from.row, from.col, None);
+            self.set_piece(to.row, to.col, Some(placed));
 
-            MoveType::Castle(val) => {
-                let x_position;
-                let y_positions: Vec<usize>;
-                if val.is_kingside {
-                    y_positions = vec![6, 9, 8, 7];
-                } else {
-                    y_positions = vec![6, 2, 4, 5];
+            //Setting enpassant if we moved a pawn two squares
+            if moved.piece_type == PieceType::Pawn && abs(to.row as i8 - from.row as i8) == 2 {
+                self.en_passant = Some(to);
+            }
+        }
+
+        //Castling rights go once the king or a rook leaves its square, or a rook is captured on it
+        for square in [from, to] {
+            match square {
+                Position { row: 9, col: 6 } => {
+                    self.castle_rights.can_castle_white_kingside = false;
+                    self.castle_rights.can_castle_white_queenside = false;
                 }
-                //Set castling rights here
-                match self.active_color {
-                    Color::White => {
-                        x_position = 9; //First row
-                        self.castle_rights.can_castle_white_kingside = false;
-                        self.castle_rights.can_castle_white_queenside = false;
-                    }
-                    Color::Black => {
-                        x_position = 2; //Last row
-                        self.castle_rights.can_castle_black_kingside = false;
-                        self.castle_rights.can_castle_black_queenside = false;
-                    }
+                Position { row: 2, col: 6 } => {
+                    self.castle_rights.can_castle_black_kingside = false;
+                    self.castle_rights.can_castle_black_queenside = false;
                 }
-                self.squares[x_position][y_positions[0]].piece = None;
-                self.squares[x_position][y_positions[1]].piece = None;
-                self.squares[x_position][y_positions[2]].piece = Some(Piece {
-                    piece_type: PieceType::King,
-                    color: self.active_color,
-                });
-                self.squares[x_position][y_positions[3]].piece = Some(Piece {
-                    piece_type: PieceType::Rook,
-                    color: self.active_color,
-                });
-            }
-            MoveType::Promotion(val) => {
-                self.squares[val.before.row][val.before.col].piece = None;
-                self.squares[val.after.row][val.after.col].piece = Some(val.promote_to);
-            }
-            MoveType::EnPassant(val) => {
-                self.squares[val.before.row][val.before.col].piece = None;
-                self.squares[val.after.row][val.after.col].piece = Some(Piece {
-                    piece_type: PieceType::Pawn,
-                    color: self.active_color,
-                });
-                self.squares[val.en_passant_pos.row][val.en_passant_pos.col].piece = None;
+                Position { row: 9, col: 2 } => {
+                    self.castle_rights.can_castle_white_queenside = false
+                }
+                Position { row: 9, col: 9 } => self.castle_rights.can_castle_white_kingside = false,
+                Position { row: 2, col: 2 } => {
+                    self.castle_rights.can_castle_black_queenside = false
+                }
+                Position { row: 2, col: 9 } => self.castle_rights.can_castle_black_kingside = false,
+                _ => {}
             }
         }
 
@@ -349,6 +398,33 @@ impl BoardState {
             Color::Black => self.active_color = Color::White,
             Color::White => self.active_color = Color::Black,
         };
+        self.key ^= self.state_key();
+    }
+
+    /* Hands the move to the other side without moving, which throws away any en passant capture */
+    pub fn make_null_move(&mut self) {
+        self.key ^= self.state_key();
+        self.active_color = self.active_color.opposite();
+        self.en_passant = None;
+        self.last_move = None;
+        self.last_capture = None;
+        self.key ^= self.state_key();
+    }
+
+    /* The piece a packed move would take on this board, None for quiet moves */
+    pub fn captured_piece(&self, current_move: MoveV2) -> Option<Piece> {
+        let from = current_move.from();
+        let to = current_move.to();
+        if current_move.is_castle() {
+            None
+        } else if current_move.is_en_passant() {
+            self.get_piece(Position {
+                row: from.row,
+                col: to.col,
+            })
+        } else {
+            self.get_piece(to)
+        }
     }
 
     /*
@@ -467,6 +543,94 @@ impl BoardState {
         false
     }
 
+    /* Zobrist hash of the position, used to detect repetitions */
+    pub fn zobrist_key(&self) -> u64 {
+        self.key
+    }
+
+    /* The key worked out from scratch, which make_move keeps equal to the one it updates */
+    fn full_zobrist_key(&self) -> u64 {
+        let mut key = self.state_key();
+        for row in 2..10 {
+            for col in 2..10 {
+                if let Some(piece) = self.squares[row][col].piece {
+                    key ^= zobrist::piece_key(piece, row, col);
+                }
+            }
+        }
+        key
+    }
+
+    /* The part of the key that isn't pieces: side to move, castling rights and en passant */
+    fn state_key(&self) -> u64 {
+        let mut key: u64 = 0;
+        if self.active_color == Color::Black {
+            key ^= zobrist::BLACK_TO_MOVE_KEY;
+        }
+
+        let rights = [
+            self.castle_rights.can_castle_white_kingside,
+            self.castle_rights.can_castle_white_queenside,
+            self.castle_rights.can_castle_black_kingside,
+            self.castle_rights.can_castle_black_queenside,
+        ];
+        for (index, right) in rights.iter().enumerate() {
+            if *right {
+                key ^= zobrist::castle_key(index);
+            }
+        }
+
+        //Only when a pawn stands ready to take, otherwise the position is the same as without it
+        if let Some(pos) = self.en_passant {
+            let capturing_pawn = Some(Piece {
+                piece_type: PieceType::Pawn,
+                color: self.active_color,
+            });
+            if self.squares[pos.row][pos.col - 1].piece == capturing_pawn
+                || self.squares[pos.row][pos.col + 1].piece == capturing_pawn
+            {
+                key ^= zobrist::en_passant_key(pos.col);
+            }
+        }
+
+        key
+    }
+
+    /* True once a hundred half moves have been played without a capture or pawn move */
+    pub fn is_fifty_move_draw(&self) -> bool {
+        self.halfmove_clock >= 100
+    }
+
+    /*
+     * Checks whether neither side has enough material left to ever checkmate.
+     * Covers K vs K, K+minor vs K and K+B vs K+B with bishops on the same color
+     */
+    pub fn is_insufficient_material(&self) -> bool {
+        let count = |piece_type, color| self.piece_count(piece_type, color);
+        let mut minor_pieces = 0;
+        for color in [Color::White, Color::Black] {
+            if count(PieceType::Pawn, color)
+                + count(PieceType::Rook, color)
+                + count(PieceType::Queen, color)
+                > 0
+            {
+                return false;
+            }
+            minor_pieces += count(PieceType::Knight, color) + count(PieceType::Bishop, color);
+        }
+
+        match minor_pieces {
+            0 | 1 => true,
+            //One bishop each, both on light squares or both on dark squares
+            2 => {
+                count(PieceType::Bishop, Color::White) == 1
+                    && count(PieceType::Bishop, Color::Black) == 1
+                    && self.light_square_bishops[0] == self.light_square_bishops[1]
+            }
+            _ => false,
+        }
+    }
+
     //Prints the board to the screen
     pub fn print_board(self) {
         for index in 2..10 {
@@ -479,3 +643,110 @@ impl BoardState {
         println!("   [a][b][c][d][e][f][g][h]");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::move_gen::gen_all_moves;
+
+    #[test]
+    fn reads_en_passant_from_fen() {
+        //After 1.e4 the fen names e3, the pawn that can be taken stands on e4
+        let board =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        assert_eq!(board.en_passant, Some(Position { row: 6, col: 6 }));
+        let board = BoardState::new("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 1")
+            .unwrap();
+        assert_eq!(board.en_passant, Some(Position { row: 5, col: 5 }));
+
+        //A square the side to move can't take on is left out
+        let board =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e3 0 1").unwrap();
+        assert_eq!(board.en_passant, None);
+        assert!(
+            BoardState::new("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e9 0 1").is_err()
+        );
+    }
+    #[test]
+    fn promotion_capturing_a_rook_ends_castling() {
+        //Kiwipete at depth 5 counted castles with a rook that gxh1=Q had already taken
+        let board = BoardState::new("r3k2r/8/8/8/8/8/6p1/R3K2R b KQkq - 0 1").unwrap();
+        let capture = gen_all_moves(&board, board.active_color)
+            .into_iter()
+            .find(|mv| mv.to_uci(board.active_color) == "g2h1q")
+            .unwrap();
+        let mut board_after = board.clone();
+        board_after.make_move(&capture);
+        assert!(!board_after.castle_rights.can_castle_white_kingside);
+        assert!(board_after.castle_rights.can_castle_white_queenside);
+    }
+    fn count_pieces(board: &BoardState) -> ([[u8; 6]; 2], [u8; 2]) {
+        let mut counts = [[0; 6]; 2];
+        let mut light_square_bishops = [0; 2];
+        for row in 2..10 {
+            for col in 2..10 {
+                if let Some(piece) = board.squares[row][col].piece {
+                    let side = color_index(piece.color);
+                    counts[side][piece_type_index(piece.piece_type)] += 1;
+                    if piece.piece_type == PieceType::Bishop
+                        && board.squares[row][col].color == Color::White
+                    {
+                        light_square_bishops[side] += 1;
+                    }
+                }
+            }
+        }
+        (counts, light_square_bishops)
+    }
+
+    #[test]
+    fn keeps_the_key_and_counts_while_moving() {
+        //Castling, en passant, promotions and captures of rooks that could still castle
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 1",
+            "r3k2r/8/8/8/8/8/6p1/R3K2R b KQkq - 0 1",
+        ] {
+            let mut boards = vec![BoardState::new(fen).unwrap()];
+            for _ in 0..3 {
+                let mut next = Vec::new();
+                for board in &boards {
+                    for mv in gen_all_moves(board, board.active_color) {
+                        let mut after = board.clone();
+                        after.make_move(&mv);
+                        assert_eq!(after.zobrist_key(), after.full_zobrist_key());
+                        assert_eq!(
+                            (after.piece_counts, after.light_square_bishops),
+                            count_pieces(&after)
+                        );
+                        next.push(after);
+                    }
+                }
+                boards = next.into_iter().step_by(7).collect();
+            }
+        }
+
+        let mut board =
+            BoardState::new("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1")
+                .unwrap();
+        board.make_null_move();
+        assert_eq!(board.zobrist_key(), board.full_zobrist_key());
+    }
+
+    #[test]
+    fn hashes_en_passant_only_when_a_pawn_can_take() {
+        let after_e4 = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        let without = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        assert_eq!(
+            BoardState::new(after_e4).unwrap().zobrist_key(),
+            BoardState::new(without).unwrap().zobrist_key()
+        );
+
+        let takeable = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        let untakeable = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        assert_ne!(
+            BoardState::new(takeable).unwrap().zobrist_key(),
+            BoardState::new(untakeable).unwrap().zobrist_key()
+        );
+    }
+}
@@ -1,5 +1,7 @@
+use crate::board_state::BoardState;
 use crate::color::Color;
 use crate::piece::{Piece, PieceType};
+use core::fmt;
 
 /* Position of a square on the board */
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
@@ -85,32 +87,162 @@ impl Move {
             },
         }
     }
-}
 
-/* A general move, simplified from the original implementation */
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
-pub struct MoveV2 {
-    // From, to squares
-    pub from: Position,
-    pub to: Position,
-    // if a promotion is made, this is what the piece if promoted to
-    pub promotion: Option<PieceType>,
-    pub is_castle: bool, //If true, then this is a castle move
+    //Long algebraic notation as UCI writes it, castles are the king's move and promotions name the new piece
+    pub fn to_uci(self, color: Color) -> String {
+        let packed = MoveV2::from_move(&self, color);
+        let promotion = match packed.promotion() {
+            Some(PieceType::Knight) => "n",
+            Some(PieceType::Bishop) => "b",
+            Some(PieceType::Rook) => "r",
+            Some(_) => "q",
+            None => "",
+        };
+        format!(
+            "{}{}{}",
+            packed.from().to_string(),
+            packed.to().to_string(),
+            promotion
+        )
+    }
 }
 
+/*
+ * A move packed into 16 bits, cheap to copy around and store in the search tables.
+ * Bits 0-5 are the from square, 6-11 the to square, 12-13 the promotion piece and 14-15 the kind of move.
+ * Squares are numbered 0 (a8) to 63 (h1), see Position::to_index. Castles are stored as the king's move.
+ */
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub struct MoveV2(u16);
+
+const NORMAL_FLAG: u16 = 0;
+const PROMOTION_FLAG: u16 = 1;
+const EN_PASSANT_FLAG: u16 = 2;
+const CASTLE_FLAG: u16 = 3;
+
 impl MoveV2 {
+    fn new(from: Position, to: Position, promotion: u16, flag: u16) -> MoveV2 {
+        MoveV2(from.to_index() as u16 | (to.to_index() as u16) << 6 | promotion << 12 | flag << 14)
+    }
+
+    /* Packs a move, the color is needed because castle moves don't record whose king is moving */
+    pub fn from_move(mv: &Move, color: Color) -> MoveV2 {
+        match mv.move_type {
+            MoveType::Standard(val) => MoveV2::new(val.before, val.after, 0, NORMAL_FLAG),
+            MoveType::Promotion(val) => {
+                let promotion = match val.promote_to.piece_type {
+                    PieceType::Knight => 0,
+                    PieceType::Bishop => 1,
+                    PieceType::Rook => 2,
+                    PieceType::Queen => 3,
+                    _ => panic!("Invalid promotion: {:?}", mv),
+                };
+                MoveV2::new(val.before, val.after, promotion, PROMOTION_FLAG)
+            }
+            MoveType::EnPassant(val) => MoveV2::new(val.before, val.after, 0, EN_PASSANT_FLAG),
+            MoveType::Castle(val) => {
+                let row = match color {
+                    Color::White => 9,
+                    Color::Black => 2,
+                };
+                let to_col = if val.is_kingside { 8 } else { 4 };
+                MoveV2::new(
+                    Position { row, col: 6 },
+                    Position { row, col: to_col },
+                    0,
+                    CASTLE_FLAG,
+                )
+            }
+        }
+    }
+
+    /* Unpacks the move, the board it is played on fills in the moved and captured pieces */
+    pub fn to_move(self, board: &BoardState) -> Move {
+        let from = self.from();
+        let to = self.to();
+        match self.flag() {
+            CASTLE_FLAG => castle(to.col > from.col, board.active_color),
+            EN_PASSANT_FLAG => {
+                let en_passant_pos = Position {
+                    row: from.row,
+                    col: to.col,
+                };
+                en_passant(from, to, en_passant_pos, board.get_piece(en_passant_pos))
+            }
+            PROMOTION_FLAG => promotion(
+                from,
+                to,
+                Piece {
+                    piece_type: self.promotion().unwrap(),
+                    color: board.active_color,
+                },
+                board.get_piece(to),
+            ),
+            _ => standard(
+                from,
+                to,
+                board
+                    .get_piece(from)
+                    .unwrap_or_else(|| panic!("No piece to move for {:?}", self)),
+                board.get_piece(to),
+            ),
+        }
+    }
+
+    pub fn from(self) -> Position {
+        Position::from_index((self.0 & 0x3f) as usize)
+    }
+
+    pub fn to(self) -> Position {
+        Position::from_index((self.0 >> 6 & 0x3f) as usize)
+    }
+
+    fn flag(self) -> u16 {
+        self.0 >> 14
+    }
+
+    // if a promotion is made, this is what the piece if promoted to
+    pub fn promotion(self) -> Option<PieceType> {
+        if self.flag() != PROMOTION_FLAG {
+            return None;
+        }
+        match self.0 >> 12 & 0x3 {
+            0 => Some(PieceType::Knight),
+            1 => Some(PieceType::Bishop),
+            2 => Some(PieceType::Rook),
+            _ => Some(PieceType::Queen),
+        }
+    }
+
+    pub fn is_castle(self) -> bool {
+        self.flag() == CASTLE_FLAG
+    }
+
+    pub fn is_en_passant(self) -> bool {
+        self.flag() == EN_PASSANT_FLAG
+    }
+
     pub fn to_string(self) -> String {
-        
-        if self.is_castle {
-            // Fancy absolute value logic for usize that's apparently fast
-            match self.from.col.saturating_sub(self.to.col).max(self.to.col.saturating_sub(self.from.col)) {
-                2 => return String::from("0-0"),
-                4 => return String::from("0-0-0"),
-                _ => panic!("Invalid castle move: {:?}", self),
+        if self.is_castle() {
+            if self.to().col > self.from().col {
+                return String::from("0-0");
             }
+            return String::from("0-0-0");
         }
 
-        format!("{}{}", self.from.to_string(), self.to.to_string())
+        format!("{}{}", self.from().to_string(), self.to().to_string())
+    }
+}
+
+impl fmt::Debug for MoveV2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MoveV2")
+            .field("from", &self.from())
+            .field("to", &self.to())
+            .field("promotion", &self.promotion())
+            .field("is_castle", &self.is_castle())
+            .field("is_en_passant", &self.is_en_passant())
+            .finish()
     }
 }
 
@@ -123,6 +255,18 @@ impl Position {
         }
     }
 
+    //Index of the square from 0 (a8) to 63 (h1)
+    pub fn to_index(self) -> usize {
+        (self.row - 2) * 8 + (self.col - 2)
+    }
+
+    pub fn from_index(index: usize) -> Position {
+        Position {
+            row: index / 8 + 2,
+            col: index % 8 + 2,
+        }
+    }
+
     pub fn is_valid_position(self) -> bool {
         self.row >= 2 && self.row <= 9 && self.col >= 2 && self.col <= 9
     }
@@ -346,4 +490,45 @@ mod tests {
             Err(e) => {}
         }
     }
+
+    #[test]
+    fn move_v2_is_16_bits() {
+        assert_eq!(std::mem::size_of::<MoveV2>(), 2);
+        assert_eq!(std::mem::size_of::<Option<MoveV2>>(), 4);
+    }
+
+    #[test]
+    fn move_v2_round_trip() {
+        use crate::move_gen::gen_all_moves;
+
+        //Castles, en passant and promotions, two plies deep
+        let mut en_passants_seen = 0;
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/pppppppp/8/4P3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2",
+        ] {
+            let board = BoardState::new(fen).unwrap();
+            for mv in gen_all_moves(&board, board.active_color) {
+                let mut board_after = board.clone();
+                board_after.make_move(&mv);
+
+                for reply in gen_all_moves(&board_after, board_after.active_color) {
+                    let packed = MoveV2::from_move(&reply, board_after.active_color);
+                    if packed.is_en_passant() {
+                        en_passants_seen += 1;
+                    }
+                    assert_eq!(packed.to_move(&board_after), reply);
+                    assert_eq!(packed.to_string(), reply.to_string());
+
+                    let mut unpacked_board = board_after.clone();
+                    let mut packed_board = board_after.clone();
+                    unpacked_board.make_move(&reply);
+                    packed_board.make_move_v2(packed);
+                    assert_eq!(unpacked_board.zobrist_key(), packed_board.zobrist_key());
+                }
+            }
+        }
+        assert!(en_passants_seen > 0);
+    }
 }
@@ -1,60 +1,209 @@
 use crate::board_state::BoardState;
-use crate::chess_move::{Move, MoveType};
-use crate::evaluation::evaluate;
+use crate::chess_move::{Move, MoveType, MoveV2};
+use crate::color::Color;
+use crate::evaluation::EvalParams;
+use crate::evaluator::{
+    DefaultEvaluator, Evaluator, EvaluatorKind, MaterialEvaluator, PestoEvaluator,
+};
+use crate::mcts::MctsOptions;
 use crate::move_gen::gen_all_moves;
+use crate::piece::PieceType;
+use crate::transposition_table::{Bound, Entry, TranspositionTable, DEFAULT_TABLE_SIZE};
 use log::{debug, info};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use simple_logger::SimpleLogger;
 use std::cmp::{max, min};
 use std::i32;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /* Everything drawn from https://www.chessprogramming.org/Main_Page */
 /* Search struct idea drawm from https://github.com/MitchelPaulin/Walleye/blob/main/src/engine.rs */
 
-const MATE_VALUE: i32 = 1000000000; //evaluation of a board state in mate
+pub const MATE_VALUE: i32 = 1000000000; //evaluation of a board state in mate
+pub const MATE_BOUND: i32 = MATE_VALUE - ARRAY_SIZE as i32; //anything past this is a mate score
 pub const MAX_DEPTH: u16 = 8;
 pub const ARRAY_SIZE: usize = ((MAX_DEPTH * MAX_DEPTH + MAX_DEPTH) / 2 + 1) as usize;
-type MoveList = [Option<Move>; ARRAY_SIZE];
-type MoveListV2 = [Option<MoveV2>; ARRAY_SIZE];
+type MoveList = [Option<MoveV2>; ARRAY_SIZE];
+
+/* Settings that change how the engine plays, as opposed to what position it is looking at */
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    //Centipawns the engine will give up to avoid a draw, positive values play on against weaker opponents
+    pub contempt: i32,
+    //Deepest iteration of the iterative deepening loop, must stay below MAX_DEPTH
+    pub max_depth: u16,
+    //Most plies a single line can be extended by
+    pub extension_budget: u16,
+    pub pruning: PruningMargins,
+    //Stop searching after this many nodes
+    pub node_limit: Option<u64>,
+    //Plays weaker on purpose, None is full strength
+    pub skill: Option<SkillLevel>,
+    //Evaluation the search runs with
+    pub evaluator: EvaluatorKind,
+    //Piece values and tables for the evaluators that use them, None for the process wide ones
+    pub eval_params: Option<Arc<EvalParams>>,
+    //Entries in the transposition table, rounded up to a power of two
+    pub table_size: usize,
+    //Only used by the Monte Carlo tree search
+    pub mcts: MctsOptions,
+}
 
-pub struct Search {
-    pub nodes_searched: u32,
-    pub pv_moves: MoveList,
-    pub current_line: MoveList, //current line being searched
+pub const MAX_SKILL_LEVEL: u8 = 20;
+//Rough Elo range covered by the skill levels, used to map UCI_Elo style settings onto a level
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2400;
+
+/*
+ * Skill levels go from 0 to 20, where 20 is full strength. Lower levels search shallower with fewer nodes
+ * and add random noise to the root move scores, so they sometimes pick a worse move.
+ * The seed makes the noise reproducible.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct SkillLevel {
+    pub level: u8,
+    pub seed: u64,
 }
 
-pub struct SearchV2 {
-    pub nodes_searched: u32,
-    pub pv_moves: MoveListV2,
-    pub current_line: MoveListV2, //current line being searched
+impl SkillLevel {
+    pub fn new(level: u8, seed: u64) -> SkillLevel {
+        SkillLevel {
+            level: min(level, MAX_SKILL_LEVEL),
+            seed,
+        }
+    }
+
+    /* Same as UCI_LimitStrength with UCI_Elo, maps the Elo linearly onto a skill level */
+    pub fn from_elo(elo: u32, seed: u64) -> SkillLevel {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        let level = (elo - MIN_ELO) * MAX_SKILL_LEVEL as u32 / (MAX_ELO - MIN_ELO);
+        SkillLevel::new(level as u8, seed)
+    }
+
+    pub fn is_full_strength(&self) -> bool {
+        self.level >= MAX_SKILL_LEVEL
+    }
+
+    //Level 0 only looks one ply ahead, every four levels adds another ply
+    pub fn max_depth(&self) -> u16 {
+        1 + self.level as u16 / 4
+    }
+
+    pub fn node_limit(&self) -> u64 {
+        1000 << self.level
+    }
+
+    //Most centipawns of noise that can be added to a root move's score
+    pub fn max_noise(&self) -> i32 {
+        (MAX_SKILL_LEVEL - self.level) as i32 * 15
+    }
 }
 
-pub struct SearchResult {
-    pub score: i32,
-    pub move_found: Option<Move>,
+/*
+ * Margins for pruning nodes close to the horizon, all in centipawns per ply of depth left.
+ * See https://www.chessprogramming.org/Futility_Pruning and https://www.chessprogramming.org/Razoring
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct PruningMargins {
+    //Nodes with more depth left than this are never pruned, 0 turns pruning off
+    pub max_depth: u16,
+    //Quiet moves are skipped when the static eval plus this can't reach alpha
+    pub futility: i32,
+    //Nodes are cut when the static eval minus this still beats beta
+    pub reverse_futility: i32,
+    //Nodes drop into quiescence when the static eval plus this can't reach alpha
+    pub razoring: i32,
 }
 
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            contempt: 0,
+            max_depth: MAX_DEPTH - 1,
+            extension_budget: 8,
+            pruning: PruningMargins::default(),
+            node_limit: None,
+            skill: None,
+            evaluator: EvaluatorKind::Default,
+            eval_params: None,
+            table_size: DEFAULT_TABLE_SIZE,
+            mcts: MctsOptions::default(),
+        }
+    }
+}
 
-pub struct SearchResultV2 {
+impl Default for PruningMargins {
+    fn default() -> PruningMargins {
+        PruningMargins {
+            max_depth: 3,
+            futility: 125,
+            reverse_futility: 100,
+            razoring: 300,
+        }
+    }
+}
+
+pub struct Search<E: Evaluator> {
+    pub nodes_searched: u32,
+    pub total_nodes: u64, //Not reset between iterations, checked against the node limit
+    pub pv_moves: MoveList,
+    pub current_line: MoveList, //current line being searched
+    pub history: Vec<u64>, //Zobrist keys of the game so far followed by the line being searched
+    pub game_length: usize, //Number of keys in history that were actually played
+    pub root_color: Color,
+    pub options: SearchOptions,
+    pub stopped: bool, //Set once we run out of time, everything after that is thrown away
+    pub table: TranspositionTable,
+    pub evaluator: E,
+}
+
+pub struct SearchResult {
     pub score: i32,
-    pub move_found: Option<MoveV2>,
+    pub move_found: Option<Move>,
+    pub nodes_searched: u64, //Summed over every iteration
+    pub pv: Vec<Move>,       //Starts with move_found, empty if there are no moves
+    pub depth: u16,          //Deepest iteration searched to the end
+    pub elapsed: Duration,
 }
 
-impl Search {
-    pub fn new() -> Search {
+impl<E: Evaluator> Search<E> {
+    pub fn new(
+        history: &[u64],
+        root_color: Color,
+        options: &SearchOptions,
+        evaluator: E,
+    ) -> Search<E> {
         Search {
             nodes_searched: 0,
+            total_nodes: 0,
             pv_moves: [None; ARRAY_SIZE],
             current_line: [None; ARRAY_SIZE],
+            history: history.to_vec(),
+            game_length: history.len(),
+            root_color,
+            options: options.clone(),
+            stopped: false,
+            table: TranspositionTable::new(options.table_size),
+            evaluator,
         }
     }
 
     pub fn increment_nodes_searched(&mut self) {
         self.nodes_searched += 1;
+        self.total_nodes += 1;
     }
 
-    pub fn insert_into_current_line(&mut self, ply: i32, mv: &Move) {
-        self.current_line[ply as usize] = Some(*mv);
+    pub fn is_over_node_limit(&self) -> bool {
+        match self.options.node_limit {
+            Some(limit) => self.total_nodes >= limit,
+            None => false,
+        }
+    }
+
+    pub fn insert_into_current_line(&mut self, ply: i32, mv: MoveV2) {
+        self.current_line[ply as usize] = Some(mv);
     }
 
     pub fn set_principle_variation(&mut self) {
@@ -65,37 +214,68 @@ impl Search {
         self.nodes_searched = 0;
         self.current_line = [None; ARRAY_SIZE];
     }
-}
 
-impl SearchV2 {
-    pub fn new() -> SearchV2 {
-        SearchV2 {
-            nodes_searched: 0,
-            pv_moves: [None; ARRAY_SIZE],
-            current_line: [None; ARRAY_SIZE],
-        }
+    pub fn push_position(&mut self, key: u64) {
+        self.history.push(key);
     }
 
-    pub fn increment_nodes_searched(&mut self) {
-        self.nodes_searched += 1;
+    pub fn pop_position(&mut self) {
+        self.history.pop();
     }
 
-    pub fn insert_into_current_line(&mut self, ply: i32, mv: &MoveV2) {
-        self.current_line[ply as usize] = Some(*mv);
+    /*
+     * Checks if the position was seen before. Repeating a position inside the line being searched is scored as a draw straight away,
+     * positions from the game itself need to have happened twice already (threefold repetition)
+     */
+    pub fn is_repetition(&self, board: &BoardState, key: u64) -> bool {
+        //Nothing before the last capture or pawn move can repeat
+        let oldest = self
+            .history
+            .len()
+            .saturating_sub(board.halfmove_clock as usize);
+        let mut game_repetitions = 0;
+        let mut index = self.history.len();
+
+        //Only positions with the same side to move can match, which is every other one
+        while index >= oldest + 2 {
+            index -= 2;
+            if self.history[index] == key {
+                if index >= self.game_length {
+                    return true;
+                }
+                game_repetitions += 1;
+                if game_repetitions >= 2 {
+                    return true;
+                }
+            }
+        }
+
+        false
     }
 
-    pub fn set_principle_variation(&mut self) {
-        self.pv_moves = self.current_line.clone();
+    pub fn is_draw(&self, board: &BoardState, key: u64) -> bool {
+        board.is_fifty_move_draw()
+            || board.is_insufficient_material()
+            || self.is_repetition(board, key)
     }
 
-    pub fn reset_search(&mut self) {
-        self.nodes_searched = 0;
-        self.current_line = [None; ARRAY_SIZE];
+    /* Draws are worth -contempt to the side the engine is playing for */
+    pub fn draw_score(&self, board: &BoardState) -> i32 {
+        if board.active_color == self.root_color {
+            -self.options.contempt
+        } else {
+            self.options.contempt
+        }
     }
 }
 
-fn quiesce(mut alpha: i32, mut beta: i32, search: &mut Search, board: &BoardState) -> i32 {
-    let init_eval: i32 = evaluate(board);
+fn quiesce<E: Evaluator>(
+    mut alpha: i32,
+    mut beta: i32,
+    search: &mut Search<E>,
+    board: &BoardState,
+) -> i32 {
+    let init_eval: i32 = search.evaluator.evaluate(board);
 
     search.increment_nodes_searched();
 
@@ -110,11 +290,14 @@ fn quiesce(mut alpha: i32, mut beta: i32, search: &mut Search, board: &BoardStat
     let mut board_copy;
     let active_color = board.active_color;
     for mv in gen_all_moves(board, active_color) {
-        match mv.piece_captured {
+        let packed_move = MoveV2::from_move(&mv, active_color);
+        match board.captured_piece(packed_move) {
             Some(_) => {
                 board_copy = board.clone();
-                board_copy.make_move(&mv);
+                board_copy.make_move_v2(packed_move);
+                search.evaluator.push(board, &board_copy);
                 score = -1 * quiesce(-1 * beta, -1 * alpha, search, &board_copy);
+                search.evaluator.pop();
                 if score >= beta {
                     return beta;
                 }
@@ -130,65 +313,80 @@ fn quiesce(mut alpha: i32, mut beta: i32, search: &mut Search, board: &BoardStat
     return alpha;
 }
 
-fn quiesceV2(mut alpha: i32, mut beta: i32, search: &mut SearchV2, board: &BoardState) -> i32 {
-    let init_eval: i32 = evaluate(board);
-
-    search.increment_nodes_searched();
-
-    if init_eval >= beta {
-        return beta;
-    }
-    if alpha < init_eval {
-        alpha = init_eval;
+/*
+ * How many extra plies to give a move. Checks, only replies, recaptures and pawns reaching the seventh rank are all extended by one,
+ * as long as the line still has some of its extension budget left
+ */
+fn extension<E: Evaluator>(
+    search: &Search<E>,
+    board: &BoardState,
+    mv: MoveV2,
+    gives_check: bool,
+    num_moves: usize,
+    extensions: u16,
+) -> u16 {
+    if extensions >= search.options.extension_budget {
+        return 0;
     }
 
-    let mut score: i32;
-    let mut board_copy;
-    let active_color = board.active_color;
-    for mv in gen_all_moves(board, active_color) {
-        match mv.piece_captured {
-            Some(_) => {
-                board_copy = board.clone();
-                board_copy.make_move(&mv);
-                score = -1 * quiesceV2(-1 * beta, -1 * alpha, search, &board_copy);
-                if score >= beta {
-                    return beta;
-                }
+    let singular_reply = num_moves == 1;
 
-                if score > alpha {
-                    alpha = score;
-                }
-            }
-            None => {}
+    //Taking back on the square the opponent just captured on
+    let recapture = match board.last_move {
+        Some(last_move) => {
+            board.last_capture.is_some()
+                && board.captured_piece(mv).is_some()
+                && last_move.to() == mv.to()
         }
-    }
+        None => false,
+    };
 
-    return alpha;
+    let seventh_rank_push = mv.promotion().is_none()
+        && board.get_piece(mv.from()).map(|piece| piece.piece_type) == Some(PieceType::Pawn)
+        && match board.active_color {
+            Color::White => mv.to().row == 3,
+            Color::Black => mv.to().row == 8,
+        };
+
+    if gives_check || singular_reply || recapture || seventh_rank_push {
+        1
+    } else {
+        0
+    }
 }
 
-fn alpha_beta(
+fn alpha_beta<E: Evaluator>(
     mut alpha: i32,
     mut beta: i32,
-    mut depth: u16,
-    search: &mut Search,
+    depth: u16,
+    extensions: u16, //Extensions used so far on this line
+    search: &mut Search<E>,
     ply: i32,
     board: &BoardState,
     start: Instant,
-    time_to_think: u64,
+    time_to_think: Duration,
 ) -> i32 {
-    if start.elapsed().as_secs() > time_to_think {
-        return quiesce(alpha, beta, search, board);
+    if search.stopped || start.elapsed() > time_to_think || search.is_over_node_limit() {
+        search.stopped = true;
+        return 0; //Thrown away by the caller
     }
     search.increment_nodes_searched();
     let ply_index: usize = ply as usize;
 
-    if depth == 0 {
-        let active_color = board.active_color;
-        if board.is_in_check(active_color, None) {
-            depth += 1;
-        } else {
-            return quiesce(alpha, beta, search, board);
+    let key = board.zobrist_key();
+    if search.is_draw(board, key) {
+        //Mate on the hundredth half move still counts as mate
+        if board.is_fifty_move_draw()
+            && board.is_in_check(board.active_color, None)
+            && gen_all_moves(board, board.active_color).is_empty()
+        {
+            return -(MATE_VALUE - ply);
         }
+        return search.draw_score(board);
+    }
+
+    if depth == 0 {
+        return quiesce(alpha, beta, search, board);
     }
 
     alpha = max(alpha, ply - MATE_VALUE);
@@ -198,238 +396,371 @@ fn alpha_beta(
         return alpha;
     }
 
+    //Reusing what we already know about this position
+    let table_entry = search.table.probe(key);
+    if let Some(entry) = table_entry {
+        if entry.depth >= depth {
+            let score = score_from_table(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => {}
+            }
+        }
+    }
+    let original_alpha = alpha;
+
     let active_color = board.active_color;
-    let mut moves = gen_all_moves(board, active_color);
+    let in_check = board.is_in_check(active_color, None);
+
+    //Pruning close to the horizon, never when in check or when a mate score is on the line
+    let margins = search.options.pruning;
+    let mut futile = false;
+    if !in_check
+        && depth <= margins.max_depth
+        && alpha.abs() < MATE_BOUND
+        && beta.abs() < MATE_BOUND
+    {
+        let static_eval = search.evaluator.evaluate(board);
+        let depth_margin = depth as i32;
+
+        //Reverse futility, we are so far ahead that the opponent won't let us get here
+        if static_eval - margins.reverse_futility * depth_margin >= beta {
+            return static_eval;
+        }
+
+        //Razoring, hopelessly behind so only captures can save us
+        if static_eval + margins.razoring * depth_margin < alpha {
+            let score = quiesce(alpha, beta, search, board);
+            if score < alpha {
+                return score;
+            }
+        }
+
+        futile = static_eval + margins.futility * depth_margin <= alpha;
+    }
+
+    let mut moves: Vec<MoveV2> = gen_all_moves(board, active_color)
+        .iter()
+        .map(|mv| MoveV2::from_move(mv, active_color))
+        .collect();
 
     //Game over
     if moves.len() == 0 {
-        if board.is_in_check(active_color, None) {
+        if in_check {
             return -1 * (MATE_VALUE - ply); //Checkmate
         } else {
-            return 0; //Draw by stalemate
+            return search.draw_score(board); //Draw by stalemate
         }
     }
 
-    let mut potential_best_score: i32 = i32::MIN;
-    let mut other_moves: Vec<Move> = Vec::new();
-    //Calculating on principal variation first
-    for mv in &mut moves {
-        if board.last_move == search.pv_moves[ply_index] {
-            let mut board_copy = board.clone();
-            board_copy.make_move(mv);
-            search.insert_into_current_line(ply, mv); //Ply or ply + 1?
-            potential_best_score = -alpha_beta(
-                -1 * beta,
-                -1 * alpha,
-                depth - 1,
-                search,
-                ply + 1,
-                &board_copy,
-                start,
-                time_to_think,
-            );
-            if potential_best_score > alpha {
-                if potential_best_score >= beta {
-                    return potential_best_score;
-                }
-                search.set_principle_variation();
-                alpha = potential_best_score;
-            }
-        } else {
-            other_moves.push(*mv);
-        }
+    //Calculating the best move from the table first, or failing that the principal variation
+    let first_move = table_entry
+        .and_then(|entry| entry.best_move)
+        .or(search.pv_moves[ply_index]);
+    if let Some(index) = moves.iter().position(|mv| Some(*mv) == first_move) {
+        moves.swap(0, index);
     }
 
-    for mv in &mut other_moves {
+    let mut best_score: i32 = -MATE_VALUE;
+    let mut best_move: Option<MoveV2> = None;
+    let mut moves_searched = 0;
+    search.push_position(key);
+    for &mv in &moves {
         search.insert_into_current_line(ply, mv);
         let mut board_copy = board.clone();
-        board_copy.make_move(mv);
-        let mut score = -alpha_beta(
+        board_copy.make_move_v2(mv);
+        let gives_check = board_copy.is_in_check(board_copy.active_color, None);
+
+        //Futility pruning, a quiet move won't bring the score back up to alpha
+        let is_quiet =
+            board.captured_piece(mv).is_none() && mv.promotion().is_none() && !gives_check;
+        if futile && is_quiet && moves_searched > 0 {
+            continue;
+        }
+        moves_searched += 1;
+
+        let extend = extension(search, board, mv, gives_check, moves.len(), extensions);
+        search.evaluator.push(board, &board_copy);
+        let score = -alpha_beta(
             -1 * beta,
             -1 * alpha,
-            depth - 1,
+            depth - 1 + extend,
+            extensions + extend,
             search,
             ply + 1,
             &board_copy,
             start,
             time_to_think,
         );
-        if -1 * score > potential_best_score {
+        search.evaluator.pop();
+        if score > best_score {
+            best_score = score;
+            best_move = Some(mv);
+        }
+        if score > alpha {
             if score >= beta {
-                return score;
+                break;
             }
             search.set_principle_variation();
-            potential_best_score = -1 * score;
+            alpha = score;
         }
     }
+    search.pop_position();
 
-    potential_best_score
+    if !search.stopped {
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        search.table.store(Entry {
+            key,
+            best_move,
+            depth,
+            score: score_to_table(best_score, ply),
+            bound,
+        });
+    }
+
+    best_score
 }
 
-fn alpha_beta_V2(
-    mut alpha: i32,
-    mut beta: i32,
-    mut depth: u16,
-    search: &mut SearchV2,
-    ply: i32,
-    board: &BoardState,
-    start: Instant,
-    time_to_think: u64,
-) -> i32 {
-    if start.elapsed().as_secs() > time_to_think {
-        return quiesce(alpha, beta, search, board);
+/* Mate scores are stored relative to the node rather than the root, so they stay right wherever the position shows up again */
+fn score_to_table(score: i32, ply: i32) -> i32 {
+    if score > MATE_BOUND {
+        score + ply
+    } else if score < -MATE_BOUND {
+        score - ply
+    } else {
+        score
     }
-    search.increment_nodes_searched();
-    let ply_index: usize = ply as usize;
+}
 
-    if depth == 0 {
-        let active_color = board.active_color;
-        if board.is_in_check(active_color, None) {
-            depth += 1;
-        } else {
-            return quiesce(alpha, beta, search, board);
-        }
+fn score_from_table(score: i32, ply: i32) -> i32 {
+    if score > MATE_BOUND {
+        score - ply
+    } else if score < -MATE_BOUND {
+        score + ply
+    } else {
+        score
     }
+}
 
-    alpha = max(alpha, ply - MATE_VALUE);
-    beta = min(beta, MATE_VALUE - ply);
-
-    if alpha >= beta {
-        return alpha;
-    }
+/* Score once every capture has been played out, used to tell quiet positions apart */
+pub fn quiescence_score(board: &BoardState) -> i32 {
+    let mut search = Search::new(
+        &[],
+        board.active_color,
+        &SearchOptions::default(),
+        DefaultEvaluator::default(),
+    );
+    quiesce(-MATE_VALUE, MATE_VALUE, &mut search, board)
+}
 
-    let active_color = board.active_color;
-    let mut moves = gen_all_moves(board, active_color);
+pub fn calculate_best_move(board: &BoardState, time_to_think: Duration) -> SearchResult {
+    search_position(board, &[], time_to_think, &SearchOptions::default())
+}
 
-    //Game over
-    if moves.len() == 0 {
-        if board.is_in_check(active_color, None) {
-            return -1 * (MATE_VALUE - ply); //Checkmate
-        } else {
-            return 0; //Draw by stalemate
+/*
+ * Iterative deepening over the root moves.
+ * history holds the zobrist keys of the positions played before this one, oldest first, so the search can spot repetitions
+ */
+pub fn search_position(
+    board: &BoardState,
+    history: &[u64],
+    time_to_think: Duration,
+    options: &SearchOptions,
+) -> SearchResult {
+    let params = options.eval_params.clone();
+    match options.evaluator {
+        EvaluatorKind::Default => {
+            let evaluator = DefaultEvaluator { params };
+            search_with(board, history, time_to_think, options, evaluator)
         }
-    }
-
-    let mut potential_best_score: i32 = i32::MIN;
-    let mut other_moves: Vec<Move> = Vec::new();
-    //Calculating on principal variation first
-    for mv in &mut moves {
-        if board.last_move == search.pv_moves[ply_index] {
-            let mut board_copy = board.clone();
-            board_copy.make_move(mv);
-            search.insert_into_current_line(ply, mv); //Ply or ply + 1?
-            potential_best_score = -alpha_beta(
-                -1 * beta,
-                -1 * alpha,
-                depth - 1,
-                search,
-                ply + 1,
-                &board_copy,
-                start,
-                time_to_think,
-            );
-            if potential_best_score > alpha {
-                if potential_best_score >= beta {
-                    return potential_best_score;
-                }
-                search.set_principle_variation();
-                alpha = potential_best_score;
-            }
-        } else {
-            other_moves.push(*mv);
+        EvaluatorKind::Pesto => {
+            let evaluator = PestoEvaluator { params };
+            search_with(board, history, time_to_think, options, evaluator)
         }
-    }
-
-    for mv in &mut other_moves {
-        search.insert_into_current_line(ply, mv);
-        let mut board_copy = board.clone();
-        board_copy.make_move(mv);
-        let mut score = -alpha_beta(
-            -1 * beta,
-            -1 * alpha,
-            depth - 1,
-            search,
-            ply + 1,
-            &board_copy,
-            start,
-            time_to_think,
-        );
-        if -1 * score > potential_best_score {
-            if score >= beta {
-                return score;
-            }
-            search.set_principle_variation();
-            potential_best_score = -1 * score;
+        EvaluatorKind::Material => {
+            search_with(board, history, time_to_think, options, MaterialEvaluator)
         }
     }
-
-    potential_best_score
 }
 
-pub fn calculate_best_move(board: &BoardState, time_to_think: u64) -> SearchResult {
+fn search_with<E: Evaluator>(
+    board: &BoardState,
+    history: &[u64],
+    time_to_think: Duration,
+    options: &SearchOptions,
+    evaluator: E,
+) -> SearchResult {
     let mut result = SearchResult {
-        score: i32::MIN,
+        score: -MATE_VALUE,
         move_found: None,
+        nodes_searched: 0,
+        pv: Vec::new(),
+        depth: 0,
+        elapsed: Duration::ZERO,
     };
-    let mut depth = 2;
-    let mut ply = 0;
-    let mut search = Search::new();
+    let ply = 0;
+    let mut search_options = options.clone();
+    //Any deeper and the lines would run past the end of the move lists
+    search_options.max_depth = min(search_options.max_depth, MAX_DEPTH - 1);
+    let skill = options.skill.filter(|skill| !skill.is_full_strength());
+    if let Some(skill) = skill {
+        search_options.max_depth = min(search_options.max_depth, skill.max_depth());
+        search_options.node_limit = Some(min(
+            search_options.node_limit.unwrap_or(u64::MAX),
+            skill.node_limit(),
+        ));
+    }
+    let mut depth = min(2, search_options.max_depth);
+    let mut search = Search::new(history, board.active_color, &search_options, evaluator);
 
     let active_color = board.active_color;
     let mut moves = gen_all_moves(board, active_color);
-    let mut alpha: i32 = -100000;
-    let mut beta: i32 = 100000;
+    let root_key = board.zobrist_key();
+    let mut root_scores: Vec<(Move, i32)> = Vec::new();
     let start = Instant::now();
-    let mut nodes_searched = 0;
-    while depth < MAX_DEPTH {
-        nodes_searched += search.nodes_searched;
-        // debug!("Trying Depth: {}, Nodes Searched: {}", depth, nodes_searched);
+    while depth <= search_options.max_depth && !moves.is_empty() {
+        // debug!("Trying Depth: {}, Nodes Searched: {}", depth, search.nodes_searched);
         search.reset_search();
+        let mut alpha: i32 = -MATE_VALUE;
+        let beta: i32 = MATE_VALUE;
+        let mut best_move: Option<Move> = None;
+        let mut scores: Vec<(Move, i32)> = Vec::new();
 
+        search.push_position(root_key);
         for mv in &moves {
-            //Ending after two minutes seconds
-            if start.elapsed().as_secs() > time_to_think {
-                match result.move_found {
-                    Some(_) => return result,
-                    None => {
-                        println!("no move found!");
-                        result.move_found = Some(moves[0]);
-                        return result;
-                    }
-                }
-            }
+            let packed_move = MoveV2::from_move(mv, active_color);
+            search.insert_into_current_line(ply, packed_move);
             let mut board_copy = board.clone();
-            board_copy.make_move(mv);
-            //-1 or positive one??
+            board_copy.make_move_v2(packed_move);
+            let gives_check = board_copy.is_in_check(board_copy.active_color, None);
+            let extend = extension(&search, board, packed_move, gives_check, moves.len(), 0);
+            search.evaluator.push(board, &board_copy);
+            //Weakened play needs a real score for every move, not just the best one
+            let window_alpha = if skill.is_some() { -MATE_VALUE } else { alpha };
             let eval = -1
                 * alpha_beta(
                     -1 * beta,
-                    -1 * alpha,
-                    depth - 1,
+                    -1 * window_alpha,
+                    depth - 1 + extend,
+                    extend,
                     &mut search,
                     ply + 1,
                     &board_copy,
                     start,
                     time_to_think,
                 );
+            search.evaluator.pop();
+            if search.stopped {
+                break;
+            }
 
-            search.insert_into_current_line(ply, mv);
-            if eval > alpha {
-                if eval > result.score {
-                    result.move_found = Some(*mv);
-                    result.score = eval;
-                }
+            scores.push((*mv, eval));
+            if eval > alpha || best_move.is_none() {
+                best_move = Some(*mv);
                 alpha = eval;
                 search.set_principle_variation();
             }
         }
+        search.pop_position();
+        result.nodes_searched += search.nodes_searched as u64;
 
+        //Only trusting depths that were searched to the end
+        if search.stopped {
+            break;
+        }
+        result.move_found = best_move;
+        result.score = alpha;
+        result.depth = depth;
+        root_scores = scores;
+
+        //Searching the best move first on the next iteration
+        if let Some(index) = moves.iter().position(|mv| Some(*mv) == best_move) {
+            let mv = moves.remove(index);
+            moves.insert(0, mv);
+        }
         depth += 1;
     }
-    //
+
+    //The limits ran out before the first iteration finished, any legal move beats none.
+    //Nothing is printed here, in uci mode stdout belongs to the protocol
+    if result.move_found.is_none() && !moves.is_empty() {
+        result.move_found = Some(moves[0]);
+    }
+
+    if let Some(skill) = skill {
+        if let Some((mv, score)) = pick_weakened_move(&root_scores, &skill, root_key) {
+            result.move_found = Some(mv);
+            result.score = score;
+        }
+    }
+    if let Some(mv) = result.move_found {
+        result.pv = principal_variation(&search, board, mv, result.depth);
+    }
+    result.elapsed = start.elapsed();
     result
 }
 
+/* Follows the best moves stored in the table after the root move, until the table runs out or the line is as long as the search went */
+fn principal_variation<E: Evaluator>(
+    search: &Search<E>,
+    board: &BoardState,
+    first: Move,
+    depth: u16,
+) -> Vec<Move> {
+    let mut pv = vec![first];
+    let mut board = board.clone();
+    board.make_move(&first);
+    while pv.len() < depth as usize {
+        let packed = match search.table.probe(board.zobrist_key()) {
+            Some(Entry {
+                best_move: Some(packed),
+                ..
+            }) => packed,
+            _ => break,
+        };
+        let active_color = board.active_color;
+        let mv = gen_all_moves(&board, active_color)
+            .into_iter()
+            .find(|mv| MoveV2::from_move(mv, active_color) == packed);
+        match mv {
+            Some(mv) => {
+                board.make_move(&mv);
+                pv.push(mv);
+            }
+            None => break,
+        }
+    }
+    pv
+}
+
+/*
+ * Adds seeded random noise to every root move's score and plays the best noisy move.
+ * The lower the skill level the more noise, so weaker levels pick worse moves more often
+ */
+fn pick_weakened_move(
+    root_scores: &[(Move, i32)],
+    skill: &SkillLevel,
+    root_key: u64,
+) -> Option<(Move, i32)> {
+    //Mixing in the position so every move of a game doesn't get the same noise
+    let mut rng = StdRng::seed_from_u64(skill.seed ^ root_key);
+    let max_noise = skill.max_noise();
+
+    root_scores
+        .iter()
+        .map(|(mv, score)| (*mv, *score, score + rng.gen_range(0..=max_noise)))
+        .max_by_key(|(_, _, noisy_score)| *noisy_score)
+        .map(|(mv, score, _)| (mv, score))
+}
+
 #[cfg(test)]
 mod tests {
     use core::panic;
@@ -471,7 +802,9 @@ mod tests {
         let board_state_fen: &str = "k7/6q1/5P2/8/8/8/8/K7 w - - 0 1";
         let mut board_state: BoardState = BoardState::new(board_state_fen)
             .unwrap_or_else(|e| panic!("Error creating board state"));
-        let best_move = calculate_best_move(&board_state, 5).move_found.unwrap();
+        let best_move = calculate_best_move(&board_state, Duration::from_secs(5))
+            .move_found
+            .unwrap();
         board_state.print_board();
         board_state.make_move(&best_move);
         board_state.print_board();
@@ -496,7 +829,9 @@ mod tests {
         let board_state_fen: &str = "k7/4r1q1/5P2/8/8/8/8/K7 w - - 0 1";
         let mut board_state: BoardState = BoardState::new(board_state_fen)
             .unwrap_or_else(|e| panic!("Error creating board state"));
-        let best_move = calculate_best_move(&board_state, 5).move_found.unwrap();
+        let best_move = calculate_best_move(&board_state, Duration::from_secs(5))
+            .move_found
+            .unwrap();
         println!("{:?}", best_move);
         board_state.print_board();
         board_state.make_move(&best_move);
@@ -523,7 +858,9 @@ mod tests {
             "rnbqkbnr/ppppp2p/5p2/6p1/3PP3/8/PPP2PPP/RNBQKBNR w KQkq d4 0 1";
         let mut board_state: BoardState = BoardState::new(board_state_fen)
             .unwrap_or_else(|e| panic!("Error creating board state"));
-        let best_move = calculate_best_move(&board_state, 5).move_found.unwrap();
+        let best_move = calculate_best_move(&board_state, Duration::from_secs(5))
+            .move_found
+            .unwrap();
         println!("{:?}", best_move);
         board_state.print_board();
         board_state.make_move(&best_move);
@@ -542,4 +879,341 @@ mod tests {
             }
         }
     }
+
+    fn play(board: &mut BoardState, history: &mut Vec<u64>, mv_string: &str) {
+        let mv = gen_all_moves(board, board.active_color)
+            .into_iter()
+            .find(|mv| mv.to_string() == mv_string)
+            .unwrap_or_else(|| panic!("{} is not a legal move", mv_string));
+        history.push(board.zobrist_key());
+        board.make_move(&mv);
+    }
+
+    #[test]
+    fn detects_threefold_repetition() {
+        let mut board =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let mut history = Vec::new();
+        for mv_string in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            play(&mut board, &mut history, mv_string);
+        }
+
+        //Second time we see the starting position, not a draw yet
+        let search = Search::new(
+            &history,
+            Color::White,
+            &SearchOptions::default(),
+            DefaultEvaluator::default(),
+        );
+        assert!(!search.is_repetition(&board, board.zobrist_key()));
+
+        for mv_string in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            play(&mut board, &mut history, mv_string);
+        }
+        let search = Search::new(
+            &history,
+            Color::White,
+            &SearchOptions::default(),
+            DefaultEvaluator::default(),
+        );
+        assert!(search.is_repetition(&board, board.zobrist_key()));
+    }
+
+    #[test]
+    fn detects_repetition_in_search_line() {
+        let mut board =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let mut search = Search::new(
+            &[],
+            Color::White,
+            &SearchOptions::default(),
+            DefaultEvaluator::default(),
+        );
+        let mut history = Vec::new();
+        for mv_string in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            search.push_position(board.zobrist_key());
+            play(&mut board, &mut history, mv_string);
+        }
+
+        assert!(search.is_repetition(&board, board.zobrist_key()));
+    }
+
+    #[test]
+    fn detects_insufficient_material() {
+        for fen in [
+            "8/8/4k3/8/8/3K4/8/8 w - - 0 1",
+            "8/8/4k3/8/8/3KN3/8/8 w - - 0 1",
+            "8/8/4k3/2b5/8/3KB3/8/8 w - - 0 1",
+        ] {
+            assert!(BoardState::new(fen).unwrap().is_insufficient_material());
+        }
+
+        for fen in [
+            "8/8/4k3/8/8/3KP3/8/8 w - - 0 1",
+            "8/8/2b1k3/8/8/3KB3/8/8 w - - 0 1",
+            "8/8/4k3/8/8/2NKN3/8/8 w - - 0 1",
+        ] {
+            assert!(!BoardState::new(fen).unwrap().is_insufficient_material());
+        }
+    }
+
+    #[test]
+    fn fifty_move_rule_with_contempt() {
+        //A queen up, but every move reaches the hundredth half move without a mate
+        let board_state = BoardState::new("7k/8/8/4K3/8/8/8/Q7 w - - 99 80").unwrap();
+        let options = SearchOptions {
+            contempt: 25,
+            ..SearchOptions::default()
+        };
+        let result = search_position(&board_state, &[], Duration::from_secs(2), &options);
+
+        assert!(result.move_found.is_some());
+        assert_eq!(result.score, -25);
+    }
+
+    #[test]
+    fn mate_on_the_hundredth_half_move_is_still_mate() {
+        let board_state = BoardState::new("7k/R7/6K1/8/8/8/8/8 w - - 99 80").unwrap();
+        let result = search_position(
+            &board_state,
+            &[],
+            Duration::from_secs(2),
+            &SearchOptions::default(),
+        );
+
+        assert_eq!(result.move_found.unwrap().to_string(), "a7a8");
+        assert_eq!(result.score, MATE_VALUE - 1);
+    }
+
+    #[test]
+    fn avoids_repetition_when_winning() {
+        //White is a queen up and has been shuffling, repeating again would be a draw
+        let mut board = BoardState::new("7k/8/8/4K3/8/8/8/Q7 w - - 0 1").unwrap();
+        let mut history = Vec::new();
+        for mv_string in ["a1b1", "h8g8", "b1a1", "g8h8", "a1b1", "h8g8", "b1a1"] {
+            play(&mut board, &mut history, mv_string);
+        }
+        let repeating_move = gen_all_moves(&board, board.active_color)
+            .into_iter()
+            .find(|mv| mv.to_string() == "g8h8")
+            .unwrap();
+        let mut board_copy = board.clone();
+        board_copy.make_move(&repeating_move);
+        history.push(board.zobrist_key());
+
+        let search = Search::new(
+            &history,
+            Color::White,
+            &SearchOptions::default(),
+            DefaultEvaluator::default(),
+        );
+        assert!(search.is_repetition(&board_copy, board_copy.zobrist_key()));
+
+        let result = search_position(
+            &board_copy,
+            &history,
+            Duration::from_secs(2),
+            &SearchOptions::default(),
+        );
+        let best_move = result.move_found.unwrap();
+        assert_ne!(best_move.to_string(), "a1b1");
+        assert!(result.score > 0);
+    }
+
+    #[test]
+    fn extensions_solve_tactics() {
+        //Win At Chess positions that need more than three plies to see through
+        let positions = [
+            ("5k2/6pp/p1qN4/1p1p4/3P4/2PKP2Q/PP3r2/3R4 b - - 0 1", "c6c4"),
+            ("7k/p7/1R5K/6r1/6p1/6P1/8/8 w - - 0 1", "b6b7"),
+            (
+                "rnbqkb1r/pppp1ppp/8/4P3/6n1/7P/PPPNPPP1/R1BQKBNR b KQkq - 0 1",
+                "g4e3",
+            ),
+            (
+                "4k1r1/2p3r1/1pR1p3/3pP2p/3P2qP/P4N2/1PQ4P/5R1K b - - 0 1",
+                "g4f3",
+            ),
+            (
+                "1R6/1brk2p1/4p2p/p1P1Pp2/P7/6P1/1P4P1/2R3K1 w - - 0 1",
+                "b8b7",
+            ),
+        ];
+
+        let count_solved = |extension_budget: u16| -> usize {
+            let options = SearchOptions {
+                max_depth: 3,
+                extension_budget,
+                ..SearchOptions::default()
+            };
+            positions
+                .iter()
+                .filter(|(fen, best_move)| {
+                    let board_state = BoardState::new(fen).unwrap();
+                    let result =
+                        search_position(&board_state, &[], Duration::from_secs(60), &options);
+                    result.move_found.unwrap().to_string() == *best_move
+                })
+                .count()
+        };
+
+        let solved_with_extensions = count_solved(8);
+        assert_eq!(solved_with_extensions, positions.len());
+        assert!(count_solved(0) < solved_with_extensions);
+    }
+
+    #[test]
+    fn pruning_keeps_tactics() {
+        let positions = [
+            ("5k2/6pp/p1qN4/1p1p4/3P4/2PKP2Q/PP3r2/3R4 b - - 0 1", "c6c4"),
+            (
+                "rnbqkb1r/pppp1ppp/8/4P3/6n1/7P/PPPNPPP1/R1BQKBNR b KQkq - 0 1",
+                "g4e3",
+            ),
+            (
+                "r1bq2rk/pp3pbp/2p1p1pQ/7P/3P4/2PB1N2/PP3PPR/2KR4 w - - 0 1",
+                "h6h7",
+            ),
+        ];
+        let unpruned = SearchOptions {
+            max_depth: 3,
+            pruning: PruningMargins {
+                max_depth: 0,
+                ..PruningMargins::default()
+            },
+            ..SearchOptions::default()
+        };
+        let pruned = SearchOptions {
+            max_depth: 3,
+            ..SearchOptions::default()
+        };
+
+        for (fen, best_move) in positions {
+            let board_state = BoardState::new(fen).unwrap();
+            let full_result =
+                search_position(&board_state, &[], Duration::from_secs(60), &unpruned);
+            let pruned_result =
+                search_position(&board_state, &[], Duration::from_secs(60), &pruned);
+
+            assert_eq!(pruned_result.move_found.unwrap().to_string(), best_move);
+            assert!(pruned_result.nodes_searched < full_result.nodes_searched);
+        }
+    }
+
+    #[test]
+    fn skill_level_from_elo() {
+        assert_eq!(SkillLevel::from_elo(500, 0).level, 0);
+        assert_eq!(SkillLevel::from_elo(MIN_ELO, 0).level, 0);
+        assert_eq!(SkillLevel::from_elo(1600, 0).level, 10);
+        assert_eq!(SkillLevel::from_elo(MAX_ELO, 0).level, MAX_SKILL_LEVEL);
+        assert_eq!(SkillLevel::new(30, 0).level, MAX_SKILL_LEVEL);
+    }
+
+    #[test]
+    fn weak_skill_levels_vary_their_moves() {
+        let board_state =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let legal_moves = gen_all_moves(&board_state, Color::White);
+        let weak_options = |seed: u64| SearchOptions {
+            skill: Some(SkillLevel::new(0, seed)),
+            ..SearchOptions::default()
+        };
+
+        let mut moves_played = Vec::new();
+        for seed in 0..10 {
+            let result = search_position(
+                &board_state,
+                &[],
+                Duration::from_secs(10),
+                &weak_options(seed),
+            );
+            let mv = result.move_found.unwrap();
+            assert!(legal_moves.contains(&mv));
+            moves_played.push(mv);
+        }
+        assert!(moves_played.iter().any(|mv| *mv != moves_played[0]));
+
+        //Same seed, same move
+        let first = search_position(&board_state, &[], Duration::from_secs(10), &weak_options(7));
+        let second = search_position(&board_state, &[], Duration::from_secs(10), &weak_options(7));
+        assert_eq!(first.move_found, second.move_found);
+    }
+
+    #[test]
+    fn full_skill_level_plays_normally() {
+        let board_state = BoardState::new("k7/4r1q1/5P2/8/8/8/8/K7 w - - 0 1").unwrap();
+        let options = SearchOptions {
+            max_depth: 3,
+            skill: Some(SkillLevel::new(MAX_SKILL_LEVEL, 1)),
+            ..SearchOptions::default()
+        };
+        let full_strength = SearchOptions {
+            max_depth: 3,
+            ..SearchOptions::default()
+        };
+
+        let result = search_position(&board_state, &[], Duration::from_secs(10), &options);
+        let expected = search_position(&board_state, &[], Duration::from_secs(10), &full_strength);
+        assert_eq!(result.move_found, expected.move_found);
+        assert_eq!(result.nodes_searched, expected.nodes_searched);
+    }
+
+    #[test]
+    fn depth_is_capped_below_max_depth() {
+        //Blocked pawns, only the kings can move so even the deepest search is quick
+        let board_state = BoardState::new("k7/p7/P7/8/8/8/8/K7 w - - 0 1").unwrap();
+        let options = SearchOptions {
+            max_depth: 50,
+            ..SearchOptions::default()
+        };
+        let result = search_position(&board_state, &[], Duration::from_secs(60), &options);
+        assert_eq!(result.depth, MAX_DEPTH - 1);
+    }
+
+    #[test]
+    fn every_evaluator_takes_the_queen() {
+        let board_state = BoardState::new("k7/4r1q1/5P2/8/8/8/8/K7 w - - 0 1").unwrap();
+        for evaluator in [
+            EvaluatorKind::Default,
+            EvaluatorKind::Pesto,
+            EvaluatorKind::Material,
+        ] {
+            let options = SearchOptions {
+                max_depth: 3,
+                evaluator,
+                ..SearchOptions::default()
+            };
+            let result = search_position(&board_state, &[], Duration::from_secs(10), &options);
+            let mv = result.move_found.unwrap();
+            assert_eq!(mv.piece_captured.unwrap().piece_type, PieceType::Queen);
+        }
+    }
+
+    #[test]
+    fn converts_bishop_and_knight_mate() {
+        //Playing both sides, the evaluation has to herd the king into a dark corner before the fifty move rule
+        let mut board = BoardState::new("8/8/3k4/8/8/2BNK3/8/8 w - - 0 1").unwrap();
+        let mut history = Vec::new();
+        let options = SearchOptions {
+            max_depth: 6,
+            ..SearchOptions::default()
+        };
+
+        while !board.is_fifty_move_draw() {
+            let moves = gen_all_moves(&board, board.active_color);
+            if moves.is_empty() {
+                break;
+            }
+            let mv = search_position(&board, &history, Duration::from_secs(60), &options)
+                .move_found
+                .unwrap();
+            history.push(board.zobrist_key());
+            board.make_move(&mv);
+            assert!(!board.is_insufficient_material());
+        }
+
+        assert!(gen_all_moves(&board, board.active_color).is_empty());
+        assert!(board.is_in_check(board.active_color, None));
+        assert!(history.len() < 100);
+    }
 }
@@ -0,0 +1,273 @@
+use crate::board_state::BoardState;
+use crate::chess_move::Move;
+use crate::san::{from_san, to_san};
+use crate::searcher::{SearchLimits, Searcher};
+use std::time::{Duration, Instant};
+
+/*
+    Test suites like WAC, ECM and STS in the Extended Position Description format, see https://www.chessprogramming.org/Extended_Position_Description
+    2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";
+    bm lists the moves that solve the position, am the ones that fail it, id names it and c0 is a comment. Other operations are skipped.
+*/
+
+pub struct EpdPosition {
+    pub fen: String,
+    pub best_moves: Vec<Move>,
+    pub avoid_moves: Vec<Move>,
+    pub id: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl EpdPosition {
+    //A position with neither bm nor am can't be failed
+    pub fn is_solved_by(&self, mv: &Move) -> bool {
+        (self.best_moves.is_empty() || self.best_moves.contains(mv))
+            && !self.avoid_moves.contains(mv)
+    }
+}
+
+/* Splits the operations on semicolons, leaving the ones inside quoted strings alone */
+fn split_operations(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut operations = Vec::new();
+    let mut operation = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => {
+                if !operation.is_empty() {
+                    operations.push(operation);
+                    operation = Vec::new();
+                }
+            }
+            '"' => {
+                let mut operand = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => operand.push(c),
+                        None => return Err("unclosed string".to_string()),
+                    }
+                }
+                operation.push(operand);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut operand = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' || c == '"' {
+                        break;
+                    }
+                    operand.push(c);
+                    chars.next();
+                }
+                operation.push(operand);
+            }
+        }
+    }
+    if !operation.is_empty() {
+        operations.push(operation);
+    }
+    Ok(operations)
+}
+
+pub fn parse_epd_line(line: &str) -> Result<EpdPosition, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 4 {
+        return Err(format!("{} has no position", line));
+    }
+    //EPD leaves out the move clocks
+    let fen = format!("{} 0 1", fields[..4].join(" "));
+    let board = BoardState::new(&fen).map_err(|e| format!("{}: {}", fen, e))?;
+
+    //The four position fields are followed by the operations
+    let mut rest = line.trim_start();
+    for _ in 0..4 {
+        rest = rest.trim_start();
+        rest = &rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..];
+    }
+
+    let (mut best_moves, mut avoid_moves, mut id, mut comment) =
+        (Vec::new(), Vec::new(), None, None);
+    for operation in split_operations(rest)? {
+        let (opcode, operands) = operation.split_first().unwrap();
+        let moves = || {
+            operands
+                .iter()
+                .map(|text| {
+                    from_san(&board, text).ok_or(format!("{} is not a legal move in {}", text, fen))
+                })
+                .collect::<Result<Vec<Move>, String>>()
+        };
+        match opcode.as_str() {
+            "bm" => best_moves = moves()?,
+            "am" => avoid_moves = moves()?,
+            "id" => id = operands.first().cloned(),
+            "c0" => comment = operands.first().cloned(),
+            _ => {}
+        }
+    }
+    Ok(EpdPosition {
+        fen,
+        best_moves,
+        avoid_moves,
+        id,
+        comment,
+    })
+}
+
+/*
+ * Reads a whole suite, blank lines and lines starting with # are skipped.
+ * A bad line doesn't stop the rest from being read, returns the positions and why each bad line was left out
+ */
+pub fn parse_epd(text: &str) -> (Vec<EpdPosition>, Vec<String>) {
+    let mut positions = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.trim().starts_with('#') {
+            continue;
+        }
+        match parse_epd_line(line) {
+            Ok(position) => positions.push(position),
+            Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+        }
+    }
+    (positions, errors)
+}
+
+pub struct EpdOutcome {
+    pub played: Option<String>, //In SAN, None when there was no move to play
+    pub solved: bool,
+    pub depth: u16,
+    //When the searcher settled on a solving move and kept it from then on
+    pub time_to_solution: Option<Duration>,
+}
+
+/*
+ * Searches one depth deeper each time until the limits run out, noting when the searcher first found a solving move it then stuck with.
+ * Searchers without depths stop after their first search.
+ */
+pub fn run_position(
+    searcher: &mut dyn Searcher,
+    position: &EpdPosition,
+    limits: &SearchLimits,
+) -> EpdOutcome {
+    let board = BoardState::new(&position.fen).unwrap();
+    let max_depth = limits.max_depth.unwrap_or(u16::MAX);
+    let start = Instant::now();
+    let mut outcome = EpdOutcome {
+        played: None,
+        solved: false,
+        depth: 0,
+        time_to_solution: None,
+    };
+
+    let mut depth = 1;
+    while depth <= max_depth {
+        let time_left = limits.time_to_think.saturating_sub(start.elapsed());
+        let result = searcher.search(
+            &board,
+            &[],
+            &SearchLimits {
+                time_to_think: time_left,
+                max_depth: Some(depth),
+                node_limit: limits.node_limit,
+            },
+        );
+        let Some(mv) = result.move_found else {
+            break;
+        };
+        //A search cut short by the clock only has an older depth's answer
+        if result.depth != 0 && result.depth < depth {
+            break;
+        }
+
+        outcome.played = Some(to_san(&board, &mv));
+        outcome.depth = result.depth;
+        outcome.solved = position.is_solved_by(&mv);
+        if !outcome.solved {
+            outcome.time_to_solution = None;
+        } else if outcome.time_to_solution.is_none() {
+            outcome.time_to_solution = Some(start.elapsed());
+        }
+
+        if result.depth == 0 || start.elapsed() >= limits.time_to_think {
+            break;
+        }
+        depth += 1;
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::SearchOptions;
+    use crate::searcher::{make_searcher, SearcherKind};
+
+    #[test]
+    fn parses_operations() {
+        let position = parse_epd_line(
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; am Qxg7+ Nh5; id \"WAC.001\"; c0 \"mates; soon\";",
+        )
+        .unwrap();
+        assert_eq!(position.id.as_deref(), Some("WAC.001"));
+        assert_eq!(position.comment.as_deref(), Some("mates; soon"));
+
+        let board = BoardState::new(&position.fen).unwrap();
+        let san = |moves: &[Move]| {
+            moves
+                .iter()
+                .map(|mv| to_san(&board, mv))
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(san(&position.best_moves), vec!["Qg6"]);
+        assert_eq!(san(&position.avoid_moves), vec!["Qxg7+", "Nh5"]);
+        assert!(position.is_solved_by(&position.best_moves[0]));
+        assert!(!position.is_solved_by(&position.avoid_moves[0]));
+
+        assert!(parse_epd_line("6k1/5ppp/8/8/8/8/8/R5K1 w").is_err());
+        assert!(parse_epd_line("6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Qh7#;").is_err());
+    }
+
+    #[test]
+    fn skips_bad_lines() {
+        let (suite, errors) = parse_epd(
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Qh7#; id \"bad move\";\n\
+             \n\
+             6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8#; id \"good\";\n\
+             6k1/5ppp/8/8/8/8/8/R5K1 w",
+        );
+        assert_eq!(suite.len(), 1);
+        assert_eq!(suite[0].id.as_deref(), Some("good"));
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("line 1: Qh7#"));
+        assert!(errors[1].starts_with("line 4: "));
+    }
+
+    #[test]
+    fn runs_suite_positions() {
+        let suite = parse_epd(
+            "# Back rank\n\
+             6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8#; id \"mate\";\n\
+             r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - am Qxf7#; id \"impossible\";",
+        )
+        .0;
+        assert_eq!(suite.len(), 2);
+
+        let mut searcher = make_searcher(SearcherKind::AlphaBeta, &SearchOptions::default(), 0);
+        let limits = SearchLimits {
+            time_to_think: Duration::from_secs(60),
+            max_depth: Some(2),
+            node_limit: None,
+        };
+        let solved = run_position(searcher.as_mut(), &suite[0], &limits);
+        assert!(solved.solved);
+        assert_eq!(solved.played.as_deref(), Some("Ra8#"));
+        assert!(solved.time_to_solution.is_some());
+
+        let failed = run_position(searcher.as_mut(), &suite[1], &limits);
+        assert!(!failed.solved);
+        assert_eq!(failed.played.as_deref(), Some("Qxf7#"));
+        assert_eq!(failed.time_to_solution, None);
+    }
+}
@@ -1,8 +1,19 @@
 use log::{debug, info};
 
 use crate::board_state::BoardState;
+use crate::chess_move::Position;
 use crate::color::Color;
+use crate::move_gen::{king_positions, piece_attacks};
 use crate::piece::{Piece, PieceType};
+use crate::square::Square;
+use serde::{Deserialize, Serialize};
+use std::cmp::{max, min};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::OnceLock;
 
 /*
     Evaluation function based on https://www.chessprogramming.org/PeSTO%27s_Evaluation_Function (inspired by the walleye chess bot)
@@ -140,54 +151,486 @@ const EG_KING_TABLE: [[i32; 8]; 8] = [
     [-53, -34, -21, -11, -28, -14, -24, -43],
 ];
 
-fn get_mg_table(piece: Piece) -> &'static [[i32; 8]; 8] {
-    match piece.piece_type {
-        PieceType::Pawn => &MG_PAWN_TABLE,
-        PieceType::Knight => &MG_KNIGHT_TABLE,
-        PieceType::Bishop => &MG_BISHOP_TABLE,
-        PieceType::Rook => &MG_ROOK_TABLE,
-        PieceType::Queen => &MG_QUEEN_TABLE,
-        PieceType::King => &MG_KING_TABLE,
-        PieceType::None => panic!("something went wrong"),
+/*
+    Pawn structure weights, see https://www.chessprogramming.org/Pawn_Structure
+    Passed pawn bonuses are indexed by the rank from the pawn's own side, so index 6 is a pawn on the seventh rank
+*/
+const MG_DOUBLED_PAWN: i32 = -10;
+const EG_DOUBLED_PAWN: i32 = -25;
+const MG_ISOLATED_PAWN: i32 = -12;
+const EG_ISOLATED_PAWN: i32 = -15;
+const MG_BACKWARD_PAWN: i32 = -8;
+const EG_BACKWARD_PAWN: i32 = -10;
+const MG_CONNECTED_PAWN: i32 = 8; //Defended by another pawn or standing next to one
+const EG_CONNECTED_PAWN: i32 = 6;
+const MG_PASSED_PAWN: [i32; 8] = [0, 2, 5, 10, 20, 35, 60, 0];
+const EG_PASSED_PAWN: [i32; 8] = [0, 5, 10, 20, 35, 60, 100, 0];
+//Instead of the bonus above when an enemy piece sits in front of the pawn
+const MG_BLOCKADED_PASSED_PAWN: [i32; 8] = [0, 1, 2, 5, 10, 17, 30, 0];
+const EG_BLOCKADED_PASSED_PAWN: [i32; 8] = [0, 2, 5, 10, 17, 30, 50, 0];
+const MG_CANDIDATE_PASSER: [i32; 8] = [0, 2, 3, 5, 10, 15, 0, 0];
+const EG_CANDIDATE_PASSER: [i32; 8] = [0, 4, 6, 10, 20, 30, 0, 0];
+const EG_PASSED_PAWN_KING_DISTANCE: i32 = 4; //Per square per rank past the fourth, enemy king far and own king close is good
+
+/*
+    King safety weights, see https://www.chessprogramming.org/King_Safety
+    These are all middlegame only so they fade out as the pieces come off
+*/
+const KING_ATTACK_WEIGHTS: [i32; 4] = [2, 2, 3, 5]; //Knight, bishop, rook, queen
+                                                    //Penalty by weighted number of attacks on the king zone, grows faster than linear since attacks add up
+const KING_DANGER: [i32; 32] = [
+    0, 0, 1, 2, 4, 6, 9, 12, 16, 20, 25, 30, 36, 42, 49, 56, 64, 72, 81, 90, 100, 110, 121, 132,
+    144, 156, 169, 182, 196, 210, 225, 240,
+];
+const MG_PAWN_SHIELD: [i32; 2] = [15, 8]; //Own pawn one or two squares in front of the king
+const MG_PAWN_STORM: [i32; 3] = [-20, -12, -6]; //Enemy pawn one, two or three squares in front of the king
+const MG_SEMI_OPEN_KING_FILE: i32 = -15; //No pawn of ours on a file next to the king
+const MG_OPEN_KING_FILE: i32 = -25; //No pawns at all on a file next to the king
+
+/*
+    Mobility and piece activity weights, see https://www.chessprogramming.org/Mobility
+    Mobility is scored per square a piece attacks, relative to a typical number of squares for that piece
+*/
+const MG_MOBILITY: [i32; 4] = [4, 5, 2, 1]; //Knight, bishop, rook, queen
+const EG_MOBILITY: [i32; 4] = [4, 5, 4, 2];
+const AVERAGE_MOBILITY: [i32; 4] = [4, 6, 7, 13];
+const MG_BISHOP_PAIR: i32 = 30;
+const EG_BISHOP_PAIR: i32 = 50;
+const MG_ROOK_OPEN_FILE: i32 = 25;
+const EG_ROOK_OPEN_FILE: i32 = 10;
+const MG_ROOK_SEMI_OPEN_FILE: i32 = 12;
+const EG_ROOK_SEMI_OPEN_FILE: i32 = 6;
+const MG_ROOK_ON_SEVENTH: i32 = 20; //Only when it pins the king to the back rank or has pawns to eat
+const EG_ROOK_ON_SEVENTH: i32 = 30;
+const MG_KNIGHT_OUTPOST: i32 = 20; //Defended by a pawn and out of reach of enemy pawns
+const EG_KNIGHT_OUTPOST: i32 = 10;
+
+/*
+    Endgame knowledge, see https://www.chessprogramming.org/Endgame
+    Won endgames get a large bonus plus a gradient toward mate, drawish material scales the endgame score down
+*/
+const KNOWN_WIN: i32 = 10000;
+const PUSH_TO_EDGE: i32 = 20; //Per square the lone king is from the centre
+const PUSH_TO_CORNER: i32 = 40; //Per square the lone king is closer to a corner the bishop can mate in
+const PUSH_KINGS_CLOSE: i32 = 10; //Per square the kings are closer together
+const KPK_PAWN_RANK: i32 = 20; //Per rank the pawn has advanced in a won KPK
+const SCALE_FACTOR_NORMAL: i32 = 64;
+const SCALE_FACTOR_OPPOSITE_BISHOPS: i32 = 32;
+const SCALE_FACTOR_NO_PAWNS: i32 = 8; //Only a minor piece or less up with no pawns to promote
+const SCALE_FACTOR_DRAW: i32 = 0;
+
+/*
+ * Every weight used by the evaluation, the defaults are the constants above.
+ * Can be loaded from a TOML file at startup, values missing from the file keep their defaults and misspelled ones are an error.
+ * Only what the weights are measured against stays fixed: game phase, average mobility and the normal and drawn scale factors
+ */
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvalParams {
+    pub mg_piece_values: [i32; 6], //Pawn, knight, bishop, rook, queen, king
+    pub eg_piece_values: [i32; 6],
+    pub mg_pawn_table: [[i32; 8]; 8],
+    pub eg_pawn_table: [[i32; 8]; 8],
+    pub mg_knight_table: [[i32; 8]; 8],
+    pub eg_knight_table: [[i32; 8]; 8],
+    pub mg_bishop_table: [[i32; 8]; 8],
+    pub eg_bishop_table: [[i32; 8]; 8],
+    pub mg_rook_table: [[i32; 8]; 8],
+    pub eg_rook_table: [[i32; 8]; 8],
+    pub mg_queen_table: [[i32; 8]; 8],
+    pub eg_queen_table: [[i32; 8]; 8],
+    pub mg_king_table: [[i32; 8]; 8],
+    pub eg_king_table: [[i32; 8]; 8],
+    pub mg_doubled_pawn: i32,
+    pub eg_doubled_pawn: i32,
+    pub mg_isolated_pawn: i32,
+    pub eg_isolated_pawn: i32,
+    pub mg_backward_pawn: i32,
+    pub eg_backward_pawn: i32,
+    pub mg_connected_pawn: i32,
+    pub eg_connected_pawn: i32,
+    pub mg_passed_pawn: [i32; 8], //By rank from the pawn's own side
+    pub eg_passed_pawn: [i32; 8],
+    pub mg_blockaded_passed_pawn: [i32; 8],
+    pub eg_blockaded_passed_pawn: [i32; 8],
+    pub mg_candidate_passer: [i32; 8],
+    pub eg_candidate_passer: [i32; 8],
+    pub eg_passed_pawn_king_distance: i32,
+    pub king_attack_weights: [i32; 4], //Knight, bishop, rook, queen
+    pub king_danger: [i32; 32],
+    pub mg_pawn_shield: [i32; 2],
+    pub mg_pawn_storm: [i32; 3],
+    pub mg_semi_open_king_file: i32,
+    pub mg_open_king_file: i32,
+    pub mg_mobility: [i32; 4], //Knight, bishop, rook, queen
+    pub eg_mobility: [i32; 4],
+    pub mg_bishop_pair: i32,
+    pub eg_bishop_pair: i32,
+    pub mg_rook_open_file: i32,
+    pub eg_rook_open_file: i32,
+    pub mg_rook_semi_open_file: i32,
+    pub eg_rook_semi_open_file: i32,
+    pub mg_rook_on_seventh: i32,
+    pub eg_rook_on_seventh: i32,
+    pub mg_knight_outpost: i32,
+    pub eg_knight_outpost: i32,
+    pub known_win: i32,
+    pub push_to_edge: i32,
+    pub push_to_corner: i32,
+    pub push_kings_close: i32,
+    pub kpk_pawn_rank: i32,
+    pub scale_factor_opposite_bishops: i32, //Out of SCALE_FACTOR_NORMAL
+    pub scale_factor_no_pawns: i32,
+}
+
+impl Default for EvalParams {
+    fn default() -> EvalParams {
+        EvalParams {
+            mg_piece_values: [82, 337, 365, 477, 1025, 0],
+            eg_piece_values: [94, 281, 297, 512, 936, 0],
+            mg_pawn_table: MG_PAWN_TABLE,
+            eg_pawn_table: EG_PAWN_TABLE,
+            mg_knight_table: MG_KNIGHT_TABLE,
+            eg_knight_table: EG_KNIGHT_TABLE,
+            mg_bishop_table: MG_BISHOP_TABLE,
+            eg_bishop_table: EG_BISHOP_TABLE,
+            mg_rook_table: MG_ROOK_TABLE,
+            eg_rook_table: EG_ROOK_TABLE,
+            mg_queen_table: MG_QUEEN_TABLE,
+            eg_queen_table: EG_QUEEN_TABLE,
+            mg_king_table: MG_KING_TABLE,
+            eg_king_table: EG_KING_TABLE,
+            mg_doubled_pawn: MG_DOUBLED_PAWN,
+            eg_doubled_pawn: EG_DOUBLED_PAWN,
+            mg_isolated_pawn: MG_ISOLATED_PAWN,
+            eg_isolated_pawn: EG_ISOLATED_PAWN,
+            mg_backward_pawn: MG_BACKWARD_PAWN,
+            eg_backward_pawn: EG_BACKWARD_PAWN,
+            mg_connected_pawn: MG_CONNECTED_PAWN,
+            eg_connected_pawn: EG_CONNECTED_PAWN,
+            mg_passed_pawn: MG_PASSED_PAWN,
+            eg_passed_pawn: EG_PASSED_PAWN,
+            mg_blockaded_passed_pawn: MG_BLOCKADED_PASSED_PAWN,
+            eg_blockaded_passed_pawn: EG_BLOCKADED_PASSED_PAWN,
+            mg_candidate_passer: MG_CANDIDATE_PASSER,
+            eg_candidate_passer: EG_CANDIDATE_PASSER,
+            eg_passed_pawn_king_distance: EG_PASSED_PAWN_KING_DISTANCE,
+            king_attack_weights: KING_ATTACK_WEIGHTS,
+            king_danger: KING_DANGER,
+            mg_pawn_shield: MG_PAWN_SHIELD,
+            mg_pawn_storm: MG_PAWN_STORM,
+            mg_semi_open_king_file: MG_SEMI_OPEN_KING_FILE,
+            mg_open_king_file: MG_OPEN_KING_FILE,
+            mg_mobility: MG_MOBILITY,
+            eg_mobility: EG_MOBILITY,
+            mg_bishop_pair: MG_BISHOP_PAIR,
+            eg_bishop_pair: EG_BISHOP_PAIR,
+            mg_rook_open_file: MG_ROOK_OPEN_FILE,
+            eg_rook_open_file: EG_ROOK_OPEN_FILE,
+            mg_rook_semi_open_file: MG_ROOK_SEMI_OPEN_FILE,
+            eg_rook_semi_open_file: EG_ROOK_SEMI_OPEN_FILE,
+            mg_rook_on_seventh: MG_ROOK_ON_SEVENTH,
+            eg_rook_on_seventh: EG_ROOK_ON_SEVENTH,
+            mg_knight_outpost: MG_KNIGHT_OUTPOST,
+            eg_knight_outpost: EG_KNIGHT_OUTPOST,
+            known_win: KNOWN_WIN,
+            push_to_edge: PUSH_TO_EDGE,
+            push_to_corner: PUSH_TO_CORNER,
+            push_kings_close: PUSH_KINGS_CLOSE,
+            kpk_pawn_rank: KPK_PAWN_RANK,
+            scale_factor_opposite_bishops: SCALE_FACTOR_OPPOSITE_BISHOPS,
+            scale_factor_no_pawns: SCALE_FACTOR_NO_PAWNS,
+        }
+    }
+}
+
+impl EvalParams {
+    pub fn from_toml(text: &str) -> Result<EvalParams, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("eval params always serialize")
+    }
+
+    pub fn load(path: &Path) -> Result<EvalParams, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        EvalParams::from_toml(&text)
+    }
+}
+
+/*
+ * Where each value sits in EvalParams::to_vec, which is also its feature index in LinearEval.
+ * Arrays take one index per entry, tables come piece by piece with the middlegame table first
+ */
+mod feature {
+    pub const MG_PIECE_VALUES: usize = 0;
+    pub const EG_PIECE_VALUES: usize = MG_PIECE_VALUES + 6;
+    pub const TABLES: usize = EG_PIECE_VALUES + 6;
+    pub const MG_DOUBLED_PAWN: usize = TABLES + 12 * 64;
+    pub const EG_DOUBLED_PAWN: usize = MG_DOUBLED_PAWN + 1;
+    pub const MG_ISOLATED_PAWN: usize = EG_DOUBLED_PAWN + 1;
+    pub const EG_ISOLATED_PAWN: usize = MG_ISOLATED_PAWN + 1;
+    pub const MG_BACKWARD_PAWN: usize = EG_ISOLATED_PAWN + 1;
+    pub const EG_BACKWARD_PAWN: usize = MG_BACKWARD_PAWN + 1;
+    pub const MG_CONNECTED_PAWN: usize = EG_BACKWARD_PAWN + 1;
+    pub const EG_CONNECTED_PAWN: usize = MG_CONNECTED_PAWN + 1;
+    pub const MG_PASSED_PAWN: usize = EG_CONNECTED_PAWN + 1;
+    pub const EG_PASSED_PAWN: usize = MG_PASSED_PAWN + 8;
+    pub const MG_BLOCKADED_PASSED_PAWN: usize = EG_PASSED_PAWN + 8;
+    pub const EG_BLOCKADED_PASSED_PAWN: usize = MG_BLOCKADED_PASSED_PAWN + 8;
+    pub const MG_CANDIDATE_PASSER: usize = EG_BLOCKADED_PASSED_PAWN + 8;
+    pub const EG_CANDIDATE_PASSER: usize = MG_CANDIDATE_PASSER + 8;
+    pub const EG_PASSED_PAWN_KING_DISTANCE: usize = EG_CANDIDATE_PASSER + 8;
+    pub const KING_ATTACK_WEIGHTS: usize = EG_PASSED_PAWN_KING_DISTANCE + 1;
+    pub const KING_DANGER: usize = KING_ATTACK_WEIGHTS + 4;
+    pub const MG_PAWN_SHIELD: usize = KING_DANGER + 32;
+    pub const MG_PAWN_STORM: usize = MG_PAWN_SHIELD + 2;
+    pub const MG_SEMI_OPEN_KING_FILE: usize = MG_PAWN_STORM + 3;
+    pub const MG_OPEN_KING_FILE: usize = MG_SEMI_OPEN_KING_FILE + 1;
+    pub const MG_MOBILITY: usize = MG_OPEN_KING_FILE + 1;
+    pub const EG_MOBILITY: usize = MG_MOBILITY + 4;
+    pub const MG_BISHOP_PAIR: usize = EG_MOBILITY + 4;
+    pub const EG_BISHOP_PAIR: usize = MG_BISHOP_PAIR + 1;
+    pub const MG_ROOK_OPEN_FILE: usize = EG_BISHOP_PAIR + 1;
+    pub const EG_ROOK_OPEN_FILE: usize = MG_ROOK_OPEN_FILE + 1;
+    pub const MG_ROOK_SEMI_OPEN_FILE: usize = EG_ROOK_OPEN_FILE + 1;
+    pub const EG_ROOK_SEMI_OPEN_FILE: usize = MG_ROOK_SEMI_OPEN_FILE + 1;
+    pub const MG_ROOK_ON_SEVENTH: usize = EG_ROOK_SEMI_OPEN_FILE + 1;
+    pub const EG_ROOK_ON_SEVENTH: usize = MG_ROOK_ON_SEVENTH + 1;
+    pub const MG_KNIGHT_OUTPOST: usize = EG_ROOK_ON_SEVENTH + 1;
+    pub const EG_KNIGHT_OUTPOST: usize = MG_KNIGHT_OUTPOST + 1;
+    pub const KNOWN_WIN: usize = EG_KNIGHT_OUTPOST + 1;
+    pub const PUSH_TO_EDGE: usize = KNOWN_WIN + 1;
+    pub const PUSH_TO_CORNER: usize = PUSH_TO_EDGE + 1;
+    pub const PUSH_KINGS_CLOSE: usize = PUSH_TO_CORNER + 1;
+    pub const KPK_PAWN_RANK: usize = PUSH_KINGS_CLOSE + 1;
+    pub const SCALE_FACTOR_OPPOSITE_BISHOPS: usize = KPK_PAWN_RANK + 1;
+    pub const SCALE_FACTOR_NO_PAWNS: usize = SCALE_FACTOR_OPPOSITE_BISHOPS + 1;
+}
+
+/* Number of values in EvalParams */
+pub const NUM_EVAL_PARAMS: usize = feature::SCALE_FACTOR_NO_PAWNS + 1;
+
+impl EvalParams {
+    fn tables(&self) -> [&[[i32; 8]; 8]; 12] {
+        [
+            &self.mg_pawn_table,
+            &self.eg_pawn_table,
+            &self.mg_knight_table,
+            &self.eg_knight_table,
+            &self.mg_bishop_table,
+            &self.eg_bishop_table,
+            &self.mg_rook_table,
+            &self.eg_rook_table,
+            &self.mg_queen_table,
+            &self.eg_queen_table,
+            &self.mg_king_table,
+            &self.eg_king_table,
+        ]
+    }
+
+    fn tables_mut(&mut self) -> [&mut [[i32; 8]; 8]; 12] {
+        [
+            &mut self.mg_pawn_table,
+            &mut self.eg_pawn_table,
+            &mut self.mg_knight_table,
+            &mut self.eg_knight_table,
+            &mut self.mg_bishop_table,
+            &mut self.eg_bishop_table,
+            &mut self.mg_rook_table,
+            &mut self.eg_rook_table,
+            &mut self.mg_queen_table,
+            &mut self.eg_queen_table,
+            &mut self.mg_king_table,
+            &mut self.eg_king_table,
+        ]
+    }
+
+    //Every field after the tables, in field order
+    fn weights(&self) -> [&[i32]; 40] {
+        [
+            slice::from_ref(&self.mg_doubled_pawn),
+            slice::from_ref(&self.eg_doubled_pawn),
+            slice::from_ref(&self.mg_isolated_pawn),
+            slice::from_ref(&self.eg_isolated_pawn),
+            slice::from_ref(&self.mg_backward_pawn),
+            slice::from_ref(&self.eg_backward_pawn),
+            slice::from_ref(&self.mg_connected_pawn),
+            slice::from_ref(&self.eg_connected_pawn),
+            &self.mg_passed_pawn,
+            &self.eg_passed_pawn,
+            &self.mg_blockaded_passed_pawn,
+            &self.eg_blockaded_passed_pawn,
+            &self.mg_candidate_passer,
+            &self.eg_candidate_passer,
+            slice::from_ref(&self.eg_passed_pawn_king_distance),
+            &self.king_attack_weights,
+            &self.king_danger,
+            &self.mg_pawn_shield,
+            &self.mg_pawn_storm,
+            slice::from_ref(&self.mg_semi_open_king_file),
+            slice::from_ref(&self.mg_open_king_file),
+            &self.mg_mobility,
+            &self.eg_mobility,
+            slice::from_ref(&self.mg_bishop_pair),
+            slice::from_ref(&self.eg_bishop_pair),
+            slice::from_ref(&self.mg_rook_open_file),
+            slice::from_ref(&self.eg_rook_open_file),
+            slice::from_ref(&self.mg_rook_semi_open_file),
+            slice::from_ref(&self.eg_rook_semi_open_file),
+            slice::from_ref(&self.mg_rook_on_seventh),
+            slice::from_ref(&self.eg_rook_on_seventh),
+            slice::from_ref(&self.mg_knight_outpost),
+            slice::from_ref(&self.eg_knight_outpost),
+            slice::from_ref(&self.known_win),
+            slice::from_ref(&self.push_to_edge),
+            slice::from_ref(&self.push_to_corner),
+            slice::from_ref(&self.push_kings_close),
+            slice::from_ref(&self.kpk_pawn_rank),
+            slice::from_ref(&self.scale_factor_opposite_bishops),
+            slice::from_ref(&self.scale_factor_no_pawns),
+        ]
+    }
+
+    fn weights_mut(&mut self) -> [&mut [i32]; 40] {
+        [
+            slice::from_mut(&mut self.mg_doubled_pawn),
+            slice::from_mut(&mut self.eg_doubled_pawn),
+            slice::from_mut(&mut self.mg_isolated_pawn),
+            slice::from_mut(&mut self.eg_isolated_pawn),
+            slice::from_mut(&mut self.mg_backward_pawn),
+            slice::from_mut(&mut self.eg_backward_pawn),
+            slice::from_mut(&mut self.mg_connected_pawn),
+            slice::from_mut(&mut self.eg_connected_pawn),
+            &mut self.mg_passed_pawn,
+            &mut self.eg_passed_pawn,
+            &mut self.mg_blockaded_passed_pawn,
+            &mut self.eg_blockaded_passed_pawn,
+            &mut self.mg_candidate_passer,
+            &mut self.eg_candidate_passer,
+            slice::from_mut(&mut self.eg_passed_pawn_king_distance),
+            &mut self.king_attack_weights,
+            &mut self.king_danger,
+            &mut self.mg_pawn_shield,
+            &mut self.mg_pawn_storm,
+            slice::from_mut(&mut self.mg_semi_open_king_file),
+            slice::from_mut(&mut self.mg_open_king_file),
+            &mut self.mg_mobility,
+            &mut self.eg_mobility,
+            slice::from_mut(&mut self.mg_bishop_pair),
+            slice::from_mut(&mut self.eg_bishop_pair),
+            slice::from_mut(&mut self.mg_rook_open_file),
+            slice::from_mut(&mut self.eg_rook_open_file),
+            slice::from_mut(&mut self.mg_rook_semi_open_file),
+            slice::from_mut(&mut self.eg_rook_semi_open_file),
+            slice::from_mut(&mut self.mg_rook_on_seventh),
+            slice::from_mut(&mut self.eg_rook_on_seventh),
+            slice::from_mut(&mut self.mg_knight_outpost),
+            slice::from_mut(&mut self.eg_knight_outpost),
+            slice::from_mut(&mut self.known_win),
+            slice::from_mut(&mut self.push_to_edge),
+            slice::from_mut(&mut self.push_to_corner),
+            slice::from_mut(&mut self.push_kings_close),
+            slice::from_mut(&mut self.kpk_pawn_rank),
+            slice::from_mut(&mut self.scale_factor_opposite_bishops),
+            slice::from_mut(&mut self.scale_factor_no_pawns),
+        ]
+    }
+
+    //Every value in field order, see the feature indices
+    pub fn to_vec(&self) -> Vec<i32> {
+        let mut values = Vec::with_capacity(NUM_EVAL_PARAMS);
+        values.extend_from_slice(&self.mg_piece_values);
+        values.extend_from_slice(&self.eg_piece_values);
+        for table in self.tables() {
+            values.extend(table.iter().flatten());
+        }
+        for weight in self.weights() {
+            values.extend_from_slice(weight);
+        }
+        values
+    }
+
+    pub fn from_slice(values: &[i32]) -> EvalParams {
+        assert_eq!(values.len(), NUM_EVAL_PARAMS);
+        let mut params = EvalParams::default();
+        params.mg_piece_values.copy_from_slice(&values[0..6]);
+        params.eg_piece_values.copy_from_slice(&values[6..12]);
+        for (index, table) in params.tables_mut().into_iter().enumerate() {
+            for (square, value) in table.iter_mut().flatten().enumerate() {
+                *value = values[feature::TABLES + index * 64 + square];
+            }
+        }
+        let mut rest = &values[feature::MG_DOUBLED_PAWN..];
+        for weight in params.weights_mut() {
+            let (head, tail) = rest.split_at(weight.len());
+            weight.copy_from_slice(head);
+            rest = tail;
+        }
+        params
     }
 }
 
-fn get_eg_table(piece: Piece) -> &'static [[i32; 8]; 8] {
+//Set once at startup, every board built after that uses these values
+static EVAL_PARAMS: OnceLock<EvalParams> = OnceLock::new();
+
+pub fn eval_params() -> &'static EvalParams {
+    EVAL_PARAMS.get_or_init(EvalParams::default)
+}
+
+/* Replaces the default values, only works before the first evaluation or board has been made */
+pub fn set_eval_params(params: EvalParams) -> Result<(), &'static str> {
+    EVAL_PARAMS
+        .set(params)
+        .map_err(|_| "eval params are already in use")
+}
+
+fn piece_index(piece: Piece) -> usize {
     match piece.piece_type {
-        PieceType::Pawn => &EG_PAWN_TABLE,
-        PieceType::Knight => &EG_KNIGHT_TABLE,
-        PieceType::Bishop => &EG_BISHOP_TABLE,
-        PieceType::Rook => &EG_ROOK_TABLE,
-        PieceType::Queen => &EG_QUEEN_TABLE,
-        PieceType::King => &EG_KING_TABLE,
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
         PieceType::None => panic!("something went wrong"),
     }
 }
 
-fn get_mg_piece_val(piece: Piece) -> i32 {
+fn get_mg_table(params: &EvalParams, piece: Piece) -> &[[i32; 8]; 8] {
     match piece.piece_type {
-        PieceType::Pawn => 82,
-        PieceType::Knight => 337,
-        PieceType::Bishop => 365,
-        PieceType::Rook => 477,
-        PieceType::Queen => 1025,
-        PieceType::King => 0,
+        PieceType::Pawn => &params.mg_pawn_table,
+        PieceType::Knight => &params.mg_knight_table,
+        PieceType::Bishop => &params.mg_bishop_table,
+        PieceType::Rook => &params.mg_rook_table,
+        PieceType::Queen => &params.mg_queen_table,
+        PieceType::King => &params.mg_king_table,
         PieceType::None => panic!("something went wrong"),
     }
 }
 
-fn get_eg_piece_val(piece: Piece) -> i32 {
+fn get_eg_table(params: &EvalParams, piece: Piece) -> &[[i32; 8]; 8] {
     match piece.piece_type {
-        PieceType::Pawn => 94,
-        PieceType::Knight => 281,
-        PieceType::Bishop => 297,
-        PieceType::Rook => 512,
-        PieceType::Queen => 936,
-        PieceType::King => 0,
+        PieceType::Pawn => &params.eg_pawn_table,
+        PieceType::Knight => &params.eg_knight_table,
+        PieceType::Bishop => &params.eg_bishop_table,
+        PieceType::Rook => &params.eg_rook_table,
+        PieceType::Queen => &params.eg_queen_table,
+        PieceType::King => &params.eg_king_table,
         PieceType::None => panic!("something went wrong"),
     }
 }
 
+fn get_mg_piece_val(params: &EvalParams, piece: Piece) -> i32 {
+    params.mg_piece_values[piece_index(piece)]
+}
+
+fn get_eg_piece_val(params: &EvalParams, piece: Piece) -> i32 {
+    params.eg_piece_values[piece_index(piece)]
+}
+
 fn get_game_phase_val(piece: Piece) -> i32 {
     match piece.piece_type {
         PieceType::Pawn => 0,
@@ -199,56 +642,1399 @@ fn get_game_phase_val(piece: Piece) -> i32 {
         PieceType::None => panic!("something went wrong"),
     }
 }
-//Returns f32 for now
-pub fn evaluate(board: &BoardState) -> i32 {
-    let mut white_mg = 0;
-    let mut black_mg = 0;
-    let mut white_eg = 0;
-    let mut black_eg = 0;
-    let mut game_phase = 0;
-    let START = 2;
-    let END = 10;
-
-    for row in START..END {
-        for col in START..END {
+
+/*
+ * Material and piece square table sums for both colors, plus the game phase.
+ * Kept on the board and updated as pieces are added and removed so evaluate doesn't have to walk every square
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct PieceSquareSums {
+    pub mg: [i32; 2],
+    pub eg: [i32; 2],
+    pub game_phase: i32,
+}
+
+impl PieceSquareSums {
+    //Full recompute from the squares of a board
+    pub fn from_squares(squares: &[[Square; 12]; 12]) -> PieceSquareSums {
+        PieceSquareSums::from_squares_with(squares, eval_params())
+    }
+
+    //Same sums for params other than the process wide ones, which are what the board keeps up to date
+    pub fn from_squares_with(squares: &[[Square; 12]; 12], params: &EvalParams) -> PieceSquareSums {
+        let mut sums = PieceSquareSums::default();
+        for row in 2..10 {
+            for col in 2..10 {
+                if let Some(piece) = squares[row][col].piece {
+                    let (mg, eg) = piece_square_values(params, piece, row, col);
+                    sums.mg[color_index(piece.color)] += mg;
+                    sums.eg[color_index(piece.color)] += eg;
+                    sums.game_phase += get_game_phase_val(piece);
+                }
+            }
+        }
+        sums
+    }
+
+    //Row and col are indices into the 12x12 board
+    pub fn add_piece(&mut self, piece: Piece, row: usize, col: usize) {
+        let (mg, eg) = piece_square_values(eval_params(), piece, row, col);
+        self.mg[color_index(piece.color)] += mg;
+        self.eg[color_index(piece.color)] += eg;
+        self.game_phase += get_game_phase_val(piece);
+    }
+
+    pub fn remove_piece(&mut self, piece: Piece, row: usize, col: usize) {
+        let (mg, eg) = piece_square_values(eval_params(), piece, row, col);
+        self.mg[color_index(piece.color)] -= mg;
+        self.eg[color_index(piece.color)] -= eg;
+        self.game_phase -= get_game_phase_val(piece);
+    }
+}
+
+/* Material plus table value of a piece on a square, black reads the tables upside down */
+fn piece_square_values(params: &EvalParams, piece: Piece, row: usize, col: usize) -> (i32, i32) {
+    let table_row = match piece.color {
+        Color::White => row - 2,
+        Color::Black => 9 - row,
+    };
+    (
+        get_mg_table(params, piece)[table_row][col - 2] + get_mg_piece_val(params, piece),
+        get_eg_table(params, piece)[table_row][col - 2] + get_eg_piece_val(params, piece),
+    )
+}
+
+/* Middlegame and endgame score for each color, white first */
+pub type SideScores = [(i32, i32); 2];
+
+fn white_minus_black(scores: SideScores) -> (i32, i32) {
+    (scores[0].0 - scores[1].0, scores[0].1 - scores[1].1)
+}
+
+/*
+ * Weighted terms added up for each side. linear_eval also needs to know which EvalParams value each term used,
+ * so with features on every term is written down as its feature index and count, negative for black
+ */
+#[derive(Default)]
+struct Terms {
+    scores: SideScores,
+    features: Option<(Vec<(usize, i32)>, Vec<(usize, i32)>)>, //Middlegame then endgame
+}
+
+impl Terms {
+    fn with_features() -> Terms {
+        Terms {
+            scores: [(0, 0); 2],
+            features: Some((Vec::new(), Vec::new())),
+        }
+    }
+
+    //Count times the value at feature, which the caller reads out of the params it was given
+    fn add_mg(&mut self, color: Color, feature: usize, value: i32, count: i32) {
+        self.scores[color_index(color)].0 += value * count;
+        self.add_mg_feature(color, feature, count);
+    }
+
+    fn add_eg(&mut self, color: Color, feature: usize, value: i32, count: i32) {
+        self.scores[color_index(color)].1 += value * count;
+        if let Some((_, eg_features)) = &mut self.features {
+            push_feature(eg_features, color, feature, count);
+        }
+    }
+
+    //Only the feature, for a value whose effect is already in the score some other way
+    fn add_mg_feature(&mut self, color: Color, feature: usize, count: i32) {
+        if let Some((mg_features, _)) = &mut self.features {
+            push_feature(mg_features, color, feature, count);
+        }
+    }
+}
+
+fn push_feature(features: &mut Vec<(usize, i32)>, color: Color, feature: usize, count: i32) {
+    match color {
+        _ if count == 0 => {}
+        Color::White => features.push((feature, count)),
+        Color::Black => features.push((feature, -count)),
+    }
+}
+
+//Chebyshev distance, the number of king moves between two squares
+fn square_distance(first: Position, second: Position) -> i32 {
+    max(
+        (first.row as i32 - second.row as i32).abs(),
+        (first.col as i32 - second.col as i32).abs(),
+    )
+}
+
+/*
+ * Scores doubled, isolated, backward, connected, passed and candidate passed pawns.
+ * Returns the middlegame and endgame scores from white's point of view
+ */
+pub fn evaluate_pawn_structure(board: &BoardState) -> (i32, i32) {
+    let mut terms = Terms::default();
+    pawn_structure_terms(board, eval_params(), &mut terms);
+    white_minus_black(terms.scores)
+}
+
+fn pawn_structure_terms(board: &BoardState, params: &EvalParams, terms: &mut Terms) {
+    let mut king_positions: [Position; 2] = [Position { row: 0, col: 0 }; 2];
+    //pawn_rows[color][col] holds the rows of every pawn of that color on that file
+    let mut pawn_rows: [[Vec<usize>; 12]; 2] = Default::default();
+
+    for row in 2..10 {
+        for col in 2..10 {
             if let Some(piece) = board.squares[row][col].piece {
-                game_phase += get_game_phase_val(piece);
-                match piece.color {
-                    Color::White => {
-                        white_mg +=
-                            get_mg_table(piece)[row - START][col - START] + get_mg_piece_val(piece);
-                        white_eg +=
-                            get_eg_table(piece)[row - START][col - START] + get_eg_piece_val(piece);
+                match piece.piece_type {
+                    PieceType::Pawn => pawn_rows[color_index(piece.color)][col].push(row),
+                    PieceType::King => {
+                        king_positions[color_index(piece.color)] = Position { row, col }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    for color in [Color::White, Color::Black] {
+        let own = color_index(color);
+        let enemy = color_index(color.opposite());
+        //Rows in front of a pawn are smaller for white and bigger for black
+        let is_ahead = |row: usize, of: usize| -> bool {
+            match color {
+                Color::White => row < of,
+                Color::Black => row > of,
+            }
+        };
+
+        for col in 2..10 {
+            let file_pawns = &pawn_rows[own][col];
+            if file_pawns.len() > 1 {
+                let doubled = file_pawns.len() as i32 - 1;
+                terms.add_mg(
+                    color,
+                    feature::MG_DOUBLED_PAWN,
+                    params.mg_doubled_pawn,
+                    doubled,
+                );
+                terms.add_eg(
+                    color,
+                    feature::EG_DOUBLED_PAWN,
+                    params.eg_doubled_pawn,
+                    doubled,
+                );
+            }
+
+            for &row in file_pawns {
+                let relative_rank = match color {
+                    Color::White => 10 - row,
+                    Color::Black => row - 1,
+                };
+                let stop_row = match color {
+                    Color::White => row - 1,
+                    Color::Black => row + 1,
+                };
+                let adjacent_files = [col - 1, col + 1];
+
+                let is_isolated = adjacent_files
+                    .iter()
+                    .all(|&file| pawn_rows[own][file].is_empty());
+                let is_connected = adjacent_files.iter().any(|&file| {
+                    pawn_rows[own][file]
+                        .iter()
+                        .any(|&other| other == row || other == 2 * row - stop_row)
+                });
+                let blockers_ahead = pawn_rows[enemy][col]
+                    .iter()
+                    .any(|&other| is_ahead(other, row));
+                let sentries = adjacent_files
+                    .iter()
+                    .map(|&file| {
+                        pawn_rows[enemy][file]
+                            .iter()
+                            .filter(|&&other| is_ahead(other, row))
+                            .count()
+                    })
+                    .sum::<usize>();
+                let supporters = adjacent_files
+                    .iter()
+                    .map(|&file| {
+                        pawn_rows[own][file]
+                            .iter()
+                            .filter(|&&other| !is_ahead(other, row))
+                            .count()
+                    })
+                    .sum::<usize>();
+
+                if is_isolated {
+                    terms.add_mg(color, feature::MG_ISOLATED_PAWN, params.mg_isolated_pawn, 1);
+                    terms.add_eg(color, feature::EG_ISOLATED_PAWN, params.eg_isolated_pawn, 1);
+                } else if supporters == 0 {
+                    //Backward, nothing can come up to defend it and an enemy pawn guards the square in front
+                    let stop_attacked = adjacent_files.iter().any(|&file| {
+                        pawn_rows[enemy][file]
+                            .iter()
+                            .any(|&other| other as i32 == 2 * stop_row as i32 - row as i32)
+                    });
+                    if stop_attacked {
+                        terms.add_mg(color, feature::MG_BACKWARD_PAWN, params.mg_backward_pawn, 1);
+                        terms.add_eg(color, feature::EG_BACKWARD_PAWN, params.eg_backward_pawn, 1);
+                    }
+                }
+
+                if is_connected {
+                    terms.add_mg(
+                        color,
+                        feature::MG_CONNECTED_PAWN,
+                        params.mg_connected_pawn,
+                        1,
+                    );
+                    terms.add_eg(
+                        color,
+                        feature::EG_CONNECTED_PAWN,
+                        params.eg_connected_pawn,
+                        1,
+                    );
+                }
+
+                if blockers_ahead {
+                    continue;
+                }
+
+                let rank = relative_rank - 1;
+                if sentries == 0 {
+                    let stop_square = Position { row: stop_row, col };
+                    let blockaded = board
+                        .get_piece(stop_square)
+                        .is_some_and(|blocker| blocker.color != color);
+                    if blockaded {
+                        terms.add_mg(
+                            color,
+                            feature::MG_BLOCKADED_PASSED_PAWN + rank,
+                            params.mg_blockaded_passed_pawn[rank],
+                            1,
+                        );
+                        terms.add_eg(
+                            color,
+                            feature::EG_BLOCKADED_PASSED_PAWN + rank,
+                            params.eg_blockaded_passed_pawn[rank],
+                            1,
+                        );
+                    } else {
+                        terms.add_mg(
+                            color,
+                            feature::MG_PASSED_PAWN + rank,
+                            params.mg_passed_pawn[rank],
+                            1,
+                        );
+                        terms.add_eg(
+                            color,
+                            feature::EG_PASSED_PAWN + rank,
+                            params.eg_passed_pawn[rank],
+                            1,
+                        );
+                    }
+
+                    //Kings matter more the further up the board the pawn is
+                    if relative_rank > 4 {
+                        let enemy_distance = square_distance(king_positions[enemy], stop_square);
+                        let own_distance = square_distance(king_positions[own], stop_square);
+                        terms.add_eg(
+                            color,
+                            feature::EG_PASSED_PAWN_KING_DISTANCE,
+                            params.eg_passed_pawn_king_distance,
+                            (enemy_distance - own_distance) * (relative_rank as i32 - 4),
+                        );
+                    }
+                } else if supporters >= sentries {
+                    //Candidate passer, an open file in front and enough friends to push through the sentries
+                    terms.add_mg(
+                        color,
+                        feature::MG_CANDIDATE_PASSER + rank,
+                        params.mg_candidate_passer[rank],
+                        1,
+                    );
+                    terms.add_eg(
+                        color,
+                        feature::EG_CANDIDATE_PASSER + rank,
+                        params.eg_candidate_passer[rank],
+                        1,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/*
+ * Counts weighted attacks on the squares around each king and looks at the pawns and files in front of it.
+ * Only a middlegame score from white's point of view, king safety doesn't matter much once the queens are gone
+ */
+pub fn evaluate_king_safety(board: &BoardState) -> i32 {
+    let mut terms = Terms::default();
+    king_safety_terms(board, eval_params(), &mut terms);
+    white_minus_black(terms.scores).0
+}
+
+fn king_safety_terms(board: &BoardState, params: &EvalParams, terms: &mut Terms) {
+    for color in [Color::White, Color::Black] {
+        let own = color_index(color);
+        let mut king_pos_opt: Option<Position> = None;
+        for row in 2..10 {
+            for col in 2..10 {
+                if board.squares[row][col].piece
+                    == Some(Piece {
+                        piece_type: PieceType::King,
+                        color,
+                    })
+                {
+                    king_pos_opt = Some(Position { row, col });
+                }
+            }
+        }
+        let king_pos = match king_pos_opt {
+            Some(val) => val,
+            None => continue,
+        };
+        //Squares in front of the king are one row up for white and one row down for black
+        let forward = |row: usize, steps: usize| -> usize {
+            match color {
+                Color::White => row - steps,
+                Color::Black => row + steps,
+            }
+        };
+
+        //King zone is the king's square, the squares around it and two more squares towards the enemy
+        let mut king_zone = king_positions(king_pos);
+        king_zone.push(king_pos);
+        for col in king_pos.col - 1..=king_pos.col + 1 {
+            king_zone.push(Position {
+                row: forward(king_pos.row, 2),
+                col,
+            });
+        }
+        king_zone.retain(|pos| pos.is_valid_position());
+
+        let mut attack_units = 0;
+        let mut attackers = 0;
+        let mut zone_attacks_by_piece = [0; 4]; //Knight, bishop, rook, queen
+        for row in 2..10 {
+            for col in 2..10 {
+                let piece = match board.squares[row][col].piece {
+                    Some(piece) if piece.color != color => piece,
+                    _ => continue,
+                };
+                let index = match piece.piece_type {
+                    PieceType::Knight => 0,
+                    PieceType::Bishop => 1,
+                    PieceType::Rook => 2,
+                    PieceType::Queen => 3,
+                    _ => continue,
+                };
+                let zone_attacks = piece_attacks(board, piece, Position { row, col })
+                    .iter()
+                    .filter(|pos| king_zone.contains(pos))
+                    .count() as i32;
+                if zone_attacks > 0 {
+                    attackers += 1;
+                    attack_units += params.king_attack_weights[index] * zone_attacks;
+                    zone_attacks_by_piece[index] += zone_attacks;
+                }
+            }
+        }
+        //A lone attacker is rarely dangerous
+        if attackers >= 2 {
+            let last = params.king_danger.len() - 1;
+            let units = attack_units.clamp(0, last as i32) as usize;
+            terms.add_mg(
+                color,
+                feature::KING_DANGER + units,
+                params.king_danger[units],
+                -1,
+            );
+            //The weights only pick the entry, how steep the table is there stands in for their gradient
+            let slope = params.king_danger[min(units + 1, last)] - params.king_danger[units];
+            for (index, zone_attacks) in zone_attacks_by_piece.into_iter().enumerate() {
+                terms.add_mg_feature(
+                    color,
+                    feature::KING_ATTACK_WEIGHTS + index,
+                    -slope * zone_attacks,
+                );
+            }
+        }
+
+        //Pawn shield, storm and open files only make sense while the king is still at home
+        let relative_rank = match color {
+            Color::White => 10 - king_pos.row,
+            Color::Black => king_pos.row - 1,
+        };
+        if relative_rank > 2 {
+            continue;
+        }
+
+        for col in king_pos.col - 1..=king_pos.col + 1 {
+            if !(2..10).contains(&col) {
+                continue;
+            }
+            let mut own_pawn = false;
+            let mut enemy_pawn = false;
+            for row in 2..10 {
+                if let Some(piece) = board.squares[row][col].piece {
+                    if piece.piece_type == PieceType::Pawn {
+                        if piece.color == color {
+                            own_pawn = true;
+                        } else {
+                            enemy_pawn = true;
+                        }
+                    }
+                }
+            }
+
+            if !own_pawn && !enemy_pawn {
+                terms.add_mg(
+                    color,
+                    feature::MG_OPEN_KING_FILE,
+                    params.mg_open_king_file,
+                    1,
+                );
+            } else if !own_pawn {
+                terms.add_mg(
+                    color,
+                    feature::MG_SEMI_OPEN_KING_FILE,
+                    params.mg_semi_open_king_file,
+                    1,
+                );
+            }
+
+            for steps in 1..=3 {
+                let row = forward(king_pos.row, steps);
+                if !(2..10).contains(&row) {
+                    break;
+                }
+                if let Some(piece) = board.squares[row][col].piece {
+                    if piece.piece_type != PieceType::Pawn {
+                        continue;
                     }
-                    Color::Black => {
-                        black_mg +=
-                            get_mg_table(piece)[9 - row][col - START] + get_mg_piece_val(piece);
-                        black_eg +=
-                            get_eg_table(piece)[9 - row][col - START] + get_eg_piece_val(piece);
+                    if piece.color == color && steps <= params.mg_pawn_shield.len() {
+                        terms.add_mg(
+                            color,
+                            feature::MG_PAWN_SHIELD + steps - 1,
+                            params.mg_pawn_shield[steps - 1],
+                            1,
+                        );
+                    } else if piece.color != color {
+                        terms.add_mg(
+                            color,
+                            feature::MG_PAWN_STORM + steps - 1,
+                            params.mg_pawn_storm[steps - 1],
+                            1,
+                        );
                     }
                 }
             }
         }
     }
+}
 
-    let mg_score;
-    let eg_score;
+/*
+ * Scores mobility for knights, bishops, rooks and queens, not counting squares held by our own pieces or covered by enemy pawns,
+ * along with the bishop pair, rooks on open files and the seventh rank, and knight outposts.
+ * Returns the middlegame and endgame scores from white's point of view
+ */
+pub fn evaluate_piece_activity(board: &BoardState) -> (i32, i32) {
+    let mut terms = Terms::default();
+    piece_activity_terms(board, eval_params(), &mut terms);
+    white_minus_black(terms.scores)
+}
 
-    match board.active_color {
-        Color::White => {
-            mg_score = white_mg - black_mg;
-            eg_score = white_eg - black_eg;
+fn piece_activity_terms(board: &BoardState, params: &EvalParams, terms: &mut Terms) {
+    //Pawn attacks and pawn counts per file for each color
+    let mut pawn_attacks = [[[false; 12]; 12]; 2];
+    let mut pawns_on_file = [[0; 12]; 2];
+    for row in 2..10 {
+        for col in 2..10 {
+            if let Some(piece) = board.squares[row][col].piece {
+                if piece.piece_type == PieceType::Pawn {
+                    pawns_on_file[color_index(piece.color)][col] += 1;
+                    for pos in piece_attacks(board, piece, Position { row, col }) {
+                        pawn_attacks[color_index(piece.color)][pos.row][pos.col] = true;
+                    }
+                }
+            }
         }
-        Color::Black => {
-            mg_score = black_mg - white_mg;
-            eg_score = black_eg - white_eg;
+    }
+
+    for color in [Color::White, Color::Black] {
+        let own = color_index(color);
+        let enemy = color_index(color.opposite());
+        let mut bishops = 0;
+
+        for row in 2..10 {
+            for col in 2..10 {
+                let piece = match board.squares[row][col].piece {
+                    Some(piece) if piece.color == color => piece,
+                    _ => continue,
+                };
+                let pos = Position { row, col };
+                let index = match piece.piece_type {
+                    PieceType::Knight => 0,
+                    PieceType::Bishop => 1,
+                    PieceType::Rook => 2,
+                    PieceType::Queen => 3,
+                    _ => continue,
+                };
+
+                let mobility = piece_attacks(board, piece, pos)
+                    .iter()
+                    .filter(|target| {
+                        !pawn_attacks[enemy][target.row][target.col]
+                            && board.squares[target.row][target.col]
+                                .piece
+                                .map_or(true, |other| other.color != color)
+                    })
+                    .count() as i32;
+                let extra_mobility = mobility - AVERAGE_MOBILITY[index];
+                terms.add_mg(
+                    color,
+                    feature::MG_MOBILITY + index,
+                    params.mg_mobility[index],
+                    extra_mobility,
+                );
+                terms.add_eg(
+                    color,
+                    feature::EG_MOBILITY + index,
+                    params.eg_mobility[index],
+                    extra_mobility,
+                );
+
+                let relative_rank = match color {
+                    Color::White => 10 - row,
+                    Color::Black => row - 1,
+                };
+                match piece.piece_type {
+                    PieceType::Bishop => bishops += 1,
+                    PieceType::Rook => {
+                        if pawns_on_file[own][col] == 0 && pawns_on_file[enemy][col] == 0 {
+                            terms.add_mg(
+                                color,
+                                feature::MG_ROOK_OPEN_FILE,
+                                params.mg_rook_open_file,
+                                1,
+                            );
+                            terms.add_eg(
+                                color,
+                                feature::EG_ROOK_OPEN_FILE,
+                                params.eg_rook_open_file,
+                                1,
+                            );
+                        } else if pawns_on_file[own][col] == 0 {
+                            terms.add_mg(
+                                color,
+                                feature::MG_ROOK_SEMI_OPEN_FILE,
+                                params.mg_rook_semi_open_file,
+                                1,
+                            );
+                            terms.add_eg(
+                                color,
+                                feature::EG_ROOK_SEMI_OPEN_FILE,
+                                params.eg_rook_semi_open_file,
+                                1,
+                            );
+                        }
+
+                        if relative_rank == 7 && rook_on_seventh_has_targets(board, color, row) {
+                            terms.add_mg(
+                                color,
+                                feature::MG_ROOK_ON_SEVENTH,
+                                params.mg_rook_on_seventh,
+                                1,
+                            );
+                            terms.add_eg(
+                                color,
+                                feature::EG_ROOK_ON_SEVENTH,
+                                params.eg_rook_on_seventh,
+                                1,
+                            );
+                        }
+                    }
+                    PieceType::Knight => {
+                        //No enemy pawn on a neighbouring file can ever come up and chase it away
+                        let can_be_chased = [col - 1, col + 1].iter().any(|&file| {
+                            (2..10).any(|other_row| {
+                                let is_ahead = match color {
+                                    Color::White => other_row < row,
+                                    Color::Black => other_row > row,
+                                };
+                                is_ahead
+                                    && board.squares[other_row][file].piece
+                                        == Some(Piece {
+                                            piece_type: PieceType::Pawn,
+                                            color: color.opposite(),
+                                        })
+                            })
+                        });
+                        if (4..=6).contains(&relative_rank)
+                            && pawn_attacks[own][row][col]
+                            && !can_be_chased
+                        {
+                            terms.add_mg(
+                                color,
+                                feature::MG_KNIGHT_OUTPOST,
+                                params.mg_knight_outpost,
+                                1,
+                            );
+                            terms.add_eg(
+                                color,
+                                feature::EG_KNIGHT_OUTPOST,
+                                params.eg_knight_outpost,
+                                1,
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if bishops >= 2 {
+            terms.add_mg(color, feature::MG_BISHOP_PAIR, params.mg_bishop_pair, 1);
+            terms.add_eg(color, feature::EG_BISHOP_PAIR, params.eg_bishop_pair, 1);
         }
     }
+}
+
+/* A rook on the seventh is only worth something if the enemy king is stuck on the back rank or there are pawns to take */
+fn rook_on_seventh_has_targets(board: &BoardState, color: Color, row: usize) -> bool {
+    let back_row = match color {
+        Color::White => row - 1,
+        Color::Black => row + 1,
+    };
+    (2..10).any(|col| {
+        let enemy_pawn = board.squares[row][col].piece
+            == Some(Piece {
+                piece_type: PieceType::Pawn,
+                color: color.opposite(),
+            });
+        let enemy_king = board.squares[back_row][col].piece
+            == Some(Piece {
+                piece_type: PieceType::King,
+                color: color.opposite(),
+            });
+        enemy_pawn || enemy_king
+    })
+}
 
-    let mg_phase = if game_phase <= 24 { game_phase } else { 24 };
-    let eg_phase = 24 - mg_phase;
+/* Where every piece on the board is, split by color */
+struct Material {
+    pieces: [Vec<(PieceType, Position)>; 2],
+}
 
-    ((mg_score * mg_phase + eg_score * eg_phase) / 24)
+impl Material {
+    fn new(board: &BoardState) -> Material {
+        let mut pieces = [Vec::new(), Vec::new()];
+        for row in 2..10 {
+            for col in 2..10 {
+                if let Some(piece) = board.squares[row][col].piece {
+                    pieces[color_index(piece.color)]
+                        .push((piece.piece_type, Position { row, col }));
+                }
+            }
+        }
+        Material { pieces }
+    }
+
+    fn count(&self, color: Color, piece_type: PieceType) -> usize {
+        self.pieces[color_index(color)]
+            .iter()
+            .filter(|(other, _)| *other == piece_type)
+            .count()
+    }
+
+    fn find(&self, color: Color, piece_type: PieceType) -> Vec<Position> {
+        self.pieces[color_index(color)]
+            .iter()
+            .filter(|(other, _)| *other == piece_type)
+            .map(|(_, pos)| *pos)
+            .collect()
+    }
+
+    fn king(&self, color: Color) -> Position {
+        self.find(color, PieceType::King)[0]
+    }
+
+    fn is_bare_king(&self, color: Color) -> bool {
+        self.pieces[color_index(color)].len() == 1
+    }
+
+    //Knights, bishops, rooks and queens
+    fn non_pawn_material(&self, color: Color, params: &EvalParams) -> i32 {
+        self.pieces[color_index(color)]
+            .iter()
+            .filter(|(piece_type, _)| *piece_type != PieceType::Pawn)
+            .map(|(piece_type, _)| {
+                get_eg_piece_val(
+                    params,
+                    Piece {
+                        piece_type: *piece_type,
+                        color,
+                    },
+                )
+            })
+            .sum()
+    }
+
+    fn has_only(&self, color: Color, piece_types: &[PieceType]) -> bool {
+        self.pieces[color_index(color)]
+            .iter()
+            .all(|(piece_type, _)| {
+                *piece_type == PieceType::King || piece_types.contains(piece_type)
+            })
+    }
+}
+
+//How many squares away from the four centre squares
+fn centre_distance(pos: Position) -> i32 {
+    let row = pos.row as i32;
+    let col = pos.col as i32;
+    max(5 - row, row - 6) + max(5 - col, col - 6)
+}
+
+//Rows go down the board, so white pawns promote on row 2 and black pawns on row 9
+fn promotion_square(color: Color, col: usize) -> Position {
+    match color {
+        Color::White => Position { row: 2, col },
+        Color::Black => Position { row: 9, col },
+    }
+}
+
+fn relative_rank(color: Color, pos: Position) -> i32 {
+    match color {
+        Color::White => 10 - pos.row as i32,
+        Color::Black => pos.row as i32 - 1,
+    }
+}
+
+/*
+ * Looks the material up against the endgames we know how to play, returning a score from the side to move's point of view.
+ * None means no specialised evaluator applies and the normal evaluation should be used
+ */
+fn evaluate_endgame(board: &BoardState, material: &Material, params: &EvalParams) -> Option<i32> {
+    let mut terms = Terms::default();
+    if !endgame_terms(board, material, params, &mut terms) {
+        return None;
+    }
+    let (_, score) = white_minus_black(terms.scores);
+    Some(match board.active_color {
+        Color::White => score,
+        Color::Black => -score,
+    })
+}
+
+/* Adds the strong side's score for an endgame we know, as endgame terms. False and nothing added when none applies */
+fn endgame_terms(
+    board: &BoardState,
+    material: &Material,
+    params: &EvalParams,
+    terms: &mut Terms,
+) -> bool {
+    for strong in [Color::White, Color::Black] {
+        let weak = strong.opposite();
+        if !material.is_bare_king(weak) {
+            continue;
+        }
+
+        if material.has_only(strong, &[PieceType::Pawn])
+            && material.count(strong, PieceType::Pawn) == 1
+        {
+            match kpk_is_won(board, material, strong) {
+                Some(true) => kpk_terms(material, strong, params, terms),
+                Some(false) => {} //Drawn, nothing to add
+                None => return false,
+            }
+        } else if material.has_only(strong, &[PieceType::Bishop, PieceType::Knight])
+            && material.count(strong, PieceType::Bishop) == 1
+            && material.count(strong, PieceType::Knight) == 1
+        {
+            kbnk_terms(board, material, strong, params, terms);
+        } else if material.has_only(strong, &[PieceType::Bishop])
+            && !has_bishops_on_both_colors(board, material, strong)
+        {
+            //Bishops that all stand on one color mate no better than a single bishop
+        } else if material.count(strong, PieceType::Queen) > 0
+            || material.count(strong, PieceType::Rook) > 0
+            || (material.count(strong, PieceType::Bishop) > 0
+                && material.count(strong, PieceType::Knight) > 0)
+            || has_bishops_on_both_colors(board, material, strong)
+        {
+            kxk_terms(material, strong, params, terms);
+        } else {
+            return false;
+        }
+        return true;
+    }
+    false
+}
+
+fn has_bishops_on_both_colors(board: &BoardState, material: &Material, color: Color) -> bool {
+    let bishops = material.find(color, PieceType::Bishop);
+    bishops.iter().any(|bishop| {
+        board.squares[bishop.row][bishop.col].color
+            != board.squares[bishops[0].row][bishops[0].col].color
+    })
+}
+
+/* A known win plus everything but the king, the endgame piece values */
+fn won_material_terms(material: &Material, strong: Color, params: &EvalParams, terms: &mut Terms) {
+    terms.add_eg(strong, feature::KNOWN_WIN, params.known_win, 1);
+    for (piece_type, _) in &material.pieces[color_index(strong)] {
+        let piece = Piece {
+            piece_type: *piece_type,
+            color: strong,
+        };
+        if *piece_type != PieceType::King {
+            terms.add_eg(
+                strong,
+                feature::EG_PIECE_VALUES + piece_index(piece),
+                get_eg_piece_val(params, piece),
+                1,
+            );
+        }
+    }
+}
+
+/* Mating material against a bare king, drive the king to the edge and bring ours up to help */
+fn kxk_terms(material: &Material, strong: Color, params: &EvalParams, terms: &mut Terms) {
+    let strong_king = material.king(strong);
+    let weak_king = material.king(strong.opposite());
+
+    won_material_terms(material, strong, params, terms);
+    terms.add_eg(
+        strong,
+        feature::PUSH_TO_EDGE,
+        params.push_to_edge,
+        centre_distance(weak_king),
+    );
+    terms.add_eg(
+        strong,
+        feature::PUSH_KINGS_CLOSE,
+        params.push_kings_close,
+        7 - square_distance(strong_king, weak_king),
+    );
+}
+
+/*
+ * Bishop and knight can only mate in a corner the bishop covers, so push toward those instead of any edge.
+ * The lone king is scored by how far it is from the long diagonal joining the other two corners
+ */
+fn kbnk_terms(
+    board: &BoardState,
+    material: &Material,
+    strong: Color,
+    params: &EvalParams,
+    terms: &mut Terms,
+) {
+    let bishop = material.find(strong, PieceType::Bishop)[0];
+    let strong_king = material.king(strong);
+    let weak_king = material.king(strong.opposite());
+    let rank = 9 - weak_king.row as i32;
+    let file = weak_king.col as i32 - 2;
+    let corner_distance =
+        if board.squares[bishop.row][bishop.col].color == board.squares[9][2].color {
+            (7 - rank - file).abs()
+        } else {
+            (rank - file).abs()
+        };
+
+    won_material_terms(material, strong, params, terms);
+    terms.add_eg(
+        strong,
+        feature::PUSH_TO_CORNER,
+        params.push_to_corner,
+        corner_distance,
+    );
+    terms.add_eg(
+        strong,
+        feature::PUSH_KINGS_CLOSE,
+        params.push_kings_close,
+        7 - square_distance(strong_king, weak_king),
+    );
+}
+
+/* A won KPK is worth more the further the pawn has come */
+fn kpk_terms(material: &Material, strong: Color, params: &EvalParams, terms: &mut Terms) {
+    let pawn = material.find(strong, PieceType::Pawn)[0];
+    won_material_terms(material, strong, params, terms);
+    terms.add_eg(
+        strong,
+        feature::KPK_PAWN_RANK,
+        params.kpk_pawn_rank,
+        relative_rank(strong, pawn),
+    );
+}
+
+/*
+ * King and pawn against king, see https://www.chessprogramming.org/King_Pawn_versus_King_Endgame
+ * Wins when the pawn outruns the king or our king holds a key square, rook pawns are drawn once the king reaches the corner.
+ * None when the rules can't tell
+ */
+fn kpk_is_won(board: &BoardState, material: &Material, strong: Color) -> Option<bool> {
+    let weak = strong.opposite();
+    let pawn = material.find(strong, PieceType::Pawn)[0];
+    let strong_king = material.king(strong);
+    let weak_king = material.king(weak);
+    let queening_square = promotion_square(strong, pawn.col);
+    let pawn_rank = relative_rank(strong, pawn);
+
+    //Rule of the square, counting the double step and who moves first
+    let pawn_moves = 8 - pawn_rank - if pawn_rank == 2 { 1 } else { 0 };
+    let king_moves = if board.active_color == weak {
+        pawn_moves
+    } else {
+        pawn_moves - 1
+    };
+    let king_in_the_way =
+        strong_king.col == pawn.col && relative_rank(strong, strong_king) > pawn_rank;
+    if square_distance(weak_king, queening_square) > king_moves + 1 && !king_in_the_way {
+        return Some(true);
+    }
+
+    let is_rook_pawn = pawn.col == 2 || pawn.col == 9;
+    if is_rook_pawn {
+        if square_distance(weak_king, queening_square) <= 1 {
+            return Some(false);
+        }
+        return None;
+    }
+
+    //The pawn can be taken before our king gets to defend it
+    if board.active_color == weak
+        && square_distance(weak_king, pawn) == 1
+        && square_distance(strong_king, pawn) > 1
+    {
+        return None;
+    }
+
+    let key_ranks = match pawn_rank {
+        2..=4 => pawn_rank + 2..=pawn_rank + 2,
+        5 | 6 => pawn_rank + 1..=pawn_rank + 2,
+        _ => 7..=8,
+    };
+    let king_file_distance = (strong_king.col as i32 - pawn.col as i32).abs();
+    let on_key_square = key_ranks.contains(&relative_rank(strong, strong_king))
+        && king_file_distance <= 1
+        && !(pawn_rank == 7 && king_file_distance == 0);
+    if on_key_square {
+        return Some(true);
+    }
+    None
+}
+
+/*
+ * How much of the endgame score the strong side keeps out of SCALE_FACTOR_NORMAL, for material that is hard or impossible to win with.
+ * Opposite colored bishops, no pawns and not enough extra material to mate, or a rook pawn with a bishop that can't cover the queening square.
+ * Also returns the feature the scale factor came from, if it is one of the params
+ */
+fn scale_factor(
+    board: &BoardState,
+    material: &Material,
+    strong: Color,
+    params: &EvalParams,
+) -> (i32, Option<usize>) {
+    let weak = strong.opposite();
+    let strong_pawns = material.find(strong, PieceType::Pawn);
+
+    let bishop = get_eg_piece_val(
+        params,
+        Piece {
+            piece_type: PieceType::Bishop,
+            color: strong,
+        },
+    );
+    if strong_pawns.is_empty()
+        && material.non_pawn_material(strong, params) - material.non_pawn_material(weak, params)
+            <= bishop
+    {
+        return (
+            params.scale_factor_no_pawns,
+            Some(feature::SCALE_FACTOR_NO_PAWNS),
+        );
+    }
+
+    let strong_bishops = material.find(strong, PieceType::Bishop);
+    let only_bishop = material.has_only(strong, &[PieceType::Pawn, PieceType::Bishop])
+        && strong_bishops.len() == 1;
+    if only_bishop && !strong_pawns.is_empty() {
+        //Every pawn on the same rook file, and the bishop on the other color to the queening square
+        let col = strong_pawns[0].col;
+        let queening_square = promotion_square(strong, col);
+        let wrong_bishop = board.squares[strong_bishops[0].row][strong_bishops[0].col].color
+            != board.squares[queening_square.row][queening_square.col].color;
+        if (col == 2 || col == 9)
+            && strong_pawns.iter().all(|pawn| pawn.col == col)
+            && wrong_bishop
+            && square_distance(material.king(weak), queening_square) <= 1
+        {
+            return (SCALE_FACTOR_DRAW, None);
+        }
+    }
+
+    let weak_bishops = material.find(weak, PieceType::Bishop);
+    if only_bishop
+        && material.has_only(weak, &[PieceType::Pawn, PieceType::Bishop])
+        && weak_bishops.len() == 1
+        && board.squares[strong_bishops[0].row][strong_bishops[0].col].color
+            != board.squares[weak_bishops[0].row][weak_bishops[0].col].color
+    {
+        return (
+            params.scale_factor_opposite_bishops,
+            Some(feature::SCALE_FACTOR_OPPOSITE_BISHOPS),
+        );
+    }
+
+    (SCALE_FACTOR_NORMAL, None)
+}
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+/* Score from the side to move's point of view, with the loaded network if there is one and the tables otherwise */
+pub fn evaluate(board: &BoardState) -> i32 {
+    evaluate_with(board, eval_params())
+}
+
+/* Same as evaluate but always with the tables, whether or not a network is loaded */
+pub fn evaluate_pesto(board: &BoardState) -> i32 {
+    evaluate_pesto_with(board, eval_params())
+}
+
+/* evaluate with params other than the process wide ones, so engines in one process can evaluate differently */
+pub fn evaluate_with(board: &BoardState, params: &EvalParams) -> i32 {
+    let material = Material::new(board);
+    if let Some(score) = evaluate_endgame(board, &material, params) {
+        return score;
+    }
+
+    //A loaded network takes over from the tables, except for the endgames above it has no feel for
+    if let Some(accumulator) = &board.accumulator {
+        return accumulator.evaluate(board.active_color);
+    }
+
+    evaluate_tables(board, &material, params)
+}
+
+pub fn evaluate_pesto_with(board: &BoardState, params: &EvalParams) -> i32 {
+    let material = Material::new(board);
+    match evaluate_endgame(board, &material, params) {
+        Some(score) => score,
+        None => evaluate_tables(board, &material, params),
+    }
+}
+
+fn evaluate_tables(board: &BoardState, material: &Material, params: &EvalParams) -> i32 {
+    //Material and tables come from the board, updated as moves were made, but only for the process wide params
+    let sums = if ptr::eq(params, eval_params()) {
+        debug_assert_eq!(
+            board.piece_square,
+            PieceSquareSums::from_squares(&board.squares)
+        );
+        board.piece_square
+    } else {
+        PieceSquareSums::from_squares_with(&board.squares, params)
+    };
+    let mut terms = Terms::default();
+    pawn_structure_terms(board, params, &mut terms);
+    king_safety_terms(board, params, &mut terms);
+    piece_activity_terms(board, params, &mut terms);
+    let mut totals = terms.scores;
+    for side in 0..2 {
+        totals[side].0 += sums.mg[side];
+        totals[side].1 += sums.eg[side];
+    }
+
+    tapered(board, material, totals, min(sums.game_phase, 24), params).1
+}
+
+/* Blends the middlegame and scaled endgame totals by game phase, returns the scale factor used and the score for the side to move */
+fn tapered(
+    board: &BoardState,
+    material: &Material,
+    totals: SideScores,
+    game_phase: i32,
+    params: &EvalParams,
+) -> (i32, i32) {
+    let strong = if totals[0].1 >= totals[1].1 {
+        Color::White
+    } else {
+        Color::Black
+    };
+    let (scale, _) = scale_factor(board, material, strong, params);
+
+    let own = color_index(board.active_color);
+    let enemy = color_index(board.active_color.opposite());
+    let mg_score = totals[own].0 - totals[enemy].0;
+    let eg_score = (totals[own].1 - totals[enemy].1) * scale / SCALE_FACTOR_NORMAL;
+    (
+        scale,
+        (mg_score * game_phase + eg_score * (24 - game_phase)) / 24,
+    )
+}
+
+/* Every piece of the evaluation laid out for debugging, scores per side are from that side's point of view */
+#[derive(Clone, Copy, Debug)]
+pub struct EvalTrace {
+    pub material: SideScores,
+    pub piece_squares: SideScores, //Just the tables, material is counted above
+    pub pawn_structure: SideScores,
+    pub king_safety: SideScores,
+    pub piece_activity: SideScores,
+    pub totals: SideScores,
+    pub game_phase: i32,      //Out of 24, capped for promoted pieces
+    pub scale_factor: i32,    //Out of SCALE_FACTOR_NORMAL, applied to the endgame score
+    pub tables: i32, //What the terms above add up to, from the side to move's point of view
+    pub network: Option<i32>, //Score from the loaded network, which replaces the terms above
+    pub endgame: Option<i32>, //Score from a specialised endgame evaluator, which replaces everything else
+    pub side_to_move: Color,
+    pub score: i32, //The score evaluate actually returns, from the side to move's point of view
+}
+
+/* The same evaluation as evaluate, but keeping each term apart. Slow, it recomputes everything from the squares */
+pub fn evaluate_traced(board: &BoardState) -> EvalTrace {
+    let params = eval_params();
+    let material = Material::new(board);
+    let mut material_scores: SideScores = [(0, 0); 2];
+    let mut piece_squares: SideScores = [(0, 0); 2];
+    for row in 2..10 {
+        for col in 2..10 {
+            if let Some(piece) = board.squares[row][col].piece {
+                let side = color_index(piece.color);
+                let (mg, eg) = piece_square_values(params, piece, row, col);
+                material_scores[side].0 += get_mg_piece_val(params, piece);
+                material_scores[side].1 += get_eg_piece_val(params, piece);
+                piece_squares[side].0 += mg - get_mg_piece_val(params, piece);
+                piece_squares[side].1 += eg - get_eg_piece_val(params, piece);
+            }
+        }
+    }
+    let [pawn_structure, king_safety, piece_activity] = [
+        pawn_structure_terms,
+        king_safety_terms,
+        piece_activity_terms,
+    ]
+    .map(|add_terms| {
+        let mut terms = Terms::default();
+        add_terms(board, params, &mut terms);
+        terms.scores
+    });
+
+    let mut totals: SideScores = [(0, 0); 2];
+    for side in 0..2 {
+        for term in [
+            material_scores,
+            piece_squares,
+            pawn_structure,
+            king_safety,
+            piece_activity,
+        ] {
+            totals[side].0 += term[side].0;
+            totals[side].1 += term[side].1;
+        }
+    }
+
+    let game_phase = min(PieceSquareSums::from_squares(&board.squares).game_phase, 24);
+    let (scale, tables) = tapered(board, &material, totals, game_phase, params);
+
+    EvalTrace {
+        material: material_scores,
+        piece_squares,
+        pawn_structure,
+        king_safety,
+        piece_activity,
+        totals,
+        game_phase,
+        scale_factor: scale,
+        tables,
+        network: board
+            .accumulator
+            .map(|accumulator| accumulator.evaluate(board.active_color)),
+        endgame: evaluate_endgame(board, &material, params),
+        side_to_move: board.active_color,
+        score: evaluate(board),
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<16}|{:>9}{:>7} |{:>9}{:>7} |{:>9}{:>7}",
+            "Term", "White mg", "eg", "Black mg", "eg", "Net mg", "eg"
+        )?;
+        writeln!(f, "{}", "-".repeat(70))?;
+        for (name, scores) in [
+            ("Material", self.material),
+            ("Piece squares", self.piece_squares),
+            ("Pawn structure", self.pawn_structure),
+            ("King safety", self.king_safety),
+            ("Piece activity", self.piece_activity),
+            ("Total", self.totals),
+        ] {
+            let (net_mg, net_eg) = white_minus_black(scores);
+            writeln!(
+                f,
+                "{:<16}|{:>9}{:>7} |{:>9}{:>7} |{:>9}{:>7}",
+                name, scores[0].0, scores[0].1, scores[1].0, scores[1].1, net_mg, net_eg
+            )?;
+        }
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "Game phase: {}/24", self.game_phase)?;
+        writeln!(
+            f,
+            "Endgame scale factor: {}/{}",
+            self.scale_factor, SCALE_FACTOR_NORMAL
+        )?;
+        writeln!(f, "Table score: {}", self.tables)?;
+        if let Some(score) = self.network {
+            writeln!(f, "Network score: {}", score)?;
+        }
+        if let Some(score) = self.endgame {
+            writeln!(f, "Specialised endgame score: {}", score)?;
+        }
+        let side = match self.side_to_move {
+            Color::White => "white",
+            Color::Black => "black",
+        };
+        write!(f, "Score ({} to move): {}", side, self.score)
+    }
+}
+
+/*
+ * The evaluation from white's point of view as a function of the EvalParams values.
+ * Each phase is the fixed part plus the sum of value * count over its features, and the scale factor can be one of the values too.
+ * That is exact for every value but the king attack weights, which only pick an entry of king_danger,
+ * their features are the slope of the table there and the fixed part makes up the difference at the params it was made with
+ */
+pub struct LinearEval {
+    pub mg_features: Vec<(usize, i32)>, //Index into EvalParams::to_vec and how often it counts, negative for black
+    pub eg_features: Vec<(usize, i32)>,
+    pub fixed_mg: i32,
+    pub fixed_eg: i32,
+    pub game_phase: i32,
+    pub scale_factor: i32,
+    pub scale_feature: Option<usize>, //Index of the value the scale factor came from, which then replaces it
+}
+
+impl LinearEval {
+    //Evaluation with the given values instead of the ones in use, same blend as evaluate but without rounding
+    pub fn evaluate(&self, values: &[f64]) -> f64 {
+        let (mg, eg) = self.phase_scores(values);
+        (mg * self.game_phase as f64
+            + eg * self.scale(values) / SCALE_FACTOR_NORMAL as f64 * (24 - self.game_phase) as f64)
+            / 24.0
+    }
+
+    pub fn scale(&self, values: &[f64]) -> f64 {
+        self.scale_feature
+            .map_or(self.scale_factor as f64, |index| values[index])
+    }
+
+    pub fn phase_scores(&self, values: &[f64]) -> (f64, f64) {
+        let mg = self.fixed_mg as f64
+            + self
+                .mg_features
+                .iter()
+                .map(|&(index, count)| values[index] * count as f64)
+                .sum::<f64>();
+        let eg = self.fixed_eg as f64
+            + self
+                .eg_features
+                .iter()
+                .map(|&(index, count)| values[index] * count as f64)
+                .sum::<f64>();
+        (mg, eg)
+    }
+}
+
+/* Specialised endgame scores aren't tapered or scaled, they come out as all endgame at game phase 0 */
+pub fn linear_eval(board: &BoardState, params: &EvalParams) -> LinearEval {
+    let material = Material::new(board);
+    let mut terms = Terms::with_features();
+    let (game_phase, (scale_factor, scale_feature)) =
+        if endgame_terms(board, &material, params, &mut terms) {
+            (0, (SCALE_FACTOR_NORMAL, None))
+        } else {
+            for row in 2..10 {
+                for col in 2..10 {
+                    if let Some(piece) = board.squares[row][col].piece {
+                        let table_row = match piece.color {
+                            Color::White => row - 2,
+                            Color::Black => 9 - row,
+                        };
+                        let index = piece_index(piece);
+                        let table = feature::TABLES + index * 128 + table_row * 8 + col - 2;
+                        terms.add_mg(
+                            piece.color,
+                            feature::MG_PIECE_VALUES + index,
+                            get_mg_piece_val(params, piece),
+                            1,
+                        );
+                        terms.add_eg(
+                            piece.color,
+                            feature::EG_PIECE_VALUES + index,
+                            get_eg_piece_val(params, piece),
+                            1,
+                        );
+                        terms.add_mg(
+                            piece.color,
+                            table,
+                            get_mg_table(params, piece)[table_row][col - 2],
+                            1,
+                        );
+                        terms.add_eg(
+                            piece.color,
+                            table + 64,
+                            get_eg_table(params, piece)[table_row][col - 2],
+                            1,
+                        );
+                    }
+                }
+            }
+            pawn_structure_terms(board, params, &mut terms);
+            king_safety_terms(board, params, &mut terms);
+            piece_activity_terms(board, params, &mut terms);
+
+            let strong = if terms.scores[0].1 >= terms.scores[1].1 {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let sums = PieceSquareSums::from_squares_with(&board.squares, params);
+            (
+                min(sums.game_phase, 24),
+                scale_factor(board, &material, strong, params),
+            )
+        };
+
+    let (mg_features, eg_features) = terms.features.expect("terms keep features");
+    let values = params.to_vec();
+    let weighted = |features: &[(usize, i32)]| -> i32 {
+        features
+            .iter()
+            .map(|&(index, count)| values[index] * count)
+            .sum()
+    };
+    let (mg, eg) = white_minus_black(terms.scores);
+    LinearEval {
+        fixed_mg: mg - weighted(&mg_features),
+        fixed_eg: eg - weighted(&eg_features),
+        mg_features,
+        eg_features,
+        game_phase,
+        scale_factor,
+        scale_feature,
+    }
 }
 
 mod tests {
@@ -280,7 +2066,7 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(evaluate(&board), -32);
+        assert_eq!(evaluate(&board), -37);
     }
 
     #[test]
@@ -294,7 +2080,7 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(evaluate(&board), -35);
+        assert_eq!(evaluate(&board), -38);
     }
 
     #[test]
@@ -308,7 +2094,7 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(evaluate(&board), 2470);
+        assert_eq!(evaluate(&board), 2574);
     }
 
     #[test]
@@ -323,11 +2109,11 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(evaluate(&board), -410);
+        assert_eq!(evaluate(&board), -384);
     }
 
     #[test]
-    // -75
+    // -96
     fn test_random_pos() {
         let board_state_fen = "rnb1kbnr/pppp1ppp/8/4p1q1/3PP3/2N5/PPP2PPP/R1BQKBNR b KQkq - 0 1";
         let board_state: Result<BoardState, &str> = BoardState::new(board_state_fen);
@@ -338,6 +2124,426 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(evaluate(&board), -75);
+        assert_eq!(evaluate(&board), -96);
+    }
+
+    fn pawn_structure(fen: &str) -> (i32, i32) {
+        evaluate_pawn_structure(&BoardState::new(fen).unwrap())
+    }
+
+    #[test]
+    fn test_symmetric_pawns() {
+        assert_eq!(
+            pawn_structure("4k3/pp3ppp/2p5/3p4/3P4/2P5/PP3PPP/4K3 w - - 0 1"),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_doubled_and_isolated_pawns() {
+        //Same pawns, but doubled and isolated on the c file instead of side by side
+        let (healthy_mg, healthy_eg) = pawn_structure("4k3/8/8/8/8/8/2PP4/4K3 w - - 0 1");
+        let (doubled_mg, doubled_eg) = pawn_structure("4k3/8/8/8/8/2P5/2P5/4K3 w - - 0 1");
+        assert!(doubled_mg < healthy_mg);
+        assert!(doubled_eg < healthy_eg);
+
+        //An isolated pawn against one with a neighbour
+        let (_, isolated_eg) = pawn_structure("4k3/pp6/8/8/8/8/P5P1/4K3 w - - 0 1");
+        let (_, supported_eg) = pawn_structure("4k3/pp6/8/8/8/8/PP6/4K3 w - - 0 1");
+        assert!(isolated_eg < supported_eg);
+    }
+
+    #[test]
+    fn test_backward_pawn() {
+        //d3 can't be defended and e5 stops it from advancing, from e6 it doesn't
+        let (backward_mg, backward_eg) = pawn_structure("4k3/8/8/4p3/2P5/3P4/8/4K3 w - - 0 1");
+        let (normal_mg, normal_eg) = pawn_structure("4k3/8/4p3/8/2P5/3P4/8/4K3 w - - 0 1");
+        assert_eq!(backward_mg - normal_mg, MG_BACKWARD_PAWN);
+        assert_eq!(backward_eg - normal_eg, EG_BACKWARD_PAWN);
+    }
+
+    #[test]
+    fn test_passed_pawns() {
+        //Further up the board is worth more
+        let ranks: Vec<i32> = [
+            "4k3/8/8/8/8/8/P7/4K3 w - - 0 1",
+            "4k3/8/8/8/P7/8/8/4K3 w - - 0 1",
+            "4k3/8/P7/8/8/8/8/4K3 w - - 0 1",
+            "4k3/P7/8/8/8/8/8/4K3 w - - 0 1",
+        ]
+        .iter()
+        .map(|fen| pawn_structure(fen).1)
+        .collect();
+        assert!(ranks.windows(2).all(|pair| pair[0] < pair[1]));
+
+        //Blockaded by a knight
+        let (_, free_eg) = pawn_structure("4k3/8/8/P7/8/8/8/4K3 w - - 0 1");
+        let (_, blocked_eg) = pawn_structure("4k3/8/n7/P7/8/8/8/4K3 w - - 0 1");
+        assert!(blocked_eg < free_eg);
+
+        //Our king escorting the pawn beats the enemy king catching it
+        let (_, escorted_eg) = pawn_structure("7k/8/1K6/P7/8/8/8/8 w - - 0 1");
+        let (_, caught_eg) = pawn_structure("8/8/1k6/P7/8/8/8/7K w - - 0 1");
+        assert!(caught_eg < escorted_eg);
+    }
+
+    #[test]
+    fn test_candidate_passer() {
+        //a2 has an open file and b2 to help it past b7, b7 is isolated and blocked
+        assert_eq!(
+            pawn_structure("4k3/1p6/8/8/8/8/PP6/4K3 w - - 0 1"),
+            (
+                2 * MG_CONNECTED_PAWN + MG_CANDIDATE_PASSER[1] - MG_ISOLATED_PAWN,
+                2 * EG_CONNECTED_PAWN + EG_CANDIDATE_PASSER[1] - EG_ISOLATED_PAWN
+            )
+        );
+    }
+
+    fn king_safety(fen: &str) -> i32 {
+        evaluate_king_safety(&BoardState::new(fen).unwrap())
+    }
+
+    #[test]
+    fn test_pawn_shield() {
+        let castled = "6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1";
+        assert_eq!(king_safety(castled), 0);
+
+        //Pushing the shield pawns up a square and then off the file
+        let pushed = king_safety("6k1/5ppp/8/8/8/6P1/5P1P/6K1 w - - 0 1");
+        let missing = king_safety("6k1/5ppp/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert_eq!(pushed, MG_PAWN_SHIELD[1] - MG_PAWN_SHIELD[0]);
+        assert_eq!(missing, MG_SEMI_OPEN_KING_FILE - MG_PAWN_SHIELD[0]);
+
+        //No pawns at all on the g file
+        let open = king_safety("6k1/5p1p/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert_eq!(open, 0);
+        let open = king_safety("6k1/5ppp/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert_eq!(open, MG_SEMI_OPEN_KING_FILE - MG_PAWN_SHIELD[0]);
+    }
+
+    #[test]
+    fn test_pawn_storm() {
+        let storm = king_safety("6k1/5p1p/8/8/8/6p1/5P1P/6K1 w - - 0 1");
+        //g3 is two squares in front of the white king and closes the file for black
+        assert_eq!(storm, MG_PAWN_STORM[1] + MG_SEMI_OPEN_KING_FILE);
+    }
+
+    #[test]
+    fn test_king_attackers() {
+        //Queen and knight both hitting the squares around the castled white king
+        let attacked = king_safety("6k1/5ppp/8/8/8/5n2/5PPq/6K1 w - - 0 1");
+        let one_attacker = king_safety("6k1/5ppp/8/8/8/8/5PPq/6K1 w - - 0 1");
+        assert!(attacked < one_attacker);
+        assert!(attacked < -KING_DANGER[5]);
+    }
+
+    fn activity(fen: &str) -> (i32, i32) {
+        evaluate_piece_activity(&BoardState::new(fen).unwrap())
+    }
+
+    #[test]
+    fn test_symmetric_activity() {
+        assert_eq!(
+            activity("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_mobility() {
+        let (corner_mg, corner_eg) = activity("4k3/8/8/8/8/8/8/N3K3 w - - 0 1");
+        let (center_mg, center_eg) = activity("4k3/8/8/8/3N4/8/8/4K3 w - - 0 1");
+        assert_eq!(center_mg - corner_mg, 6 * MG_MOBILITY[0]);
+        assert_eq!(center_eg - corner_eg, 6 * EG_MOBILITY[0]);
+
+        //d7 covers c6 and e6, h7 covers nothing the knight can reach
+        let (covered_mg, _) = activity("4k3/3p4/8/8/3N4/8/8/4K3 w - - 0 1");
+        let (free_mg, _) = activity("4k3/7p/8/8/3N4/8/8/4K3 w - - 0 1");
+        assert_eq!(free_mg - covered_mg, 2 * MG_MOBILITY[0]);
+    }
+
+    #[test]
+    fn test_bishop_pair() {
+        let (pair_mg, pair_eg) = activity("2b1kb2/8/8/8/8/8/8/2B1KB2 w - - 0 1");
+        assert_eq!((pair_mg, pair_eg), (0, 0));
+        let (single_mg, single_eg) = activity("2n1kb2/8/8/8/8/8/8/2B1KB2 w - - 0 1");
+        assert!(single_mg >= MG_BISHOP_PAIR);
+        assert!(single_eg >= EG_BISHOP_PAIR);
+    }
+
+    #[test]
+    fn test_rook_files() {
+        //Both rooks see nine squares, a7 only closes the file for black
+        let (open_mg, open_eg) = activity("k7/1p6/8/8/8/8/8/R3K3 w - - 0 1");
+        let (semi_open_mg, semi_open_eg) = activity("k7/p7/8/8/8/8/8/R3K3 w - - 0 1");
+        assert_eq!(
+            open_mg - semi_open_mg,
+            MG_ROOK_OPEN_FILE - MG_ROOK_SEMI_OPEN_FILE
+        );
+        assert_eq!(
+            open_eg - semi_open_eg,
+            EG_ROOK_OPEN_FILE - EG_ROOK_SEMI_OPEN_FILE
+        );
+    }
+
+    #[test]
+    fn test_rook_on_seventh() {
+        let (seventh_mg, seventh_eg) = activity("4k3/R7/8/8/8/8/8/4K3 w - - 0 1");
+        let (sixth_mg, sixth_eg) = activity("4k3/8/R7/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(seventh_mg - sixth_mg, MG_ROOK_ON_SEVENTH);
+        assert_eq!(seventh_eg - sixth_eg, EG_ROOK_ON_SEVENTH);
+    }
+
+    #[test]
+    fn test_knight_outpost() {
+        //c6 covers none of the knight's targets but can still kick it with ...c5
+        let (outpost_mg, outpost_eg) = activity("4k3/8/8/3N4/4P3/8/8/4K3 w - - 0 1");
+        let (chased_mg, chased_eg) = activity("4k3/8/2p5/3N4/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(outpost_mg - chased_mg, MG_KNIGHT_OUTPOST);
+        assert_eq!(outpost_eg - chased_eg, EG_KNIGHT_OUTPOST);
+    }
+
+    fn endgame(fen: &str) -> Option<i32> {
+        let board = BoardState::new(fen).unwrap();
+        evaluate_endgame(&board, &Material::new(&board), eval_params())
+    }
+
+    #[test]
+    fn test_kxk_pushes_king_to_edge() {
+        let centre = endgame("8/8/8/3k4/8/8/8/R3K3 w - - 0 1").unwrap();
+        let edge = endgame("3k4/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        assert!(centre > KNOWN_WIN);
+        assert!(edge > centre);
+
+        //Same position from the losing side
+        assert_eq!(endgame("3k4/8/8/8/8/8/8/R3K3 b - - 0 1"), Some(-edge));
+
+        //Two knights can't force mate
+        assert_eq!(endgame("3k4/8/8/8/8/8/8/1N2KN2 w - - 0 1"), None);
+
+        //Two bishops only mate from opposite colors
+        assert!(endgame("3k4/8/8/8/8/8/8/2B1KB2 w - - 0 1").unwrap() > KNOWN_WIN);
+        assert_eq!(endgame("3k4/8/8/8/8/8/8/2B1K1B1 w - - 0 1"), Some(0));
+        assert_eq!(endgame("3k4/8/8/8/8/8/8/2B1K1B1 b - - 0 1"), Some(0));
+    }
+
+    #[test]
+    fn test_kbnk_prefers_bishop_corner() {
+        //Dark squared bishop, a1 and h8 are the mating corners
+        let right_corner = endgame("8/8/8/8/8/4BNK1/8/k7 w - - 0 1").unwrap();
+        let wrong_corner = endgame("k7/8/8/8/8/4BNK1/8/8 w - - 0 1").unwrap();
+        assert!(right_corner > wrong_corner);
+        assert!(wrong_corner > KNOWN_WIN);
+    }
+
+    #[test]
+    fn test_kpk() {
+        //Black's king is outside the square of the pawn
+        assert!(endgame("8/7k/8/8/P7/8/8/4K3 w - - 0 1").unwrap() > KNOWN_WIN);
+        assert_eq!(endgame("8/8/8/4k3/P7/8/8/4K3 b - - 0 1"), None);
+
+        //White's king on a key square wins no matter who moves
+        assert!(endgame("8/8/3k4/8/3K4/8/3P4/8 b - - 0 1").unwrap() < -KNOWN_WIN);
+        assert!(endgame("8/8/3k4/8/3K4/8/3P4/8 w - - 0 1").unwrap() > KNOWN_WIN);
+
+        //Black's king reaching the corner in front of a rook pawn holds the draw
+        assert_eq!(endgame("1k6/8/8/8/8/P7/8/K7 w - - 0 1"), Some(0));
+    }
+
+    #[test]
+    fn test_drawish_scaling() {
+        //A rook pawn with a bishop that can't cover a8
+        let board = BoardState::new("k7/8/8/8/8/P7/8/2K1B3 w - - 0 1").unwrap();
+        let material = Material::new(&board);
+        assert_eq!(
+            scale_factor(&board, &material, Color::White, eval_params()).0,
+            SCALE_FACTOR_DRAW
+        );
+        let board = BoardState::new("k7/8/8/8/8/P7/8/2K2B2 w - - 0 1").unwrap();
+        let material = Material::new(&board);
+        assert_eq!(
+            scale_factor(&board, &material, Color::White, eval_params()).0,
+            SCALE_FACTOR_NORMAL
+        );
+
+        let board = BoardState::new("4k3/5p2/2b5/8/8/4B3/5PP1/4K3 w - - 0 1").unwrap();
+        let material = Material::new(&board);
+        assert_eq!(
+            scale_factor(&board, &material, Color::White, eval_params()).0,
+            SCALE_FACTOR_OPPOSITE_BISHOPS
+        );
+
+        //A rook against a bishop with no pawns
+        let board = BoardState::new("4k3/8/2b5/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let material = Material::new(&board);
+        assert_eq!(
+            scale_factor(&board, &material, Color::White, eval_params()).0,
+            SCALE_FACTOR_NO_PAWNS
+        );
+    }
+
+    #[test]
+    fn test_incremental_piece_square_sums() {
+        use crate::move_gen::gen_all_moves;
+
+        //Castles, en passant, promotions and captures, two plies deep
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/pppppppp/8/4P3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2",
+        ] {
+            let board = BoardState::new(fen).unwrap();
+            for mv in gen_all_moves(&board, board.active_color) {
+                let mut board_after = board.clone();
+                board_after.make_move(&mv);
+                assert_eq!(
+                    board_after.piece_square,
+                    PieceSquareSums::from_squares(&board_after.squares)
+                );
+
+                for reply in gen_all_moves(&board_after, board_after.active_color) {
+                    let mut board_reply = board_after.clone();
+                    board_reply.make_move(&reply);
+                    assert_eq!(
+                        board_reply.piece_square,
+                        PieceSquareSums::from_squares(&board_reply.squares)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_traced_matches_evaluate() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1kq1b2/1p2b3/3p4/2n1p1p1/8/7r/N4P2/KR6 b KQkq - 0 1",
+            "r2qkb1r/1p1n1pp1/p2p1n2/3bp2p/4P3/1N2BP2/PPPQ2PP/R3KB1R w KQkq - 0 1",
+            "4k3/5p2/2b5/8/8/4B3/5PP1/4K3 w - - 0 1",
+            "8/8/3k4/8/8/2BNK3/8/8 b - - 0 1",
+        ] {
+            let board = BoardState::new(fen).unwrap();
+            let trace = evaluate_traced(&board);
+            assert_eq!(trace.score, evaluate(&board));
+
+            let (pawn_mg, pawn_eg) = evaluate_pawn_structure(&board);
+            assert_eq!(white_minus_black(trace.pawn_structure), (pawn_mg, pawn_eg));
+            assert_eq!(
+                white_minus_black(trace.king_safety),
+                (evaluate_king_safety(&board), 0)
+            );
+        }
+
+        let trace = evaluate_traced(
+            &BoardState::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap(),
+        );
+        assert_eq!(trace.material[0], trace.material[1]);
+        assert_eq!(trace.game_phase, 24);
+        assert!(trace.to_string().contains("Score (white to move): 0"));
+    }
+
+    #[test]
+    fn test_eval_params_round_trip() {
+        let params = EvalParams::default();
+        assert_eq!(EvalParams::from_toml(&params.to_toml()), Ok(params.clone()));
+
+        //Anything left out of the file keeps its default
+        let partial =
+            EvalParams::from_toml("mg_piece_values = [100, 300, 300, 500, 900, 0]").unwrap();
+        assert_eq!(partial.mg_piece_values, [100, 300, 300, 500, 900, 0]);
+        assert_eq!(partial.eg_piece_values, params.eg_piece_values);
+        assert_eq!(partial.mg_king_table, MG_KING_TABLE);
+
+        assert!(EvalParams::from_toml("mg_piece_values = [1, 2]").is_err());
+        assert!(EvalParams::from_toml("mg_pawn_tabel = []").is_err());
+    }
+
+    #[test]
+    fn test_linear_eval_matches_evaluate() {
+        let values: Vec<f64> = EvalParams::default()
+            .to_vec()
+            .iter()
+            .map(|&value| value as f64)
+            .collect();
+        assert_eq!(
+            EvalParams::from_slice(&EvalParams::default().to_vec()),
+            EvalParams::default()
+        );
+
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1kq1b2/1p2b3/3p4/2n1p1p1/8/7r/N4P2/KR6 b KQkq - 0 1",
+            "r2qkb1r/1p1n1pp1/p2p1n2/3bp2p/4P3/1N2BP2/PPPQ2PP/R3KB1R w KQkq - 0 1",
+            "4k3/5p2/2b5/8/8/4B3/5PP1/4K3 b - - 0 1",
+        ] {
+            let board = BoardState::new(fen).unwrap();
+            let white_score = match board.active_color {
+                Color::White => evaluate(&board),
+                Color::Black => -evaluate(&board),
+            };
+            let linear = linear_eval(&board, eval_params()).evaluate(&values);
+            //evaluate rounds twice along the way
+            assert!((linear - white_score as f64).abs() <= 2.0);
+        }
+
+        //Specialised endgames are features too, all of them endgame
+        let board = BoardState::new("8/8/3k4/8/8/2BNK3/8/8 b - - 0 1").unwrap();
+        let linear = linear_eval(&board, eval_params());
+        assert_eq!(linear.evaluate(&values), -evaluate(&board) as f64);
+        assert!(linear.eg_features.contains(&(feature::KNOWN_WIN, 1)));
+    }
+
+    #[test]
+    fn test_features_follow_the_params() {
+        let indices: Vec<i32> = (0..NUM_EVAL_PARAMS as i32).collect();
+        let params = EvalParams::from_slice(&indices);
+        assert_eq!(params.to_vec(), indices);
+        assert_eq!(params.mg_doubled_pawn, feature::MG_DOUBLED_PAWN as i32);
+        assert_eq!(params.king_danger[0], feature::KING_DANGER as i32);
+        assert_eq!(params.mg_mobility[0], feature::MG_MOBILITY as i32);
+        assert_eq!(params.known_win, feature::KNOWN_WIN as i32);
+        assert_eq!(
+            params.scale_factor_no_pawns,
+            feature::SCALE_FACTOR_NO_PAWNS as i32
+        );
+
+        //Features made with the defaults still give the evaluation once every value has moved,
+        //apart from the king attack weights whose features only hold near where they were made
+        let changed: Vec<i32> = EvalParams::default()
+            .to_vec()
+            .iter()
+            .enumerate()
+            .map(|(index, &value)| match index {
+                feature::KING_ATTACK_WEIGHTS..feature::KING_DANGER => value,
+                _ => value + (index * 7 % 11) as i32 - 5,
+            })
+            .collect();
+        let changed_params = EvalParams::from_slice(&changed);
+        let values: Vec<f64> = changed.iter().map(|&value| value as f64).collect();
+        for fen in [
+            "r1kq1b2/1p2b3/3p4/2n1p1p1/8/7r/N4P2/KR6 b KQkq - 0 1",
+            "r2qkb1r/1p1n1pp1/p2p1n2/3bp2p/4P3/1N2BP2/PPPQ2PP/R3KB1R w KQkq - 0 1",
+            "6k1/5ppp/8/8/8/5n2/5PPq/6K1 w - - 0 1",
+            "4k3/R7/2p5/3N4/4P3/8/1P6/4K3 w - - 0 1",
+            "4k3/5p2/2b5/8/8/4B3/5PP1/4K3 b - - 0 1",
+            "4k3/8/2b5/8/8/8/8/R3K3 w - - 0 1",
+            "8/8/3k4/8/8/2BNK3/8/8 b - - 0 1",
+            "8/8/3k4/8/3K4/8/3P4/8 w - - 0 1",
+        ] {
+            let board = BoardState::new(fen).unwrap();
+            let score = evaluate_pesto_with(&board, &changed_params);
+            let white_score = match board.active_color {
+                Color::White => score,
+                Color::Black => -score,
+            };
+            let linear = linear_eval(&board, eval_params()).evaluate(&values);
+            assert!(
+                (linear - white_score as f64).abs() <= 2.0,
+                "{}: {} against {}",
+                fen,
+                linear,
+                white_score
+            );
+        }
     }
 }
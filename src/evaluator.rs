@@ -0,0 +1,158 @@
+use crate::board_state::BoardState;
+use crate::evaluation::{evaluate, evaluate_pesto, evaluate_pesto_with, evaluate_with, EvalParams};
+use crate::piece::PieceType;
+use clap::ValueEnum;
+use std::sync::Arc;
+
+/*
+    Evaluations the search can be run with, so they can be played against each other without touching the search.
+    The search tells an evaluator about every move it makes and takes back on the line it is searching,
+    evaluators that keep their own incremental state follow along with push and pop.
+    A loaded network needs neither, its accumulator lives on the board and make_move keeps it up to date.
+*/
+
+const MATERIAL_VALUES: [i32; 6] = [100, 320, 330, 500, 900, 0]; //Pawn, knight, bishop, rook, queen, king
+
+pub trait Evaluator {
+    /* Score from the side to move's point of view */
+    fn evaluate(&mut self, board: &BoardState) -> i32;
+
+    /* A move was made on the line being searched, going from before to after */
+    fn push(&mut self, _before: &BoardState, _after: &BoardState) {}
+
+    /* The last move pushed was taken back */
+    fn pop(&mut self) {}
+}
+
+/* Picks an evaluator by name, on the command line or in SearchOptions */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum EvaluatorKind {
+    /// The network if one is loaded, PeSTO otherwise
+    #[default]
+    Default,
+    /// Tapered piece square tables plus pawn structure, king safety and piece activity
+    Pesto,
+    /// Counts material and nothing else
+    Material,
+}
+
+/*
+ * Whatever evaluation::evaluate does, the network on the board if there is one and the tables otherwise.
+ * Tables come from params when they are given and from the process wide ones otherwise
+ */
+#[derive(Clone, Default)]
+pub struct DefaultEvaluator {
+    pub params: Option<Arc<EvalParams>>,
+}
+
+impl Evaluator for DefaultEvaluator {
+    fn evaluate(&mut self, board: &BoardState) -> i32 {
+        match &self.params {
+            Some(params) => evaluate_with(board, params),
+            None => evaluate(board),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct PestoEvaluator {
+    pub params: Option<Arc<EvalParams>>,
+}
+
+impl Evaluator for PestoEvaluator {
+    fn evaluate(&mut self, board: &BoardState) -> i32 {
+        match &self.params {
+            Some(params) => evaluate_pesto_with(board, params),
+            None => evaluate_pesto(board),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct MaterialEvaluator;
+
+impl Evaluator for MaterialEvaluator {
+    fn evaluate(&mut self, board: &BoardState) -> i32 {
+        let mut score = 0;
+        for row in 2..10 {
+            for col in 2..10 {
+                if let Some(piece) = board.squares[row][col].piece {
+                    let value = match piece.piece_type {
+                        PieceType::Pawn => MATERIAL_VALUES[0],
+                        PieceType::Knight => MATERIAL_VALUES[1],
+                        PieceType::Bishop => MATERIAL_VALUES[2],
+                        PieceType::Rook => MATERIAL_VALUES[3],
+                        PieceType::Queen => MATERIAL_VALUES[4],
+                        PieceType::King => MATERIAL_VALUES[5],
+                        PieceType::None => panic!("something went wrong"),
+                    };
+                    if piece.color == board.active_color {
+                        score += value;
+                    } else {
+                        score -= value;
+                    }
+                }
+            }
+        }
+        score
+    }
+}
+
+/* For searchers that aren't generic over their evaluator, params only matter to the ones with tables */
+pub fn make_evaluator(kind: EvaluatorKind, params: Option<Arc<EvalParams>>) -> Box<dyn Evaluator> {
+    match kind {
+        EvaluatorKind::Default => Box::new(DefaultEvaluator { params }),
+        EvaluatorKind::Pesto => Box::new(PestoEvaluator { params }),
+        EvaluatorKind::Material => Box::new(MaterialEvaluator),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn material_counts_from_side_to_move() {
+        let mut evaluator = MaterialEvaluator;
+        let board =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        assert_eq!(evaluator.evaluate(&board), 0);
+
+        //White is a queen and a pawn up
+        let board = BoardState::new("4k3/8/8/8/8/8/4P3/3QK3 w - - 0 1").unwrap();
+        assert_eq!(evaluator.evaluate(&board), 1000);
+        let board = BoardState::new("4k3/8/8/8/8/8/4P3/3QK3 b - - 0 1").unwrap();
+        assert_eq!(evaluator.evaluate(&board), -1000);
+    }
+
+    #[test]
+    fn default_evaluator_matches_evaluate() {
+        let board =
+            BoardState::new("r2qkb1r/1p1n1pp1/p2p1n2/3bp2p/4P3/1N2BP2/PPPQ2PP/R3KB1R w KQkq - 0 1")
+                .unwrap();
+        assert_eq!(
+            DefaultEvaluator::default().evaluate(&board),
+            evaluate(&board)
+        );
+        assert_eq!(PestoEvaluator::default().evaluate(&board), -384);
+    }
+
+    #[test]
+    fn evaluates_with_the_params_it_is_given() {
+        let board = BoardState::new("4k3/4p3/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        let mut params = EvalParams::default();
+        assert_eq!(
+            PestoEvaluator {
+                params: Some(Arc::new(params.clone()))
+            }
+            .evaluate(&board),
+            evaluate_pesto(&board)
+        );
+
+        //The board's own sums were made with the process wide params and still count the queen
+        params.mg_piece_values[4] = 0;
+        params.eg_piece_values[4] = 0;
+        let mut evaluator = make_evaluator(EvaluatorKind::Pesto, Some(Arc::new(params)));
+        assert!(evaluator.evaluate(&board) < evaluate_pesto(&board) - 500);
+    }
+}
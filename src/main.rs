@@ -1,25 +1,68 @@
 #![allow(warnings)]
 
+mod bench;
 mod board_state;
 mod chess_move;
 mod color;
 mod engine;
+mod epd;
 mod evaluation;
+mod evaluator;
+mod mcts;
 mod move_gen;
 mod move_parser;
+mod nnue;
+mod perft;
+mod pgn;
 mod piece;
+mod pn_search;
+mod problem;
+mod san;
+mod searcher;
+mod self_play;
 mod square;
+mod syzygy;
+mod tournament;
+mod transposition_table;
+mod tuner;
+mod uci;
+mod zobrist;
 
+use crate::bench::{bench, BENCH_POSITIONS, DEFAULT_BENCH_DEPTH};
 use crate::color::Color;
-use crate::engine::calculate_best_move;
+use crate::engine::{SearchOptions, SkillLevel, MAX_DEPTH, MAX_SKILL_LEVEL};
+use crate::epd::{parse_epd, run_position};
+use crate::evaluation::{eval_params, evaluate_traced, set_eval_params, EvalParams};
+use crate::evaluator::{make_evaluator, EvaluatorKind};
+use crate::mcts::{Mcts, MctsOptions, Selection};
 use crate::move_gen::gen_all_moves;
 use crate::move_parser::parse_move;
+use crate::nnue::{set_network, Network};
+use crate::perft::{parse_suite, Perft, PerftOptions, DEFAULT_PERFT_HASH_MB};
+use crate::pn_search::{prove, Goal, Outcome, PnOptions, ProofTree};
+use crate::problem::{solve, Stipulation};
+use crate::searcher::{make_searcher, pv_to_string, SearchLimits, Searcher, SearcherKind};
+use crate::self_play::{
+    parse_openings, run_match, EngineConfig, MatchSettings, Sprt, SprtVerdict, DEFAULT_OPENINGS,
+};
+use crate::syzygy::Tablebases;
+use crate::tournament::{
+    run_tournament, Adjudication, DrawRule, EngineSpec, MateProofRule, ResignRule,
+    TournamentSettings,
+};
+use crate::tuner::{parse_dataset, tune};
+use crate::uci::run_uci;
 use board_state::BoardState;
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
 use log::{error, info};
 use move_parser::validate_move;
 use simple_logger::SimpleLogger;
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const DEFAULT_BOARD_STATE: &'static str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - - -";
 
@@ -38,19 +81,496 @@ struct Args {
     /// Enables the engine mode
     #[arg(long, action = ArgAction::SetTrue)]
     engine_mode: bool,
+
+    /// Centipawns the engine gives up to avoid a draw, use a positive value against weaker opponents
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    contempt: i32,
+
+    /// Skill level from 0 to 20, lower levels think less and make more mistakes. 20 is full strength
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=MAX_SKILL_LEVEL as i64))]
+    skill_level: Option<u8>,
+
+    /// Limits the engine to roughly this Elo rating instead of picking a skill level
+    #[arg(long, conflicts_with = "skill_level")]
+    elo: Option<u32>,
+
+    /// Seed for the randomness used by weaker skill levels, random if not given
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// TOML file of evaluation weights to use instead of the built in ones
+    #[arg(long)]
+    eval_params: Option<PathBuf>,
+
+    /// Network file to evaluate with instead of the piece square tables
+    #[arg(long)]
+    nnue: Option<PathBuf>,
+
+    /// Evaluation the engine searches with
+    #[arg(long, value_enum, default_value_t = EvaluatorKind::Default)]
+    evaluator: EvaluatorKind,
+
+    /// Algorithm the engine picks its moves with
+    #[arg(long, value_enum, default_value_t = SearcherKind::AlphaBeta)]
+    searcher: SearcherKind,
+
+    /// Iterations of Monte Carlo tree search per move, the time to think can stop it sooner
+    #[arg(long, default_value_t = MctsOptions::default().iterations)]
+    mcts_iterations: u32,
+
+    /// How Monte Carlo tree search picks which move to explore
+    #[arg(long, value_enum, default_value_t = Selection::Puct)]
+    mcts_selection: Selection,
+
+    /// Exploration constant for Monte Carlo tree search
+    #[arg(long, default_value_t = MctsOptions::default().exploration)]
+    mcts_exploration: f64,
+
+    /// Plies of random play Monte Carlo tree search values leaves with, 0 uses the evaluation
+    #[arg(long, default_value_t = 0)]
+    mcts_rollout_plies: u16,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Tools that run instead of a game
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Searches a built in set of positions to a fixed depth, the total node count is a signature of the search
+    Bench {
+        /// Plies to search each position to
+        #[arg(default_value_t = DEFAULT_BENCH_DEPTH, value_parser = clap::value_parser!(u16).range(1..MAX_DEPTH as i64))]
+        depth: u16,
+    },
+    /// Runs an EPD test suite like WAC, checking the move of --searcher against each position's bm and am moves
+    Epd {
+        /// Suite to run
+        file: PathBuf,
+        /// Deepest search per position, --time-to-think seconds per position is the other limit
+        #[arg(short, long)]
+        depth: Option<u16>,
+    },
+    /// Prints a breakdown of the evaluation of the --fen position
+    Eval,
+    /// Writes the evaluation weights in use as TOML, a starting point for an --eval-params file
+    ExportParams {
+        /// File to write to, printed if left out
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Plays two engine configurations against each other, each opening twice with colors swapped, and runs an SPRT on the results.
+    /// Each engine can load its own eval_params file, but both share the process and so the network from --nnue
+    Match {
+        /// Settings for the first engine on top of the global ones, like searcher=mcts,evaluator=material,depth=3,time=1
+        #[arg(long, default_value = "")]
+        engine_a: String,
+        /// Settings for the second engine, the same keys as --engine-a
+        #[arg(long, default_value = "")]
+        engine_b: String,
+        /// Opening positions one per line, FEN or EPD, a small built in set if left out
+        #[arg(long)]
+        openings: Option<PathBuf>,
+        /// Most game pairs to play, fewer if the SPRT finishes first
+        #[arg(long, default_value_t = 50)]
+        pairs: usize,
+        /// Games to play at once, all cores if left out
+        #[arg(long)]
+        concurrency: Option<usize>,
+        /// Elo gain of the first engine under the null hypothesis
+        #[arg(long, default_value_t = 0.0)]
+        elo0: f64,
+        /// Elo gain of the first engine under the alternative hypothesis
+        #[arg(long, default_value_t = 10.0)]
+        elo1: f64,
+        /// Chance of accepting the alternative hypothesis when the null one holds
+        #[arg(long, default_value_t = 0.05)]
+        alpha: f64,
+        /// Chance of accepting the null hypothesis when the alternative one holds
+        #[arg(long, default_value_t = 0.05)]
+        beta: f64,
+    },
+    /// Runs Monte Carlo tree search on the --fen position and prints its visits per move next to the alpha-beta engine's choice
+    Mcts,
+    /// Counts the positions reachable from the --fen position to a depth, split by first move
+    Perft {
+        /// Plies to count to
+        #[arg(default_value_t = 5)]
+        depth: u16,
+        /// EPD file of positions and their expected counts like ;D1 20 ;D2 400, checked up to depth
+        #[arg(long)]
+        epd: Option<PathBuf>,
+        /// Threads to share the root moves between, all cores if left out
+        #[arg(long)]
+        threads: Option<usize>,
+        /// Megabytes of subtree counts to keep, 0 to count without the table
+        #[arg(long, default_value_t = DEFAULT_PERFT_HASH_MB)]
+        hash_mb: usize,
+    },
+    /// Proves or disproves a forced mate for the side to move in the --fen position with proof-number search
+    Prove {
+        /// Most nodes to search
+        #[arg(short, long, default_value_t = PnOptions::default().node_budget)]
+        nodes: u64,
+        /// Prove a win of at least this many centipawns of material instead of mate
+        #[arg(long)]
+        material: Option<i32>,
+        /// Lines longer than this many plies don't count
+        #[arg(long, default_value_t = PnOptions::default().max_plies)]
+        max_plies: u16,
+        /// Plain proof-number search instead of PN²
+        #[arg(long, action = ArgAction::SetTrue)]
+        plain: bool,
+        /// Print the whole proof tree and not just the main line
+        #[arg(long, action = ArgAction::SetTrue)]
+        tree: bool,
+    },
+    /// Solves the --fen position as a chess problem, printing every key with its variations, cooks and duals
+    Solve {
+        /// #n for directmate, s#n for selfmate or h#n for helpmate in n moves
+        stipulation: Stipulation,
+    },
+    /// Plays a round robin or gauntlet between UCI engines run as child processes, writing every game as PGN and printing a crosstable
+    Tournament {
+        /// An engine as comma separated settings, give two or more. cmd=path is needed, then name, args split on spaces,
+        /// option.Name=value, and a time control of tc=40/60+0.6, st= seconds a move, depth= or nodes=
+        #[arg(long = "engine", required = true)]
+        engines: Vec<String>,
+        /// Settings every engine gets before its own, like tc=10+0.1
+        #[arg(long, default_value = "")]
+        each: String,
+        /// The first engine plays all of the others, who don't play each other
+        #[arg(long, action = ArgAction::SetTrue)]
+        gauntlet: bool,
+        /// Opening positions one per line, FEN or EPD, a small built in set if left out
+        #[arg(long)]
+        openings: Option<PathBuf>,
+        /// Times every pairing plays each opening with both colors
+        #[arg(long, default_value_t = 1)]
+        rounds: usize,
+        /// Games to play at once, each one runs two engines
+        #[arg(long, default_value_t = 1)]
+        concurrency: usize,
+        /// File to append every game to in PGN
+        #[arg(long)]
+        pgn: Option<PathBuf>,
+        /// Milliseconds an engine may go past its clock before it loses on time
+        #[arg(long, default_value_t = 100)]
+        time_margin: u64,
+        /// Adjudicate a loss once an engine's score is at or below minus this many centipawns for --resign-moves moves in a row
+        #[arg(long)]
+        resign_score: Option<i32>,
+        #[arg(long, default_value_t = 3)]
+        resign_moves: usize,
+        /// Adjudicate a draw once both engines' scores stay within this many centipawns for --draw-moves moves each,
+        /// from move --draw-move-number on
+        #[arg(long)]
+        draw_score: Option<i32>,
+        #[arg(long, default_value_t = 8)]
+        draw_moves: usize,
+        #[arg(long, default_value_t = 40)]
+        draw_move_number: u32,
+        /// Mate proof adjudication: a win once this many pieces or fewer are left and proof-number search finds the mate.
+        /// Unproven wins and drawn endings are left to --syzygy and the other rules
+        #[arg(long)]
+        mate_proof_pieces: Option<u32>,
+        /// Most nodes of proof-number search for --mate-proof-pieces
+        #[arg(long, default_value_t = 100_000)]
+        mate_proof_nodes: u64,
+        /// Tablebase adjudication: games end in the result the Syzygy tables in this directory give once they cover the position.
+        /// Needs ReeseBot built with the syzygy feature
+        #[arg(long)]
+        syzygy: Option<PathBuf>,
+    },
+    /// Texel tunes every evaluation weight against a file of positions, one FEN and game result like [1.0] per line.
+    /// Material, the piece square tables and the pawn structure, king safety, mobility and endgame weights
+    Tune {
+        /// Labeled positions to tune against
+        dataset: PathBuf,
+        /// Where to write the tuned weights, load them back with --eval-params
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Gradient descent steps to take
+        #[arg(long, default_value_t = 1000)]
+        iterations: u32,
+        /// Step size in centipawns
+        #[arg(long, default_value_t = 1.0)]
+        learning_rate: f64,
+    },
+    /// Speaks the Universal Chess Interface on stdin and stdout, for GUIs and tournament managers
+    Uci,
 }
 
 fn main() {
     SimpleLogger::new().without_timestamps().init().unwrap();
+    let args = Args::parse();
+
+    //Has to happen before any board is made, boards keep sums based on these weights
+    if let Some(path) = &args.eval_params {
+        match EvalParams::load(path) {
+            Ok(params) => set_eval_params(params).unwrap(),
+            Err(e) => {
+                error!("Invalid eval params: {}", e);
+                exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.nnue {
+        match Network::load(path) {
+            Ok(network) => set_network(network).unwrap(),
+            Err(e) => {
+                error!("Invalid network: {}", e);
+                exit(1);
+            }
+        }
+    }
+
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let skill = match (args.skill_level, args.elo) {
+        (Some(level), _) => Some(SkillLevel::new(level, seed)),
+        (None, Some(elo)) => Some(SkillLevel::from_elo(elo, seed)),
+        (None, None) => None,
+    };
+    let options = SearchOptions {
+        contempt: args.contempt,
+        skill,
+        evaluator: args.evaluator,
+        mcts: MctsOptions {
+            selection: args.mcts_selection,
+            exploration: args.mcts_exploration,
+            iterations: args.mcts_iterations,
+            rollout_plies: args.mcts_rollout_plies,
+        },
+        ..SearchOptions::default()
+    };
+    let limits = SearchLimits {
+        time_to_think: Duration::from_secs(args.time_to_think),
+        max_depth: None,
+        node_limit: None,
+    };
+
+    match args.command {
+        Some(Command::Bench { depth }) => {
+            run_bench(depth, &options);
+            return;
+        }
+        Some(Command::Epd { file, depth }) => {
+            let mut searcher = make_searcher(args.searcher, &options, seed);
+            let limits = SearchLimits {
+                max_depth: depth,
+                ..limits
+            };
+            run_epd_suite(&file, searcher.as_mut(), &limits);
+            return;
+        }
+        Some(Command::Eval) => {
+            print_eval(&args.fen);
+            return;
+        }
+        Some(Command::ExportParams { output }) => {
+            let text = eval_params().to_toml();
+            match output {
+                Some(path) => {
+                    if let Err(e) = fs::write(&path, text) {
+                        error!("Could not write {}: {}", path.display(), e);
+                        exit(1);
+                    }
+                }
+                None => print!("{}", text),
+            }
+            return;
+        }
+        Some(Command::Tune {
+            dataset,
+            output,
+            iterations,
+            learning_rate,
+        }) => {
+            run_tuner(&dataset, &output, iterations, learning_rate);
+            return;
+        }
+        Some(Command::Match {
+            engine_a,
+            engine_b,
+            openings,
+            pairs,
+            concurrency,
+            elo0,
+            elo1,
+            alpha,
+            beta,
+        }) => {
+            let base = EngineConfig {
+                name: String::new(),
+                searcher: args.searcher,
+                options,
+                limits,
+            };
+            let engines = EngineConfig::parse(&format!("name=A,{}", engine_a), &base, seed)
+                .and_then(|first| {
+                    EngineConfig::parse(&format!("name=B,{}", engine_b), &base, seed)
+                        .map(|second| (first, second))
+                });
+            let (first, second) = match engines {
+                Ok(engines) => engines,
+                Err(e) => {
+                    error!("Invalid engine settings: {}", e);
+                    exit(1);
+                }
+            };
+            let openings = load_openings(openings.as_deref());
+            let settings = MatchSettings {
+                openings,
+                pairs,
+                concurrency: concurrency.unwrap_or_else(|| {
+                    std::thread::available_parallelism().map_or(1, |threads| threads.get())
+                }),
+                sprt: Sprt {
+                    elo0,
+                    elo1,
+                    alpha,
+                    beta,
+                },
+                seed,
+            };
+            run_self_play(&first, &second, &settings);
+            return;
+        }
+        Some(Command::Mcts) => {
+            compare_mcts(&args.fen, &options, &limits, seed);
+            return;
+        }
+        Some(Command::Prove {
+            nodes,
+            material,
+            max_plies,
+            plain,
+            tree,
+        }) => {
+            let pn_options = PnOptions {
+                goal: material.map_or(Goal::Mate, Goal::Material),
+                node_budget: nodes,
+                max_plies,
+                pn2: !plain,
+            };
+            run_prover(&args.fen, &pn_options, tree);
+            return;
+        }
+        Some(Command::Perft {
+            depth,
+            epd,
+            threads,
+            hash_mb,
+        }) => {
+            let perft = Perft::new(&PerftOptions {
+                threads: threads.unwrap_or(PerftOptions::default().threads),
+                hash_mb,
+            });
+            match epd {
+                Some(path) => run_perft_suite(&perft, &path, depth),
+                None => run_perft(&perft, &args.fen, depth),
+            }
+            return;
+        }
+        Some(Command::Tournament {
+            engines,
+            each,
+            gauntlet,
+            openings,
+            rounds,
+            concurrency,
+            pgn,
+            time_margin,
+            resign_score,
+            resign_moves,
+            draw_score,
+            draw_moves,
+            draw_move_number,
+            mate_proof_pieces,
+            mate_proof_nodes,
+            syzygy,
+        }) => {
+            let engines = match engines
+                .iter()
+                .map(|engine| EngineSpec::parse(&format!("{},{}", each, engine)))
+                .collect()
+            {
+                Ok(engines) => engines,
+                Err(e) => {
+                    error!("Invalid engine settings: {}", e);
+                    exit(1);
+                }
+            };
+            let syzygy = match syzygy.as_deref().map(Tablebases::open).transpose() {
+                Ok(tables) => tables.map(Arc::new),
+                Err(e) => {
+                    error!("Invalid tablebases: {}", e);
+                    exit(1);
+                }
+            };
+            let settings = TournamentSettings {
+                engines,
+                gauntlet,
+                openings: load_openings(openings.as_deref()),
+                rounds,
+                concurrency,
+                time_margin: Duration::from_millis(time_margin),
+                adjudication: Adjudication {
+                    resign: resign_score.map(|score| ResignRule {
+                        move_count: resign_moves,
+                        score,
+                    }),
+                    draw: draw_score.map(|score| DrawRule {
+                        move_number: draw_move_number,
+                        move_count: draw_moves,
+                        score,
+                    }),
+                    mate_proof: mate_proof_pieces.map(|max_pieces| MateProofRule {
+                        max_pieces,
+                        node_budget: mate_proof_nodes,
+                    }),
+                    syzygy,
+                },
+                pgn,
+            };
+            run_uci_tournament(&settings);
+            return;
+        }
+        Some(Command::Uci) => {
+            if let Err(e) = run_uci(
+                args.searcher,
+                &options,
+                seed,
+                &limits,
+                std::io::stdin().lock(),
+                &mut std::io::stdout().lock(),
+            ) {
+                error!("Lost the connection: {}", e);
+                exit(1);
+            }
+            return;
+        }
+        Some(Command::Solve { stipulation }) => {
+            run_solver(&args.fen, stipulation);
+            return;
+        }
+        None => {}
+    }
+
     info!("Hello! I am Reese Bot, a CLI based (for now) chess engine.");
     info!("To play, simply type your move in standard fen string notation.");
     info!("");
-    let args = Args::parse();
 
-    play_game(&args.fen, args.time_to_think, args.engine_mode);
+    let mut searcher = make_searcher(args.searcher, &options, seed);
+    play_game(&args.fen, args.engine_mode, searcher.as_mut(), &limits);
 }
 
-fn play_game(board_state_fen: &str, time_to_think: u64, engine_mode: bool) {
+fn play_game(
+    board_state_fen: &str,
+    engine_mode: bool,
+    searcher: &mut dyn Searcher,
+    limits: &SearchLimits,
+) {
     let board_state: Result<BoardState, &str> = BoardState::new(board_state_fen);
     let mut board: BoardState;
 
@@ -61,6 +581,7 @@ fn play_game(board_state_fen: &str, time_to_think: u64, engine_mode: bool) {
 
     board.print_board();
     let mut moves;
+    let mut history: Vec<u64> = Vec::new(); //Positions played so far, the engine needs these to spot repetitions
     loop {
         let mut input = String::new();
         moves = gen_all_moves(&board, Color::White);
@@ -74,6 +595,11 @@ fn play_game(board_state_fen: &str, time_to_think: u64, engine_mode: bool) {
             break;
         }
 
+        if board.is_fifty_move_draw() || board.is_insufficient_material() {
+            info!("Game over by draw");
+            break;
+        }
+
         match board.active_color {
             Color::White => {
                 println!("Please enter a move: ");
@@ -81,6 +607,7 @@ fn play_game(board_state_fen: &str, time_to_think: u64, engine_mode: bool) {
                 match parse_move(&input, &board, engine_mode) {
                     Ok(mv) => {
                         clear_screen();
+                        history.push(board.zobrist_key());
                         board.make_move(&mv);
                         board.print_board();
                     }
@@ -91,11 +618,21 @@ fn play_game(board_state_fen: &str, time_to_think: u64, engine_mode: bool) {
             }
             Color::Black => {
                 println!("Thinking...");
-                let result = calculate_best_move(&board, time_to_think);
+                let result = searcher.search(&board, &history, limits);
                 if let Some(mv) = result.move_found {
                     clear_screen();
+                    history.push(board.zobrist_key());
                     board.make_move(&mv);
                     board.print_board();
+                    info!(
+                        "{}: depth {} score {} nodes {} time {}ms pv {}",
+                        searcher.name(),
+                        result.depth,
+                        result.score,
+                        result.nodes_searched,
+                        result.elapsed.as_millis(),
+                        pv_to_string(&result.pv)
+                    );
                 } else {
                     //Black has no moves
                     if board.is_in_check(Color::Black, None) {
@@ -110,6 +647,414 @@ fn play_game(board_state_fen: &str, time_to_think: u64, engine_mode: bool) {
     }
 }
 
+fn print_eval(board_state_fen: &str) {
+    let board = match BoardState::new(board_state_fen) {
+        Ok(board) => board,
+        Err(e) => panic!("Error: {}", e),
+    };
+
+    board.print_board();
+    println!();
+    println!("{}", evaluate_traced(&board));
+}
+
+fn compare_mcts(board_state_fen: &str, options: &SearchOptions, limits: &SearchLimits, seed: u64) {
+    let board = match BoardState::new(board_state_fen) {
+        Ok(board) => board,
+        Err(e) => panic!("Error: {}", e),
+    };
+    board.print_board();
+
+    let evaluator = make_evaluator(options.evaluator, options.eval_params.clone());
+    let mcts = Mcts::new(options.mcts, evaluator, seed).run(&board, &[], limits);
+    let alpha_beta =
+        make_searcher(SearcherKind::AlphaBeta, options, seed).search(&board, &[], limits);
+    let alpha_beta_move = alpha_beta.move_found;
+
+    println!(
+        "MCTS ran {} iterations in {}ms, pv {}",
+        mcts.search.nodes_searched,
+        mcts.search.elapsed.as_millis(),
+        pv_to_string(&mcts.search.pv)
+    );
+    println!("{:<8}{:>8}{:>8}{:>8}", "Move", "Visits", "Value", "Prior");
+    for stats in &mcts.root_moves {
+        let marker = if Some(stats.mv) == alpha_beta_move {
+            "  <- alpha-beta"
+        } else {
+            ""
+        };
+        println!(
+            "{:<8}{:>8}{:>8.3}{:>8.3}{}",
+            stats.mv.to_string(),
+            stats.visits,
+            stats.value,
+            stats.prior,
+            marker
+        );
+    }
+    println!(
+        "Alpha-beta: depth {} score {} nodes {} pv {}",
+        alpha_beta.depth,
+        alpha_beta.score,
+        alpha_beta.nodes_searched,
+        pv_to_string(&alpha_beta.pv)
+    );
+}
+
+fn run_prover(board_state_fen: &str, options: &PnOptions, print_tree: bool) {
+    let board = match BoardState::new(board_state_fen) {
+        Ok(board) => board,
+        Err(e) => panic!("Error: {}", e),
+    };
+    board.print_board();
+
+    let result = prove(&board, options);
+    let goal = match options.goal {
+        Goal::Mate => "mate".to_string(),
+        Goal::Material(gain) => format!("a win of {} centipawns", gain),
+    };
+    match result.outcome {
+        Outcome::Proven => {
+            println!("Proved {} in {} nodes", goal, result.nodes);
+            println!("Main line: {}", pv_to_string(&result.main_line));
+            if print_tree {
+                print_proof_tree(&result.proof_tree, 0);
+            }
+        }
+        Outcome::Disproven => println!(
+            "Disproved {} within {} plies in {} nodes",
+            goal, options.max_plies, result.nodes
+        ),
+        Outcome::Unknown => println!("Could not settle {} in {} nodes", goal, result.nodes),
+    }
+}
+
+fn print_proof_tree(trees: &[ProofTree], depth: usize) {
+    for tree in trees {
+        println!("{}{}", "  ".repeat(depth), tree.mv.to_string());
+        print_proof_tree(&tree.replies, depth + 1);
+    }
+}
+
+/* Reads the openings of a match or tournament, the built in ones if there is no file */
+fn load_openings(path: Option<&Path>) -> Vec<String> {
+    let Some(path) = path else {
+        return DEFAULT_OPENINGS.iter().map(|fen| fen.to_string()).collect();
+    };
+    match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| parse_openings(&text))
+    {
+        Ok(openings) if !openings.is_empty() => openings,
+        Ok(_) => {
+            error!("No openings in {}", path.display());
+            exit(1);
+        }
+        Err(e) => {
+            error!("Invalid openings {}: {}", path.display(), e);
+            exit(1);
+        }
+    }
+}
+
+fn run_uci_tournament(settings: &TournamentSettings) {
+    let result = run_tournament(settings, |index, game, record, _| {
+        println!(
+            "Game {}: {} vs {} {} ({}, {} plies)",
+            index + 1,
+            settings.engines[game.white].name,
+            settings.engines[game.black].name,
+            record.result.to_pgn(),
+            record.reason,
+            record.moves.len()
+        );
+    });
+    match result {
+        Ok(crosstable) => {
+            println!();
+            print!("{}", crosstable);
+        }
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_self_play(first: &EngineConfig, second: &EngineConfig, settings: &MatchSettings) {
+    let score = run_match(
+        first,
+        second,
+        settings,
+        |index, record, first_is_white, score| {
+            let (white, black) = if first_is_white {
+                (&first.name, &second.name)
+            } else {
+                (&second.name, &first.name)
+            };
+            println!(
+                "Game {}: {} vs {} {} ({}, {} plies), {} +{} ={} -{}",
+                index + 1,
+                white,
+                black,
+                record.result.to_pgn(),
+                record.reason,
+                record.moves.len(),
+                first.name,
+                score.wins,
+                score.draws,
+                score.losses
+            );
+        },
+    );
+
+    let sprt = settings.sprt;
+    let (lower, upper) = sprt.bounds();
+    let verdict = match sprt.verdict(&score) {
+        SprtVerdict::AcceptH1 => "H1 accepted",
+        SprtVerdict::AcceptH0 => "H0 accepted",
+        SprtVerdict::Continue => "inconclusive",
+    };
+    println!();
+    println!(
+        "Score of {} vs {}: {} - {} - {} [{:.3}] {}",
+        first.name,
+        second.name,
+        score.wins,
+        score.losses,
+        score.draws,
+        score.ratio(),
+        score.games()
+    );
+    println!(
+        "Elo difference: {:.1} +/- {:.1}",
+        score.elo(),
+        score.elo_error()
+    );
+    println!(
+        "SPRT elo0 {} elo1 {}: LLR {:.2} ({:.2}, {:.2}), {}",
+        sprt.elo0,
+        sprt.elo1,
+        score.llr(sprt.elo0, sprt.elo1),
+        lower,
+        upper,
+        verdict
+    );
+}
+
+fn run_bench(depth: u16, options: &SearchOptions) {
+    let result = bench(&BENCH_POSITIONS, depth, options);
+    for (index, nodes) in result.nodes.iter().enumerate() {
+        println!("Position {:>2}: {} nodes", index + 1, nodes);
+    }
+    println!();
+    println!("Nodes: {}", result.total_nodes());
+    println!(
+        "Time: {} ms, {} nps",
+        result.elapsed.as_millis(),
+        nodes_per_second(result.total_nodes(), result.elapsed)
+    );
+}
+
+fn run_epd_suite(path: &PathBuf, searcher: &mut dyn Searcher, limits: &SearchLimits) {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("Could not read {}: {}", path.display(), e);
+            exit(1);
+        }
+    };
+    let (suite, errors) = parse_epd(&text);
+    for e in &errors {
+        error!("Skipping {}", e);
+    }
+
+    let mut failed = Vec::new();
+    let mut solution_time = Duration::ZERO;
+    for (index, position) in suite.iter().enumerate() {
+        let name = position
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("#{}", index + 1));
+        let outcome = run_position(searcher, position, limits);
+        let played = outcome.played.as_deref().unwrap_or("nothing");
+        match outcome.time_to_solution {
+            Some(time) if outcome.solved => {
+                solution_time += time;
+                println!(
+                    "{}: solved with {} in {} ms, depth {}",
+                    name,
+                    played,
+                    time.as_millis(),
+                    outcome.depth
+                );
+            }
+            _ => {
+                println!(
+                    "{}: failed, played {} at depth {}",
+                    name, played, outcome.depth
+                );
+                failed.push(name);
+            }
+        }
+    }
+
+    let solved = suite.len() - failed.len();
+    println!();
+    println!(
+        "Solved {} of {} ({:.1}%)",
+        solved,
+        suite.len(),
+        100.0 * solved as f64 / suite.len().max(1) as f64
+    );
+    if solved > 0 {
+        println!(
+            "Average time to solution: {} ms",
+            solution_time.as_millis() / solved as u128
+        );
+    }
+    if !failed.is_empty() {
+        println!("Failed: {}", failed.join(", "));
+    }
+    if !errors.is_empty() {
+        println!("Skipped {} unreadable lines", errors.len());
+    }
+}
+
+fn run_perft(perft: &Perft, board_state_fen: &str, depth: u16) {
+    let board = match BoardState::new(board_state_fen) {
+        Ok(board) => board,
+        Err(e) => panic!("Error: {}", e),
+    };
+
+    let start = Instant::now();
+    let mut divided: Vec<(String, u64)> = perft
+        .divide(&board, depth)
+        .into_iter()
+        .map(|(mv, nodes)| (mv.to_uci(board.active_color), nodes))
+        .collect();
+    let elapsed = start.elapsed();
+    divided.sort();
+
+    for (mv, nodes) in &divided {
+        println!("{}: {}", mv, nodes);
+    }
+    let total: u64 = divided.iter().map(|(_, nodes)| nodes).sum();
+    println!();
+    println!("Moves: {}", divided.len());
+    println!("Nodes: {}", total);
+    println!(
+        "Time: {} ms, {} nps",
+        elapsed.as_millis(),
+        nodes_per_second(total, elapsed)
+    );
+}
+
+//Checks every position of the suite against its expected counts up to max_depth, exiting with an error if any are off
+fn run_perft_suite(perft: &Perft, path: &PathBuf, max_depth: u16) {
+    let cases = match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| parse_suite(&text))
+    {
+        Ok(cases) => cases,
+        Err(e) => {
+            error!("Invalid perft suite {}: {}", path.display(), e);
+            exit(1);
+        }
+    };
+
+    let start = Instant::now();
+    let (mut checked, mut right, mut mismatches, mut total) = (0, 0, 0, 0);
+    for case in &cases {
+        let board = match BoardState::new(&case.fen) {
+            Ok(board) => board,
+            Err(e) => {
+                error!("Invalid position {}: {}", case.fen, e);
+                mismatches += 1;
+                continue;
+            }
+        };
+        for &(depth, expected) in case
+            .expected
+            .iter()
+            .filter(|(depth, _)| *depth <= max_depth)
+        {
+            let nodes = perft.count(&board, depth);
+            checked += 1;
+            total += nodes;
+            if nodes == expected {
+                right += 1;
+            } else {
+                mismatches += 1;
+                println!(
+                    "Mismatch: {} at depth {}, expected {} but counted {}",
+                    case.fen, depth, expected, nodes
+                );
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} positions, {} of {} counts right, {} nodes in {} ms, {} nps",
+        cases.len(),
+        right,
+        checked,
+        total,
+        elapsed.as_millis(),
+        nodes_per_second(total, elapsed)
+    );
+    if mismatches > 0 {
+        exit(1);
+    }
+}
+
+fn nodes_per_second(nodes: u64, elapsed: Duration) -> u64 {
+    (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+}
+
+fn run_solver(board_state_fen: &str, stipulation: Stipulation) {
+    let board = match BoardState::new(board_state_fen) {
+        Ok(board) => board,
+        Err(e) => panic!("Error: {}", e),
+    };
+    board.print_board();
+    print!("{}", solve(&board, stipulation));
+}
+
+fn run_tuner(dataset: &PathBuf, output: &PathBuf, iterations: u32, learning_rate: f64) {
+    let text = match fs::read_to_string(dataset) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("Could not read {}: {}", dataset.display(), e);
+            exit(1);
+        }
+    };
+    //Tuning starts from whatever --eval-params loaded
+    let positions = match parse_dataset(&text, eval_params()) {
+        Ok((positions, skipped)) => {
+            info!("Skipped {} positions that weren't quiet", skipped);
+            positions
+        }
+        Err(e) => {
+            error!("Invalid dataset: {}", e);
+            exit(1);
+        }
+    };
+    if positions.is_empty() {
+        error!("No quiet positions to tune with");
+        exit(1);
+    }
+
+    let tuned = tune(&positions, eval_params(), iterations, learning_rate);
+    if let Err(e) = fs::write(output, tuned.to_toml()) {
+        error!("Could not write {}: {}", output.display(), e);
+        exit(1);
+    }
+    info!("Wrote tuned weights to {}", output.display());
+}
+
 // Weird hack but it works
 fn clear_screen() {
     print!("{}[2J", 27 as u8 as char);
@@ -0,0 +1,451 @@
+use crate::board_state::BoardState;
+use crate::chess_move::Move;
+use crate::engine::{SearchResult, MATE_VALUE};
+use crate::evaluator::Evaluator;
+use crate::move_gen::gen_all_moves;
+use crate::searcher::{SearchLimits, Searcher};
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, Instant};
+
+/*
+    Monte Carlo tree search, see https://www.chessprogramming.org/Monte-Carlo_Tree_Search
+    Every iteration walks down the tree picking children by UCT or PUCT, expands the leaf it reaches,
+    values it with the evaluation or a random playout and backs that value up the path.
+    Values are win chances between 0 and 1, stored from the point of view of the side that made the move into a node.
+*/
+
+const PRIOR_TEMPERATURE: f64 = 100.0; //Centipawns, how sharply PUCT priors favour the better looking moves
+const MAX_SCORE: i32 = 10000; //Centipawns reported for a win chance of 0 or 1
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum Selection {
+    /// Upper confidence bound, every move is tried before any is tried twice
+    Uct,
+    /// Predictor UCB, moves that evaluate well one ply ahead are tried first
+    #[default]
+    Puct,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MctsOptions {
+    pub selection: Selection,
+    //Exploration constant, higher spreads the visits over more moves
+    pub exploration: f64,
+    //Iterations to run, the time budget can stop it earlier
+    pub iterations: u32,
+    //Plies of random play to value a leaf with, 0 uses the evaluation of the leaf instead
+    pub rollout_plies: u16,
+}
+
+impl Default for MctsOptions {
+    fn default() -> MctsOptions {
+        MctsOptions {
+            selection: Selection::Puct,
+            exploration: 1.4,
+            iterations: 20000,
+            rollout_plies: 0,
+        }
+    }
+}
+
+/* How often a root move was visited and how good it looked */
+#[derive(Clone, Copy, Debug)]
+pub struct RootMoveStats {
+    pub mv: Move,
+    pub visits: u32,
+    pub value: f64, //Win chance for the side to move at the root
+    pub prior: f64,
+}
+
+pub struct MctsResult {
+    pub search: SearchResult,
+    pub root_moves: Vec<RootMoveStats>, //Most visited first
+}
+
+struct Node {
+    mv: Option<Move>,
+    children: Vec<usize>,
+    expanded: bool,
+    visits: u32,
+    value_sum: f64,
+    prior: f64,
+}
+
+impl Node {
+    fn new(mv: Option<Move>, prior: f64) -> Node {
+        Node {
+            mv,
+            children: Vec::new(),
+            expanded: false,
+            visits: 0,
+            value_sum: 0.0,
+            prior,
+        }
+    }
+
+    fn value(&self) -> f64 {
+        if self.visits == 0 {
+            0.5
+        } else {
+            self.value_sum / self.visits as f64
+        }
+    }
+}
+
+pub struct Mcts {
+    pub options: MctsOptions,
+    evaluator: Box<dyn Evaluator>,
+    rng: StdRng,
+    nodes: Vec<Node>,
+}
+
+//Centipawns to a win chance, the same curve the tuner fits
+fn win_chance(score: i32) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-score as f64 / 400.0))
+}
+
+fn centipawns(win_chance: f64) -> i32 {
+    if win_chance <= 0.0 {
+        -MAX_SCORE
+    } else if win_chance >= 1.0 {
+        MAX_SCORE
+    } else {
+        ((-400.0 * (1.0 / win_chance - 1.0).log10()) as i32).clamp(-MAX_SCORE, MAX_SCORE)
+    }
+}
+
+impl Mcts {
+    pub fn new(options: MctsOptions, evaluator: Box<dyn Evaluator>, seed: u64) -> Mcts {
+        Mcts {
+            options,
+            evaluator,
+            rng: StdRng::seed_from_u64(seed),
+            nodes: Vec::new(),
+        }
+    }
+
+    /* Searches and also hands back the visit counts of every root move */
+    pub fn run(
+        &mut self,
+        board: &BoardState,
+        history: &[u64],
+        limits: &SearchLimits,
+    ) -> MctsResult {
+        let start = Instant::now();
+        let mut iterations = self.options.iterations;
+        if let Some(limit) = limits.node_limit {
+            iterations = iterations.min(limit.min(u32::MAX as u64) as u32);
+        }
+
+        self.nodes = vec![Node::new(None, 1.0)];
+        let mut done = 0;
+        while done < iterations && start.elapsed() <= limits.time_to_think {
+            self.iterate(board, history);
+            done += 1;
+            //Nothing to choose between
+            if self.nodes[0].expanded && self.nodes[0].children.len() <= 1 {
+                break;
+            }
+        }
+
+        let mut root_moves: Vec<RootMoveStats> = self.nodes[0]
+            .children
+            .iter()
+            .map(|&child| {
+                let node = &self.nodes[child];
+                RootMoveStats {
+                    mv: node.mv.unwrap(),
+                    visits: node.visits,
+                    value: node.value(),
+                    prior: node.prior,
+                }
+            })
+            .collect();
+        root_moves.sort_by(|a, b| b.visits.cmp(&a.visits));
+
+        let pv = self.principal_variation();
+        let search = SearchResult {
+            score: root_moves
+                .first()
+                .map_or(0, |stats| centipawns(stats.value)),
+            move_found: pv.first().copied(),
+            nodes_searched: done as u64,
+            depth: pv.len() as u16,
+            pv,
+            elapsed: start.elapsed(),
+        };
+        MctsResult { search, root_moves }
+    }
+
+    //Most visited child all the way down
+    fn principal_variation(&self) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut index = 0;
+        while let Some(&child) = self.nodes[index]
+            .children
+            .iter()
+            .max_by_key(|&&child| self.nodes[child].visits)
+        {
+            if self.nodes[child].visits == 0 {
+                break;
+            }
+            pv.push(self.nodes[child].mv.unwrap());
+            index = child;
+        }
+        pv
+    }
+
+    fn iterate(&mut self, root: &BoardState, history: &[u64]) {
+        let mut board = root.clone();
+        let mut keys = history.to_vec();
+        let mut path = vec![0];
+
+        //Selection
+        let mut index = 0;
+        while self.nodes[index].expanded && !self.nodes[index].children.is_empty() {
+            index = self.select_child(index);
+            let before = board.clone();
+            keys.push(board.zobrist_key());
+            board.make_move(&self.nodes[index].mv.unwrap());
+            self.evaluator.push(&before, &board);
+            path.push(index);
+        }
+
+        //Expansion and the value of the leaf for its side to move
+        let value = match self.game_over_value(&board, &keys, history.len()) {
+            Some(value) => value,
+            None => {
+                self.expand(index, &board);
+                if self.options.rollout_plies > 0 {
+                    self.rollout(&board, keys, history.len())
+                } else {
+                    win_chance(self.evaluator.evaluate(&board))
+                }
+            }
+        };
+        for _ in 1..path.len() {
+            self.evaluator.pop();
+        }
+
+        //Backing up, the leaf's own value belongs to the side that moved into it
+        let mut value = 1.0 - value;
+        for &node in path.iter().rev() {
+            self.nodes[node].visits += 1;
+            self.nodes[node].value_sum += value;
+            value = 1.0 - value;
+        }
+    }
+
+    fn select_child(&self, index: usize) -> usize {
+        let parent = &self.nodes[index];
+        let parent_visits = parent.visits.max(1) as f64;
+        let score = |child: &Node| match self.options.selection {
+            Selection::Uct => {
+                if child.visits == 0 {
+                    f64::INFINITY
+                } else {
+                    child.value()
+                        + self.options.exploration
+                            * (parent_visits.ln() / child.visits as f64).sqrt()
+                }
+            }
+            Selection::Puct => {
+                child.value()
+                    + self.options.exploration * child.prior * parent_visits.sqrt()
+                        / (1 + child.visits) as f64
+            }
+        };
+        *parent
+            .children
+            .iter()
+            .max_by(|&&a, &&b| {
+                score(&self.nodes[a])
+                    .partial_cmp(&score(&self.nodes[b]))
+                    .unwrap()
+            })
+            .unwrap()
+    }
+
+    fn expand(&mut self, index: usize, board: &BoardState) {
+        let moves = gen_all_moves(board, board.active_color);
+        let priors = match self.options.selection {
+            Selection::Uct => vec![1.0 / moves.len() as f64; moves.len()],
+            //Softmax over how each move evaluates one ply ahead
+            Selection::Puct => {
+                let scores: Vec<f64> = moves
+                    .iter()
+                    .map(|mv| {
+                        let mut board_copy = board.clone();
+                        board_copy.make_move(mv);
+                        self.evaluator.push(board, &board_copy);
+                        let score = -self.evaluator.evaluate(&board_copy);
+                        self.evaluator.pop();
+                        score.clamp(-MAX_SCORE, MAX_SCORE) as f64 / PRIOR_TEMPERATURE
+                    })
+                    .collect();
+                let highest = scores.iter().cloned().fold(f64::MIN, f64::max);
+                let weights: Vec<f64> =
+                    scores.iter().map(|score| (score - highest).exp()).collect();
+                let total: f64 = weights.iter().sum();
+                weights.iter().map(|weight| weight / total).collect()
+            }
+        };
+        for (mv, prior) in moves.into_iter().zip(priors) {
+            self.nodes.push(Node::new(Some(mv), prior));
+            let child = self.nodes.len() - 1;
+            self.nodes[index].children.push(child);
+        }
+        self.nodes[index].expanded = true;
+    }
+
+    /*
+     * Win chance for the side to move when the game is already decided, None if it goes on.
+     * keys holds every position before this one, the first game_length of them were actually played
+     */
+    fn game_over_value(&self, board: &BoardState, keys: &[u64], game_length: usize) -> Option<f64> {
+        if gen_all_moves(board, board.active_color).is_empty() {
+            return if board.is_in_check(board.active_color, None) {
+                Some(0.0)
+            } else {
+                Some(0.5)
+            };
+        }
+        if board.is_fifty_move_draw() || board.is_insufficient_material() {
+            return Some(0.5);
+        }
+        //Once inside the tree, twice in the game itself
+        let key = board.zobrist_key();
+        let in_tree = keys[game_length..].contains(&key);
+        let in_game = keys[..game_length].iter().filter(|&&k| k == key).count();
+        if in_tree || in_game >= 2 {
+            return Some(0.5);
+        }
+        None
+    }
+
+    /*
+     * Random moves from the leaf, valued with the evaluation if the game hasn't ended by then.
+     * Returns the win chance for the side to move at the leaf
+     */
+    fn rollout(&mut self, leaf: &BoardState, mut keys: Vec<u64>, game_length: usize) -> f64 {
+        let mut board = leaf.clone();
+        let mut plies = 0;
+        let mut value = None;
+        while plies < self.options.rollout_plies {
+            //The leaf was checked before, and every position after it below, so there is always a move
+            let moves = gen_all_moves(&board, board.active_color);
+            let mv = moves[self.rng.gen_range(0..moves.len())];
+            let before = board.clone();
+            keys.push(board.zobrist_key());
+            board.make_move(&mv);
+            self.evaluator.push(&before, &board);
+            plies += 1;
+            value = self.game_over_value(&board, &keys, game_length);
+            if value.is_some() {
+                break;
+            }
+        }
+        let value = value.unwrap_or_else(|| win_chance(self.evaluator.evaluate(&board)));
+        for _ in 0..plies {
+            self.evaluator.pop();
+        }
+
+        //Value is for whoever is to move now, flipped back to the leaf's side on odd plies
+        if plies % 2 == 0 {
+            value
+        } else {
+            1.0 - value
+        }
+    }
+}
+
+impl Searcher for Mcts {
+    fn name(&self) -> &'static str {
+        "mcts"
+    }
+
+    fn search(
+        &mut self,
+        board: &BoardState,
+        history: &[u64],
+        limits: &SearchLimits,
+    ) -> SearchResult {
+        self.run(board, history, limits).search
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::{make_evaluator, EvaluatorKind};
+    use crate::piece::PieceType;
+
+    fn run(fen: &str, options: MctsOptions) -> MctsResult {
+        let board = BoardState::new(fen).unwrap();
+        let limits = SearchLimits {
+            time_to_think: Duration::from_secs(60),
+            max_depth: None,
+            node_limit: None,
+        };
+        Mcts::new(options, make_evaluator(EvaluatorKind::Default, None), 1).run(
+            &board,
+            &[],
+            &limits,
+        )
+    }
+
+    #[test]
+    fn visit_counts_add_up() {
+        let options = MctsOptions {
+            iterations: 500,
+            ..MctsOptions::default()
+        };
+        let result = run(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            options,
+        );
+        assert_eq!(result.root_moves.len(), 20);
+        assert_eq!(result.search.nodes_searched, 500);
+        //The root's first visit expands it
+        let visits: u32 = result.root_moves.iter().map(|stats| stats.visits).sum();
+        assert_eq!(visits, 499);
+        assert!(result
+            .root_moves
+            .windows(2)
+            .all(|pair| pair[0].visits >= pair[1].visits));
+        assert_eq!(result.search.move_found, Some(result.root_moves[0].mv));
+        let priors: f64 = result.root_moves.iter().map(|stats| stats.prior).sum();
+        assert!((priors - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn takes_the_queen() {
+        for selection in [Selection::Uct, Selection::Puct] {
+            let options = MctsOptions {
+                selection,
+                iterations: 400,
+                ..MctsOptions::default()
+            };
+            let result = run("k7/4r1q1/5P2/8/8/8/8/K7 w - - 0 1", options);
+            let mv = result.search.move_found.unwrap();
+            assert_eq!(mv.piece_captured.unwrap().piece_type, PieceType::Queen);
+        }
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        for rollout_plies in [0, 8] {
+            let options = MctsOptions {
+                iterations: 300,
+                rollout_plies,
+                ..MctsOptions::default()
+            };
+            let result = run("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", options);
+            assert_eq!(result.search.move_found.unwrap().to_string(), "a1a8");
+            assert!(result.root_moves[0].value > 0.99);
+            assert_eq!(result.search.score, MAX_SCORE);
+        }
+    }
+}
@@ -3,7 +3,6 @@ use crate::chess_move::*;
 use crate::color::*;
 use crate::piece;
 use crate::piece::*;
-use std::collections::HashSet;
 
 //Generates all valid moves for a color from a given board
 pub fn gen_all_moves(board: &BoardState, color: Color) -> Vec<Move> {
@@ -151,25 +150,26 @@ fn filter_moves(
         })
         .collect()
 }
+/* Positions are listed in board order so that move ordering, and with it node counts, are the same on every run */
 pub fn find_pieces(
     board: &BoardState,
     color: Color,
-) -> (HashSet<Position>, HashSet<Position>, Option<Position>) {
+) -> (Vec<Position>, Vec<Position>, Option<Position>) {
     /* Storing the positions of the white and black pieces */
-    let mut curr_pieces: HashSet<Position> = HashSet::new();
-    let mut other_pieces: HashSet<Position> = HashSet::new();
+    let mut curr_pieces: Vec<Position> = Vec::new();
+    let mut other_pieces: Vec<Position> = Vec::new();
     let mut opt_king_pos: Option<Position> = None;
 
     for x in 2..=10 {
         for y in 2..=10 {
             if let Some(curr_piece) = board.squares[x][y].piece {
                 if curr_piece.color == color {
-                    curr_pieces.insert(Position { row: x, col: y });
+                    curr_pieces.push(Position { row: x, col: y });
                     if curr_piece.piece_type == PieceType::King {
                         opt_king_pos = Some(Position { row: x, col: y });
                     }
                 } else {
-                    other_pieces.insert(Position { row: x, col: y });
+                    other_pieces.push(Position { row: x, col: y });
                 }
             }
         }
@@ -194,11 +194,22 @@ pub fn generate_castle_moves(
         return vec![];
     }
 
-    if castle_rights.can_castle_white_kingside || castle_rights.can_castle_black_kingside {
+    //Only our own rights matter, the other side's would walk off the board when our king isn't at home
+    let (can_castle_kingside, can_castle_queenside) = match color {
+        Color::White => (
+            castle_rights.can_castle_white_kingside,
+            castle_rights.can_castle_white_queenside,
+        ),
+        Color::Black => (
+            castle_rights.can_castle_black_kingside,
+            castle_rights.can_castle_black_queenside,
+        ),
+    };
+    if can_castle_kingside {
         king_side_squares = vec![king_pos.right(), king_pos.right().right()];
     }
 
-    if castle_rights.can_castle_white_queenside || castle_rights.can_castle_black_queenside {
+    if can_castle_queenside {
         queen_side_squares = vec![
             king_pos.left(),
             king_pos.left().left(),
@@ -446,6 +457,68 @@ pub fn move_in_direction(
     valid_moves
 }
 
+/* Squares a piece attacks from a position, including squares held by its own side. Pawns only attack diagonally forwards */
+pub fn piece_attacks(board: &BoardState, piece: Piece, pos: Position) -> Vec<Position> {
+    let directions = match piece.piece_type {
+        PieceType::Pawn => {
+            let (right_up, left_up, _, _, _, _) = generate_pawn_moves_helper(&pos, &piece.color);
+            return vec![right_up, left_up]
+                .into_iter()
+                .filter(|val| val.is_valid_position())
+                .collect();
+        }
+        PieceType::Knight => {
+            return knight_positions(pos)
+                .into_iter()
+                .filter(|val| val.is_valid_position())
+                .collect();
+        }
+        PieceType::King => {
+            return king_positions(pos)
+                .into_iter()
+                .filter(|val| val.is_valid_position())
+                .collect();
+        }
+        PieceType::Bishop => vec![
+            Direction::UpRight,
+            Direction::UpLeft,
+            Direction::DownRight,
+            Direction::DownLeft,
+        ],
+        PieceType::Rook => vec![
+            Direction::Right,
+            Direction::Left,
+            Direction::Up,
+            Direction::Down,
+        ],
+        PieceType::Queen => vec![
+            Direction::Right,
+            Direction::Left,
+            Direction::Up,
+            Direction::Down,
+            Direction::UpRight,
+            Direction::UpLeft,
+            Direction::DownRight,
+            Direction::DownLeft,
+        ],
+        PieceType::None => return Vec::new(),
+    };
+
+    let mut attacks: Vec<Position> = Vec::new();
+    for dir in directions {
+        let mut next_pos = pos.next_position(&dir);
+        while next_pos.is_valid_position() {
+            attacks.push(next_pos);
+            if board.squares[next_pos.row][next_pos.col].is_occupied() {
+                break;
+            }
+            next_pos = next_pos.next_position(&dir);
+        }
+    }
+
+    attacks
+}
+
 //Generates all positions a knight could move to from a given position
 pub fn knight_positions(pos: Position) -> Vec<Position> {
     vec![
@@ -476,22 +549,7 @@ pub fn king_positions(pos: Position) -> Vec<Position> {
 
 mod tests {
     use super::*;
-
-    fn count_moves(depth: u16, board: &BoardState) -> i64 {
-        if depth == 0 {
-            return 1;
-        }
-
-        let mut num_positions: i64 = 0;
-
-        for mv in gen_all_moves(board, board.active_color) {
-            let board_copy = &mut board.clone();
-            board_copy.make_move(&mv);
-            num_positions += count_moves(depth - 1, board_copy);
-        }
-
-        num_positions
-    }
+    use crate::perft::{Perft, PerftOptions};
 
     #[test]
     fn test_1() {
@@ -512,12 +570,14 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(count_moves(0, &mut board), 1);
-        assert_eq!(count_moves(1, &mut board), 20);
-        assert_eq!(count_moves(2, &mut board), 400);
-        assert_eq!(count_moves(3, &mut board), 8902);
-        assert_eq!(count_moves(4, &mut board), 197281);
-        assert_eq!(count_moves(5, &mut board), 4865609);
+        //One table for every depth of the position
+        let perft = Perft::new(&PerftOptions::default());
+        assert_eq!(perft.count(&board, 0), 1);
+        assert_eq!(perft.count(&board, 1), 20);
+        assert_eq!(perft.count(&board, 2), 400);
+        assert_eq!(perft.count(&board, 3), 8902);
+        assert_eq!(perft.count(&board, 4), 197281);
+        assert_eq!(perft.count(&board, 5), 4865609);
     }
 
     #[test] //Making sure the number of moves is correct
@@ -531,11 +591,12 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(count_moves(0, &mut board), 1);
-        assert_eq!(count_moves(1, &mut board), 48);
-        assert_eq!(count_moves(2, &mut board), 2039);
-        assert_eq!(count_moves(3, &mut board), 97862);
-        assert_eq!(count_moves(4, &mut board), 4085603);
+        let perft = Perft::new(&PerftOptions::default());
+        assert_eq!(perft.count(&board, 0), 1);
+        assert_eq!(perft.count(&board, 1), 48);
+        assert_eq!(perft.count(&board, 2), 2039);
+        assert_eq!(perft.count(&board, 3), 97862);
+        assert_eq!(perft.count(&board, 4), 4085603);
     }
 
     #[test] //Making sure the number of moves is correct
@@ -549,11 +610,12 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(count_moves(0, &mut board), 1);
-        assert_eq!(count_moves(1, &mut board), 14);
-        assert_eq!(count_moves(2, &mut board), 191);
-        assert_eq!(count_moves(3, &mut board), 2812);
-        assert_eq!(count_moves(4, &mut board), 43238);
+        let perft = Perft::new(&PerftOptions::default());
+        assert_eq!(perft.count(&board, 0), 1);
+        assert_eq!(perft.count(&board, 1), 14);
+        assert_eq!(perft.count(&board, 2), 191);
+        assert_eq!(perft.count(&board, 3), 2812);
+        assert_eq!(perft.count(&board, 4), 43238);
     }
 
     #[test] //Making sure the number of moves is correct
@@ -567,12 +629,13 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(count_moves(0, &mut board), 1);
-        assert_eq!(count_moves(1, &mut board), 6);
-        assert_eq!(count_moves(2, &mut board), 264);
-        assert_eq!(count_moves(3, &mut board), 9467);
-        assert_eq!(count_moves(4, &mut board), 422333);
-        assert_eq!(count_moves(5, &mut board), 15833292);
+        let perft = Perft::new(&PerftOptions::default());
+        assert_eq!(perft.count(&board, 0), 1);
+        assert_eq!(perft.count(&board, 1), 6);
+        assert_eq!(perft.count(&board, 2), 264);
+        assert_eq!(perft.count(&board, 3), 9467);
+        assert_eq!(perft.count(&board, 4), 422333);
+        assert_eq!(perft.count(&board, 5), 15833292);
     }
 
     #[test] //Making sure the number of moves is correct
@@ -586,11 +649,12 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(count_moves(0, &mut board), 1);
-        assert_eq!(count_moves(1, &mut board), 44);
-        assert_eq!(count_moves(2, &mut board), 1486);
-        assert_eq!(count_moves(3, &mut board), 62379);
-        assert_eq!(count_moves(4, &mut board), 2103487);
+        let perft = Perft::new(&PerftOptions::default());
+        assert_eq!(perft.count(&board, 0), 1);
+        assert_eq!(perft.count(&board, 1), 44);
+        assert_eq!(perft.count(&board, 2), 1486);
+        assert_eq!(perft.count(&board, 3), 62379);
+        assert_eq!(perft.count(&board, 4), 2103487);
     }
 
     #[test] //Making sure the number of moves is correct
@@ -605,10 +669,18 @@ mod tests {
             Err(e) => panic!("Error: {}", e),
         }
 
-        assert_eq!(count_moves(0, &mut board), 1);
-        assert_eq!(count_moves(1, &mut board), 46);
-        assert_eq!(count_moves(2, &mut board), 2079);
-        assert_eq!(count_moves(3, &mut board), 89890);
-        assert_eq!(count_moves(4, &mut board), 3894594);
+        let perft = Perft::new(&PerftOptions::default());
+        assert_eq!(perft.count(&board, 0), 1);
+        assert_eq!(perft.count(&board, 1), 46);
+        assert_eq!(perft.count(&board, 2), 2079);
+        assert_eq!(perft.count(&board, 3), 89890);
+        assert_eq!(perft.count(&board, 4), 3894594);
+    }
+
+    #[test]
+    fn castle_rights_of_the_other_side() {
+        //Black can still castle long, white's king in the corner must not look three squares to its left
+        let board = BoardState::new("r3k3/p7/8/8/8/8/8/K7 w q - 0 1").unwrap();
+        assert_eq!(gen_all_moves(&board, Color::White).len(), 3);
     }
 }
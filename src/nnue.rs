@@ -0,0 +1,302 @@
+use crate::color::Color;
+use crate::piece::{Piece, PieceType};
+use crate::square::Square;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/*
+    Small NNUE style evaluation, see https://www.chessprogramming.org/NNUE
+    768 inputs (color, piece type and square) feed HIDDEN_SIZE neurons through a clipped ReLU, and those feed a single output.
+    The hidden layer, the accumulator, lives on the board and is updated as pieces move instead of being recomputed.
+    Everything is integer arithmetic, weights are quantised to i16 and summed as i32 so no network can overflow the accumulator.
+*/
+
+pub const INPUT_SIZE: usize = 768;
+pub const HIDDEN_SIZE: usize = 64;
+const QA: i32 = 255; //Hidden layer quantisation, also where the clipped ReLU clips
+const QB: i32 = 64; //Output weight quantisation
+const OUTPUT_SCALE: i32 = 400; //Output units to centipawns
+const MAGIC: &[u8; 4] = b"RBNN";
+
+/*
+ * Network file layout, all little endian:
+ * "RBNN", hidden size as u32, feature weights as i16 (INPUT_SIZE rows of HIDDEN_SIZE), feature biases as i16,
+ * output weights as i16, then the output bias as i32
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct Network {
+    pub feature_weights: Vec<[i16; HIDDEN_SIZE]>,
+    pub feature_biases: [i16; HIDDEN_SIZE],
+    pub output_weights: [i16; HIDDEN_SIZE],
+    pub output_bias: i32,
+}
+
+impl Network {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, String> {
+        let expected = 4 + 4 + 2 * (INPUT_SIZE * HIDDEN_SIZE + 2 * HIDDEN_SIZE) + 4;
+        if bytes.len() < 8 || &bytes[0..4] != MAGIC {
+            return Err("not a ReeseBot network file".to_string());
+        }
+        let hidden_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        if hidden_size != HIDDEN_SIZE {
+            return Err(format!(
+                "network has {} hidden neurons, this build expects {}",
+                hidden_size, HIDDEN_SIZE
+            ));
+        }
+        if bytes.len() != expected {
+            return Err(format!(
+                "network file is {} bytes, expected {}",
+                bytes.len(),
+                expected
+            ));
+        }
+
+        let mut values = bytes[8..expected - 4]
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]));
+        let mut next_row = || -> [i16; HIDDEN_SIZE] {
+            let mut row = [0; HIDDEN_SIZE];
+            row.iter_mut()
+                .for_each(|value| *value = values.next().unwrap());
+            row
+        };
+        let feature_weights = (0..INPUT_SIZE).map(|_| next_row()).collect();
+        let feature_biases = next_row();
+        let output_weights = next_row();
+        let output_bias = i32::from_le_bytes(bytes[expected - 4..].try_into().unwrap());
+
+        Ok(Network {
+            feature_weights,
+            feature_biases,
+            output_weights,
+            output_bias,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend((HIDDEN_SIZE as u32).to_le_bytes());
+        for row in self
+            .feature_weights
+            .iter()
+            .chain([&self.feature_biases, &self.output_weights])
+        {
+            for value in row {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        bytes.extend(self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    pub fn load(path: &Path) -> Result<Network, String> {
+        let bytes =
+            fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Network::from_bytes(&bytes)
+    }
+}
+
+//Set once at startup, boards made after that carry an accumulator for it
+static NETWORK: OnceLock<Network> = OnceLock::new();
+
+pub fn network() -> Option<&'static Network> {
+    NETWORK.get()
+}
+
+/* Switches evaluation over to the network, only works before the first board has been made */
+pub fn set_network(network: Network) -> Result<(), &'static str> {
+    NETWORK
+        .set(network)
+        .map_err(|_| "a network is already loaded")
+}
+
+//Row and col are indices into the 12x12 board
+fn feature_index(piece: Piece, row: usize, col: usize) -> usize {
+    let color = match piece.color {
+        Color::White => 0,
+        Color::Black => 1,
+    };
+    let piece_type = match piece.piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+        PieceType::None => panic!("something went wrong"),
+    };
+    color * 384 + piece_type * 64 + (row - 2) * 8 + (col - 2)
+}
+
+/* Hidden layer values for a position, updated a piece at a time as moves are made */
+#[derive(Clone, Copy)]
+pub struct Accumulator {
+    pub values: [i32; HIDDEN_SIZE],
+    network: &'static Network,
+}
+
+impl Accumulator {
+    //Full refresh from the squares of a board
+    pub fn new(network: &'static Network, squares: &[[Square; 12]; 12]) -> Accumulator {
+        let mut accumulator = Accumulator {
+            values: network.feature_biases.map(i32::from),
+            network,
+        };
+        for row in 2..10 {
+            for col in 2..10 {
+                if let Some(piece) = squares[row][col].piece {
+                    accumulator.add_piece(piece, row, col);
+                }
+            }
+        }
+        accumulator
+    }
+
+    pub fn add_piece(&mut self, piece: Piece, row: usize, col: usize) {
+        let weights = &self.network.feature_weights[feature_index(piece, row, col)];
+        for (value, weight) in self.values.iter_mut().zip(weights) {
+            *value += *weight as i32;
+        }
+    }
+
+    pub fn remove_piece(&mut self, piece: Piece, row: usize, col: usize) {
+        let weights = &self.network.feature_weights[feature_index(piece, row, col)];
+        for (value, weight) in self.values.iter_mut().zip(weights) {
+            *value -= *weight as i32;
+        }
+    }
+
+    /* The network's score in centipawns, from the side to move's point of view */
+    pub fn evaluate(&self, active_color: Color) -> i32 {
+        let output: i32 = self
+            .values
+            .iter()
+            .zip(self.network.output_weights.iter())
+            .map(|(&value, &weight)| value.clamp(0, QA) * weight as i32)
+            .sum();
+        //Widened since a large output bias could overflow once scaled
+        let white_score = ((output as i64 + self.network.output_bias as i64) * OUTPUT_SCALE as i64
+            / (QA * QB) as i64) as i32;
+        match active_color {
+            Color::White => white_score,
+            Color::Black => -white_score,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board_state::BoardState;
+    use crate::evaluation::{evaluate, evaluate_traced};
+    use crate::move_gen::gen_all_moves;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_network(seed: u64) -> &'static Network {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut random_row = || -> [i16; HIDDEN_SIZE] {
+            let mut row = [0; HIDDEN_SIZE];
+            row.iter_mut()
+                .for_each(|value| *value = rng.gen_range(-40..40));
+            row
+        };
+        let network = Network {
+            feature_weights: (0..INPUT_SIZE).map(|_| random_row()).collect(),
+            feature_biases: random_row(),
+            output_weights: random_row(),
+            output_bias: 1000,
+        };
+        Box::leak(Box::new(network))
+    }
+
+    #[test]
+    fn network_file_round_trip() {
+        let network = random_network(1);
+        let bytes = network.to_bytes();
+        assert_eq!(Network::from_bytes(&bytes).as_ref(), Ok(network));
+
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Network::from_bytes(b"NOPE").is_err());
+        let mut wrong_size = bytes.clone();
+        wrong_size[4] = 32;
+        assert!(Network::from_bytes(&wrong_size).is_err());
+    }
+
+    #[test]
+    fn accumulator_updates_match_refresh() {
+        let network = random_network(2);
+
+        //Castles, en passant, promotions and captures, two plies deep
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/pppppppp/8/4P3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2",
+        ] {
+            let mut board = BoardState::new(fen).unwrap();
+            board.accumulator = Some(Accumulator::new(network, &board.squares));
+            for mv in gen_all_moves(&board, board.active_color) {
+                let mut board_after = board.clone();
+                board_after.make_move(&mv);
+                for reply in gen_all_moves(&board_after, board_after.active_color) {
+                    let mut board_reply = board_after.clone();
+                    board_reply.make_move(&reply);
+                    assert_eq!(
+                        board_reply.accumulator.unwrap().values,
+                        Accumulator::new(network, &board_reply.squares).values
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn extreme_weights_do_not_overflow() {
+        let network: &'static Network = Box::leak(Box::new(Network {
+            feature_weights: vec![[i16::MAX; HIDDEN_SIZE]; INPUT_SIZE],
+            feature_biases: [i16::MAX; HIDDEN_SIZE],
+            output_weights: [i16::MAX; HIDDEN_SIZE],
+            output_bias: i32::MAX,
+        }));
+        let mut board =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        board.accumulator = Some(Accumulator::new(network, &board.squares));
+        assert_eq!(board.accumulator.unwrap().values[0], 33 * i16::MAX as i32);
+
+        let mv = gen_all_moves(&board, board.active_color)[0];
+        board.make_move(&mv);
+        assert_eq!(board.accumulator.unwrap().values[0], 33 * i16::MAX as i32);
+        assert!(evaluate(&board) < 0);
+    }
+
+    #[test]
+    fn evaluates_with_network_when_loaded() {
+        let network = random_network(3);
+        let fen = "r2qkb1r/1p1n1pp1/p2p1n2/3bp2p/4P3/1N2BP2/PPPQ2PP/R3KB1R w KQkq - 0 1";
+        let mut board = BoardState::new(fen).unwrap();
+
+        //No network, the piece square evaluation
+        assert!(board.accumulator.is_none());
+        assert_eq!(evaluate(&board), -384);
+
+        board.accumulator = Some(Accumulator::new(network, &board.squares));
+        let accumulator = board.accumulator.unwrap();
+        let expected: i32 = accumulator
+            .values
+            .iter()
+            .zip(network.output_weights.iter())
+            .map(|(&value, &weight)| value.clamp(0, QA) * weight as i32)
+            .sum::<i32>()
+            + network.output_bias;
+        assert_eq!(evaluate(&board), expected * OUTPUT_SCALE / (QA * QB));
+        assert_eq!(accumulator.evaluate(Color::Black), -evaluate(&board));
+
+        //The trace shows the network's score as the one used
+        let trace = evaluate_traced(&board);
+        assert_eq!(trace.network, Some(evaluate(&board)));
+        assert_eq!(trace.score, evaluate(&board));
+        assert_eq!(trace.tables, -384);
+    }
+}
@@ -0,0 +1,334 @@
+use crate::board_state::BoardState;
+use crate::chess_move::Move;
+use crate::move_gen::gen_all_moves;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
+/*
+    Counting every line of play to a fixed depth, the counts are known for many positions so they catch move generation bugs. See https://www.chessprogramming.org/Perft
+    The last ply is bulk counted from the length of the move list, subtree counts are cached by zobrist key and root moves are shared out between threads.
+*/
+
+pub const DEFAULT_PERFT_HASH_MB: usize = 64;
+
+pub struct PerftOptions {
+    pub threads: usize,
+    pub hash_mb: usize, //0 turns the table off
+}
+
+impl Default for PerftOptions {
+    fn default() -> PerftOptions {
+        PerftOptions {
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            hash_mb: DEFAULT_PERFT_HASH_MB,
+        }
+    }
+}
+
+/*
+ * Subtree counts shared between the threads without locking. An entry holds the key xored with the data next to the data,
+ * a torn write from two threads racing on the same slot no longer matches its key and is ignored. See https://www.chessprogramming.org/Shared_Hash_Table#Lockless
+ */
+struct PerftTable {
+    entries: Vec<[AtomicU64; 2]>,
+}
+
+impl PerftTable {
+    fn new(hash_mb: usize) -> PerftTable {
+        let size = (hash_mb << 20) / std::mem::size_of::<[AtomicU64; 2]>();
+        PerftTable {
+            entries: (0..size.next_power_of_two())
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    //Counts to different depths of one position get different slots
+    fn slot(&self, key: u64, depth: u16) -> &[AtomicU64; 2] {
+        let index = (key ^ (depth as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)) as usize;
+        &self.entries[index & (self.entries.len() - 1)]
+    }
+
+    fn probe(&self, key: u64, depth: u16) -> Option<u64> {
+        let slot = self.slot(key, depth);
+        let check = slot[0].load(Ordering::Relaxed);
+        let data = slot[1].load(Ordering::Relaxed);
+        if check ^ data == key && data & 0xff == depth as u64 {
+            Some(data >> 8)
+        } else {
+            None
+        }
+    }
+
+    fn store(&self, key: u64, depth: u16, nodes: u64) {
+        let slot = self.slot(key, depth);
+        let data = nodes << 8 | depth as u64;
+        slot[0].store(key ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+}
+
+pub struct Perft {
+    threads: usize,
+    table: Option<PerftTable>,
+}
+
+impl Perft {
+    pub fn new(options: &PerftOptions) -> Perft {
+        Perft {
+            threads: options.threads.max(1),
+            table: (options.hash_mb > 0).then(|| PerftTable::new(options.hash_mb)),
+        }
+    }
+
+    pub fn count(&self, board: &BoardState, depth: u16) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        self.divide(board, depth)
+            .iter()
+            .map(|(_, nodes)| nodes)
+            .sum()
+    }
+
+    /* Perft split by root move, comparing it against another engine's points straight at the broken move */
+    pub fn divide(&self, board: &BoardState, depth: u16) -> Vec<(Move, u64)> {
+        let moves = gen_all_moves(board, board.active_color);
+        let counts: Vec<AtomicU64> = moves.iter().map(|_| AtomicU64::new(0)).collect();
+        let next = AtomicUsize::new(0);
+
+        //Each thread takes the next root move nobody has started on until they run out
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(moves.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(mv) = moves.get(index) else {
+                        break;
+                    };
+                    let mut board_after = board.clone();
+                    board_after.make_move(mv);
+                    counts[index].store(
+                        self.subtree(&board_after, depth.saturating_sub(1)),
+                        Ordering::Relaxed,
+                    );
+                });
+            }
+        });
+
+        moves
+            .into_iter()
+            .zip(counts)
+            .map(|(mv, nodes)| (mv, nodes.into_inner()))
+            .collect()
+    }
+
+    fn subtree(&self, board: &BoardState, depth: u16) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = gen_all_moves(board, board.active_color);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        let key = board.zobrist_key();
+        if let Some(nodes) = self
+            .table
+            .as_ref()
+            .and_then(|table| table.probe(key, depth))
+        {
+            return nodes;
+        }
+        let mut nodes = 0;
+        for mv in moves {
+            let mut board_after = board.clone();
+            board_after.make_move(&mv);
+            nodes += self.subtree(&board_after, depth - 1);
+        }
+        if let Some(table) = &self.table {
+            table.store(key, depth, nodes);
+        }
+        nodes
+    }
+}
+
+/* A one off count. The table is sized for the depth, a shallow count would spend longer clearing the default one than counting */
+pub fn perft(board: &BoardState, depth: u16) -> u64 {
+    let options = PerftOptions {
+        hash_mb: perft_hash_mb(depth),
+        ..PerftOptions::default()
+    };
+    Perft::new(&options).count(board, depth)
+}
+
+//The last two plies never reach the table, and each ply deeper needs about four times the entries
+fn perft_hash_mb(depth: u16) -> usize {
+    match depth {
+        0..=2 => 0,
+        _ => DEFAULT_PERFT_HASH_MB.min(1 << (2 * (depth as usize - 3)).min(16)),
+    }
+}
+
+/* A position of a perft suite with the counts it should have at each depth */
+pub struct PerftCase {
+    pub fen: String,
+    pub expected: Vec<(u16, u64)>,
+}
+
+/*
+ * Reads an EPD perft suite, one position per line followed by its counts
+ * rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - ;D1 20 ;D2 400 ;D3 8902
+ * Blank lines and lines starting with # are skipped
+ */
+pub fn parse_suite(text: &str) -> Result<Vec<PerftCase>, String> {
+    let mut cases = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split(';');
+        let position = fields.next().unwrap_or("").trim();
+        //EPD leaves out the move clocks
+        let fen = match position.split_whitespace().count() {
+            4 => format!("{} 0 1", position),
+            6 => position.to_string(),
+            _ => {
+                return Err(format!(
+                    "line {}: {} is not a position",
+                    index + 1,
+                    position
+                ))
+            }
+        };
+
+        let mut expected = Vec::new();
+        for field in fields {
+            let field = field.trim();
+            let count = field
+                .strip_prefix('D')
+                .and_then(|field| field.split_once(char::is_whitespace))
+                .and_then(|(depth, nodes)| Some((depth.parse().ok()?, nodes.trim().parse().ok()?)));
+            match count {
+                Some(count) => expected.push(count),
+                None => {
+                    return Err(format!(
+                        "line {}: {} is not a count like D1 20",
+                        index + 1,
+                        field
+                    ))
+                }
+            }
+        }
+        cases.push(PerftCase { fen, expected });
+    }
+    Ok(cases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divide_adds_up_to_perft() {
+        let board =
+            BoardState::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let divided = Perft::new(&PerftOptions::default()).divide(&board, 2);
+        assert_eq!(divided.len(), 48);
+        assert_eq!(divided.iter().map(|(_, nodes)| nodes).sum::<u64>(), 2039);
+
+        let names: Vec<String> = divided
+            .iter()
+            .map(|(mv, _)| mv.to_uci(board.active_color))
+            .collect();
+        assert!(names.contains(&"e1g1".to_string()));
+        assert!(names.contains(&"e1c1".to_string()));
+    }
+
+    #[test]
+    fn sizes_the_table_for_the_depth() {
+        assert_eq!(perft_hash_mb(2), 0);
+        assert_eq!(perft_hash_mb(3), 1);
+        assert_eq!(perft_hash_mb(5), 16);
+        assert_eq!(perft_hash_mb(7), DEFAULT_PERFT_HASH_MB);
+        assert_eq!(perft_hash_mb(u16::MAX), DEFAULT_PERFT_HASH_MB);
+    }
+
+    #[test]
+    fn counts_en_passant_from_fen() {
+        //Reaching the position by playing d4 has to count the same as reading the en passant square from the fen
+        let mut played = BoardState::new("8/8/8/2k5/2p5/8/B2P4/4K3 w - - 0 1").unwrap();
+        let push = gen_all_moves(&played, played.active_color)
+            .into_iter()
+            .find(|mv| mv.to_string() == "d2d4")
+            .unwrap();
+        played.make_move(&push);
+        let read = BoardState::new("8/8/8/2k5/2pP4/8/B7/4K3 b - d3 0 1").unwrap();
+        assert_eq!(read.en_passant, played.en_passant);
+        assert_eq!(perft(&read, 3), perft(&played, 3));
+
+        let cases = parse_suite(
+            "# After 1.e4\n\
+             \n\
+             rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 ;D1 20 ;D2 600 ;D3 13160",
+        )
+        .unwrap();
+        assert_eq!(cases.len(), 1);
+        let board = BoardState::new(&cases[0].fen).unwrap();
+        for &(depth, nodes) in &cases[0].expected {
+            assert_eq!(perft(&board, depth), nodes);
+        }
+    }
+
+    //The plain clone per node count, nothing clever that could hide a bug
+    fn count_plainly(board: &BoardState, depth: u16) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        gen_all_moves(board, board.active_color)
+            .iter()
+            .map(|mv| {
+                let mut board_after = board.clone();
+                board_after.make_move(mv);
+                count_plainly(&board_after, depth - 1)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn matches_plain_count() {
+        //One thread and a tiny table so entries get overwritten, then several threads sharing it
+        for options in [
+            PerftOptions {
+                threads: 1,
+                hash_mb: 0,
+            },
+            PerftOptions {
+                threads: 1,
+                hash_mb: 1,
+            },
+            PerftOptions {
+                threads: 4,
+                hash_mb: 1,
+            },
+        ] {
+            let perft = Perft::new(&options);
+            for fen in [
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            ] {
+                let board = BoardState::new(fen).unwrap();
+                assert_eq!(perft.count(&board, 3), count_plainly(&board, 3), "{}", fen);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_suites() {
+        assert!(parse_suite("8/8/8/8 ;D1 1").is_err());
+        assert!(parse_suite("8/8/8/2k5/2pP4/8/B7/4K3 b - d3 ;D1 eight").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::color::Color;
+use crate::self_play::GameRecord;
+use crate::uci::START_FEN;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+    Writes finished games in Portable Game Notation, see https://www.chessprogramming.org/Portable_Game_Notation
+*/
+
+//Movetext lines are wrapped before this many characters
+const LINE_WIDTH: usize = 80;
+
+/* Today's date the way the Date tag wants it, 2024.03.09 */
+pub fn pgn_date(time: SystemTime) -> String {
+    //Days since 1970 to a civil date, see https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / 86400) as i64
+        + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/*
+ * One game as PGN text ending in a blank line, so games can be appended to a file one after another.
+ * tags are written in the order given and should start with the seven tag roster. A game that didn't start
+ * from the usual position gets SetUp and FEN tags, and the reason it ended goes in a comment before the result.
+ */
+pub fn to_pgn(tags: &[(&str, String)], record: &GameRecord) -> String {
+    let mut text = String::new();
+    for (name, value) in tags {
+        text.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+    }
+    if record.start_fen != START_FEN {
+        text.push_str("[SetUp \"1\"]\n");
+        text.push_str(&format!("[FEN \"{}\"]\n", record.start_fen));
+    }
+    text.push('\n');
+
+    let fields: Vec<&str> = record.start_fen.split_whitespace().collect();
+    let mut color = if fields.get(1) == Some(&"b") {
+        Color::Black
+    } else {
+        Color::White
+    };
+    let mut move_number: u32 = fields
+        .get(5)
+        .and_then(|field| field.parse().ok())
+        .unwrap_or(1);
+    let mut tokens = Vec::new();
+    for (index, san) in record.moves.iter().enumerate() {
+        match color {
+            Color::White => tokens.push(format!("{}. {}", move_number, san)),
+            Color::Black if index == 0 => tokens.push(format!("{}... {}", move_number, san)),
+            Color::Black => tokens.push(san.clone()),
+        }
+        if color == Color::Black {
+            move_number += 1;
+        }
+        color = color.opposite();
+    }
+    tokens.push(format!("{{{}}}", record.reason));
+    tokens.push(record.result.to_pgn().to_string());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+            text.push_str(&line);
+            text.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    text.push_str(&line);
+    text.push_str("\n\n");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::self_play::GameResult;
+    use std::time::Duration;
+
+    #[test]
+    fn writes_games() {
+        assert_eq!(pgn_date(UNIX_EPOCH), "1970.01.01");
+        assert_eq!(
+            pgn_date(UNIX_EPOCH + Duration::from_secs(1_709_942_400)),
+            "2024.03.09"
+        );
+
+        let record = GameRecord {
+            start_fen: "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1".to_string(),
+            moves: ["e5", "Bc4", "Nc6", "Qh5", "Nf6", "Qxf7#"]
+                .iter()
+                .map(|san| san.to_string())
+                .collect(),
+            result: GameResult::WhiteWins,
+            reason: "checkmate",
+        };
+        let pgn = to_pgn(
+            &[
+                ("Event", "Test \"quoted\"".to_string()),
+                ("Result", record.result.to_pgn().to_string()),
+            ],
+            &record,
+        );
+        assert_eq!(
+            pgn,
+            "[Event \"Test \\\"quoted\\\"\"]\n\
+             [Result \"1-0\"]\n\
+             [SetUp \"1\"]\n\
+             [FEN \"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\"]\n\
+             \n\
+             1... e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# {checkmate} 1-0\n\n"
+        );
+    }
+}
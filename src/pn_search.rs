@@ -0,0 +1,477 @@
+use crate::board_state::BoardState;
+use crate::chess_move::Move;
+use crate::color::Color;
+use crate::evaluator::{Evaluator, MaterialEvaluator};
+use crate::move_gen::gen_all_moves;
+
+/*
+    Proof-number search, see https://www.chessprogramming.org/Proof-Number_Search
+    Proves or disproves that the side to move at the root (the attacker) can force a goal, mate or winning material.
+    The tree keeps expanding the most proving node, the one that would take the fewest expansions to settle the root.
+    PN² runs a smaller proof-number search at every node it expands and keeps only the numbers it found for the children,
+    which lets it get much further on the same memory.
+*/
+
+const INFINITY: u32 = u32::MAX;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Goal {
+    Mate,
+    //Centipawns of material the attacker has to come out ahead by, counted when it is the attacker's move
+    Material(i32),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PnOptions {
+    pub goal: Goal,
+    //Nodes created, including those of PN² sub-searches
+    pub node_budget: u64,
+    //Lines longer than this count as failures for the attacker
+    pub max_plies: u16,
+    pub pn2: bool,
+}
+
+impl Default for PnOptions {
+    fn default() -> PnOptions {
+        PnOptions {
+            goal: Goal::Mate,
+            node_budget: 1_000_000,
+            max_plies: 15,
+            pn2: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Proven,
+    Disproven,
+    Unknown, //Ran out of nodes
+}
+
+/* The attacker's moves, each with every defence to it, down to where the goal is reached */
+#[derive(Clone, PartialEq, Debug)]
+pub struct ProofTree {
+    pub mv: Move,
+    pub replies: Vec<ProofTree>,
+}
+
+impl ProofTree {
+    //Plies in the longest line of the tree
+    pub fn depth(&self) -> usize {
+        1 + self
+            .replies
+            .iter()
+            .map(|reply| reply.depth())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+pub struct PnResult {
+    pub outcome: Outcome,
+    pub nodes: u64,
+    //Attacker moves that prove the goal at the root, with all of the defender's answers below them, empty unless proven
+    pub proof_tree: Vec<ProofTree>,
+    //Longest resistance through the proof tree, empty unless proven
+    pub main_line: Vec<Move>,
+}
+
+#[derive(Clone)]
+struct Node {
+    mv: Option<Move>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    expanded: bool,
+    proof: u32,
+    disproof: u32,
+}
+
+impl Node {
+    fn new(mv: Option<Move>, parent: Option<usize>, (proof, disproof): (u32, u32)) -> Node {
+        Node {
+            mv,
+            parent,
+            children: Vec::new(),
+            expanded: false,
+            proof,
+            disproof,
+        }
+    }
+}
+
+struct Tree<'a> {
+    options: &'a PnOptions,
+    attacker: Color,
+    root_material: i32, //From the attacker's point of view
+    nodes: Vec<Node>,
+}
+
+fn material_for(board: &BoardState, color: Color) -> i32 {
+    let score = MaterialEvaluator.evaluate(board);
+    if board.active_color == color {
+        score
+    } else {
+        -score
+    }
+}
+
+fn numbers(solved: Option<bool>) -> (u32, u32) {
+    match solved {
+        Some(true) => (0, INFINITY),
+        Some(false) => (INFINITY, 0),
+        None => (1, 1),
+    }
+}
+
+impl<'a> Tree<'a> {
+    fn new(
+        options: &'a PnOptions,
+        root: &BoardState,
+        attacker: Color,
+        root_material: i32,
+    ) -> Tree<'a> {
+        let mut tree = Tree {
+            options,
+            attacker,
+            root_material,
+            nodes: Vec::new(),
+        };
+        let solved = tree.solved(root, &[root.zobrist_key()], 0);
+        tree.nodes.push(Node::new(None, None, numbers(solved)));
+        tree
+    }
+
+    /* Whether a position already settles the goal, keys holds the line from the root including this position */
+    fn solved(&self, board: &BoardState, keys: &[u64], ply: u16) -> Option<bool> {
+        let attacker_to_move = board.active_color == self.attacker;
+        if gen_all_moves(board, board.active_color).is_empty() {
+            //Mating the defender proves any goal, anything else ends the game without it
+            return Some(!attacker_to_move && board.is_in_check(board.active_color, None));
+        }
+        if let Goal::Material(gain) = self.options.goal {
+            if attacker_to_move && material_for(board, self.attacker) - self.root_material >= gain {
+                return Some(true);
+            }
+        }
+        let (key, earlier) = keys.split_last().unwrap();
+        if board.is_fifty_move_draw()
+            || board.is_insufficient_material()
+            || earlier.contains(key)
+            || ply >= self.options.max_plies
+        {
+            return Some(false);
+        }
+        None
+    }
+
+    fn is_or_node(&self, board: &BoardState) -> bool {
+        board.active_color == self.attacker
+    }
+
+    /*
+     * Searches until the root is settled or the budget is used up, then returns the nodes used.
+     * Boards are not stored in the tree, every iteration plays the moves from the root again
+     */
+    fn run(&mut self, root: &BoardState, budget: u64) -> u64 {
+        let mut used = 0;
+        while self.nodes[0].proof != 0 && self.nodes[0].disproof != 0 && used < budget {
+            //Down to the most proving node
+            let mut board = root.clone();
+            let mut keys = vec![board.zobrist_key()];
+            let mut index = 0;
+            while self.nodes[index].expanded {
+                let or_node = self.is_or_node(&board);
+                index = *self.nodes[index]
+                    .children
+                    .iter()
+                    .min_by_key(|&&child| {
+                        if or_node {
+                            self.nodes[child].proof
+                        } else {
+                            self.nodes[child].disproof
+                        }
+                    })
+                    .unwrap();
+                board.make_move(&self.nodes[index].mv.unwrap());
+                keys.push(board.zobrist_key());
+            }
+
+            used += self.expand(index, &board, &keys, budget - used);
+            self.update_ancestors(index, &board);
+        }
+        used
+    }
+
+    //Adds the children of a node, returns the nodes used doing so
+    fn expand(&mut self, index: usize, board: &BoardState, keys: &[u64], budget: u64) -> u64 {
+        if !self.options.pn2 {
+            return self.expand_plain(index, board, keys);
+        }
+
+        //A smaller search from this node decides the children's numbers, only proven lines are kept whole
+        let ply = keys.len() as u16 - 1;
+        let sub_options = PnOptions {
+            max_plies: self.options.max_plies - ply,
+            pn2: false,
+            ..*self.options
+        };
+        let mut sub_tree = Tree::new(&sub_options, board, self.attacker, self.root_material);
+        let sub_budget = (self.nodes.len() as u64).clamp(1, budget.max(1));
+        let mut used = sub_tree.run(board, sub_budget);
+
+        for &child in &sub_tree.nodes[0].children {
+            //The sub-search can't see the line above it, so going back to a position on it is checked here
+            let mv = sub_tree.nodes[child].mv;
+            let mut board_copy = board.clone();
+            board_copy.make_move(&mv.unwrap());
+            if keys.contains(&board_copy.zobrist_key()) {
+                self.add_child(index, mv, numbers(Some(false)));
+            } else {
+                self.copy_from(&sub_tree, child, index);
+            }
+            used += 1;
+        }
+        self.nodes[index].expanded = true;
+        used
+    }
+
+    fn expand_plain(&mut self, index: usize, board: &BoardState, keys: &[u64]) -> u64 {
+        let ply = keys.len() as u16;
+        let moves = gen_all_moves(board, board.active_color);
+        let count = moves.len() as u64;
+        let mut child_keys = keys.to_vec();
+        for mv in moves {
+            let mut board_copy = board.clone();
+            board_copy.make_move(&mv);
+            child_keys.push(board_copy.zobrist_key());
+            let solved = self.solved(&board_copy, &child_keys, ply);
+            child_keys.pop();
+            self.add_child(index, Some(mv), numbers(solved));
+        }
+        self.nodes[index].expanded = true;
+        count
+    }
+
+    fn add_child(&mut self, parent: usize, mv: Option<Move>, numbers: (u32, u32)) -> usize {
+        self.nodes.push(Node::new(mv, Some(parent), numbers));
+        let child = self.nodes.len() - 1;
+        self.nodes[parent].children.push(child);
+        child
+    }
+
+    //Brings a child of the sub-search root over, along with everything below it when it is proven
+    fn copy_from(&mut self, sub_tree: &Tree, sub_index: usize, parent: usize) -> usize {
+        let sub_node = &sub_tree.nodes[sub_index];
+        let index = self.add_child(parent, sub_node.mv, (sub_node.proof, sub_node.disproof));
+        if sub_node.proof == 0 && sub_node.expanded {
+            for &child in &sub_node.children {
+                self.copy_from(sub_tree, child, index);
+            }
+            self.nodes[index].expanded = true;
+        }
+        index
+    }
+
+    //Proof and disproof numbers from the children's, board is the position at index and the sides alternate going up
+    fn update_ancestors(&mut self, index: usize, board: &BoardState) {
+        let mut or_node = self.is_or_node(board);
+        let mut current = Some(index);
+        while let Some(index) = current {
+            let children = &self.nodes[index].children;
+            if !children.is_empty() {
+                let proofs = children.iter().map(|&child| self.nodes[child].proof);
+                let disproofs = children.iter().map(|&child| self.nodes[child].disproof);
+                let (proof, disproof) = if or_node {
+                    (
+                        proofs.min().unwrap(),
+                        disproofs.fold(0, u32::saturating_add),
+                    )
+                } else {
+                    (
+                        proofs.fold(0, u32::saturating_add),
+                        disproofs.min().unwrap(),
+                    )
+                };
+                self.nodes[index].proof = proof;
+                self.nodes[index].disproof = disproof;
+            }
+            or_node = !or_node;
+            current = self.nodes[index].parent;
+        }
+    }
+
+    fn proof_tree(&self, index: usize, or_node: bool) -> Vec<ProofTree> {
+        let proven = self.nodes[index]
+            .children
+            .iter()
+            .filter(|&&child| self.nodes[child].proof == 0);
+        let to_tree = |child: usize| ProofTree {
+            mv: self.nodes[child].mv.unwrap(),
+            replies: self.proof_tree(child, !or_node),
+        };
+        if or_node {
+            //One winning move is enough, the quickest one
+            proven
+                .map(|&child| to_tree(child))
+                .min_by_key(|tree| tree.depth())
+                .into_iter()
+                .collect()
+        } else {
+            proven.map(|&child| to_tree(child)).collect()
+        }
+    }
+}
+
+fn main_line(trees: &[ProofTree]) -> Vec<Move> {
+    match trees.iter().max_by_key(|tree| tree.depth()) {
+        Some(tree) => {
+            let mut line = vec![tree.mv];
+            line.extend(main_line(&tree.replies));
+            line
+        }
+        None => Vec::new(),
+    }
+}
+
+/* Tries to prove the goal for the side to move */
+pub fn prove(board: &BoardState, options: &PnOptions) -> PnResult {
+    let attacker = board.active_color;
+    let root_material = material_for(board, attacker);
+    let mut tree = Tree::new(options, board, attacker, root_material);
+    let nodes = tree.run(board, options.node_budget);
+
+    let outcome = match (tree.nodes[0].proof, tree.nodes[0].disproof) {
+        (0, _) => Outcome::Proven,
+        (_, 0) => Outcome::Disproven,
+        _ => Outcome::Unknown,
+    };
+    let proof_tree = if outcome == Outcome::Proven {
+        tree.proof_tree(0, true)
+    } else {
+        Vec::new()
+    };
+    PnResult {
+        outcome,
+        nodes,
+        main_line: main_line(&proof_tree),
+        proof_tree,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prove_fen(fen: &str, options: PnOptions) -> PnResult {
+        prove(&BoardState::new(fen).unwrap(), &options)
+    }
+
+    //Plays the main line out and checks the goal is met at the end of it
+    fn play_main_line(fen: &str, result: &PnResult) -> BoardState {
+        let mut board = BoardState::new(fen).unwrap();
+        for mv in &result.main_line {
+            assert!(gen_all_moves(&board, board.active_color).contains(mv));
+            board.make_move(mv);
+        }
+        board
+    }
+
+    #[test]
+    fn proves_mate_in_one() {
+        let fen = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+        for pn2 in [false, true] {
+            let result = prove_fen(
+                fen,
+                PnOptions {
+                    pn2,
+                    ..PnOptions::default()
+                },
+            );
+            assert_eq!(result.outcome, Outcome::Proven);
+            assert_eq!(result.main_line.len(), 1);
+            assert_eq!(result.main_line[0].to_string(), "a1a8");
+        }
+    }
+
+    #[test]
+    fn proves_longer_mate() {
+        //Mate in two, the king can only step out of the corner and back
+        let fen = "7k/8/5K2/8/8/8/8/6R1 w - - 0 1";
+        for pn2 in [false, true] {
+            let result = prove_fen(
+                fen,
+                PnOptions {
+                    max_plies: 5,
+                    pn2,
+                    ..PnOptions::default()
+                },
+            );
+            assert_eq!(result.outcome, Outcome::Proven);
+            assert_eq!(result.main_line.len() % 2, 1);
+            let board = play_main_line(fen, &result);
+            assert!(gen_all_moves(&board, board.active_color).is_empty());
+            assert!(board.is_in_check(board.active_color, None));
+
+            //Every defence to the first move is answered in the tree
+            let first = &result.proof_tree[0];
+            let mut board = BoardState::new(fen).unwrap();
+            board.make_move(&first.mv);
+            assert_eq!(
+                first.replies.len(),
+                gen_all_moves(&board, board.active_color).len()
+            );
+        }
+    }
+
+    #[test]
+    fn disproves_without_mate() {
+        //Nothing but a draw in sight
+        let result = prove_fen(
+            "8/8/4k3/8/8/3K4/8/8 w - - 0 1",
+            PnOptions {
+                max_plies: 3,
+                ..PnOptions::default()
+            },
+        );
+        assert_eq!(result.outcome, Outcome::Disproven);
+        assert!(result.main_line.is_empty());
+
+        //Too few nodes to tell
+        let result = prove_fen(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 1",
+            PnOptions {
+                node_budget: 100,
+                ..PnOptions::default()
+            },
+        );
+        assert_eq!(result.outcome, Outcome::Unknown);
+    }
+
+    #[test]
+    fn proves_material_win() {
+        //Knight fork of king and rook, with a pawn left so the end isn't a dead draw
+        let fen = "r3k3/8/8/1N6/8/8/7P/4K3 w - - 0 1";
+        let result = prove_fen(
+            fen,
+            PnOptions {
+                goal: Goal::Material(300),
+                max_plies: 5,
+                ..PnOptions::default()
+            },
+        );
+        assert_eq!(result.outcome, Outcome::Proven);
+        assert_eq!(result.main_line[0].to_string(), "b5c7");
+
+        //A queen is more than the fork wins
+        let result = prove_fen(
+            fen,
+            PnOptions {
+                goal: Goal::Material(900),
+                max_plies: 5,
+                ..PnOptions::default()
+            },
+        );
+        assert_eq!(result.outcome, Outcome::Disproven);
+    }
+}
@@ -0,0 +1,526 @@
+use crate::board_state::BoardState;
+use crate::chess_move::Move;
+use crate::move_gen::gen_all_moves;
+use crate::san::to_san;
+use std::fmt;
+use std::str::FromStr;
+
+/*
+    Solver for composed problems, see https://en.wikipedia.org/wiki/Chess_problem
+    Directmate #n: the side to move mates in n moves against any defence.
+    Selfmate s#n: the side to move forces the other side to mate it within n moves.
+    Helpmate h#n: the side to move helps the other side mate it, both sides play n moves, the side to move first.
+    Every key is found, more than one is a cook. A defence that can be answered in more than one way has a dual.
+    Moves are numbered from the start of the problem whichever side is to move.
+*/
+
+pub const MAX_PROBLEM_MOVES: u8 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stipulation {
+    DirectMate(u8),
+    SelfMate(u8),
+    HelpMate(u8),
+}
+
+impl FromStr for Stipulation {
+    type Err = String;
+
+    /* #2, s#3 or h#2 */
+    fn from_str(text: &str) -> Result<Stipulation, String> {
+        let text = text.trim().to_lowercase();
+        let (kind, moves): (fn(u8) -> Stipulation, &str) =
+            if let Some(moves) = text.strip_prefix("s#") {
+                (Stipulation::SelfMate, moves)
+            } else if let Some(moves) = text.strip_prefix("h#") {
+                (Stipulation::HelpMate, moves)
+            } else if let Some(moves) = text.strip_prefix('#') {
+                (Stipulation::DirectMate, moves)
+            } else {
+                return Err(format!(
+                    "unknown stipulation {}, expected #n, s#n or h#n",
+                    text
+                ));
+            };
+        match moves.parse::<u8>() {
+            Ok(moves) if (1..=MAX_PROBLEM_MOVES).contains(&moves) => Ok(kind(moves)),
+            _ => Err(format!(
+                "the number of moves has to be between 1 and {}",
+                MAX_PROBLEM_MOVES
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Stipulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stipulation::DirectMate(moves) => write!(f, "#{}", moves),
+            Stipulation::SelfMate(moves) => write!(f, "s#{}", moves),
+            Stipulation::HelpMate(moves) => write!(f, "h#{}", moves),
+        }
+    }
+}
+
+/* A move of a solution and the moves that answer it */
+#[derive(Clone, PartialEq, Debug)]
+pub struct Line {
+    pub mv: Move,
+    pub san: String,
+    //What the attacker would play if the defender could pass, only for directmate and selfmate keys and continuations
+    pub threats: Vec<String>,
+    pub replies: Vec<Line>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Verdict {
+    NoSolution,
+    Sound,
+    Cooked, //More than one solution
+}
+
+pub struct ProblemReport {
+    pub stipulation: Stipulation,
+    //Keys for directmates and selfmates, first moves of the solutions for helpmates
+    pub solutions: Vec<Line>,
+    //Each dual as the moves leading up to it and the moves that all work there
+    pub duals: Vec<(Vec<String>, Vec<String>)>,
+    pub nodes: u64,
+}
+
+impl ProblemReport {
+    //Number of helpmate lines, or of keys for the other stipulations
+    pub fn solution_count(&self) -> usize {
+        match self.stipulation {
+            Stipulation::HelpMate(_) => self.solutions.iter().map(count_lines).sum(),
+            _ => self.solutions.len(),
+        }
+    }
+
+    pub fn verdict(&self) -> Verdict {
+        match self.solution_count() {
+            0 => Verdict::NoSolution,
+            1 => Verdict::Sound,
+            _ => Verdict::Cooked,
+        }
+    }
+}
+
+fn count_lines(line: &Line) -> usize {
+    if line.replies.is_empty() {
+        1
+    } else {
+        line.replies.iter().map(count_lines).sum()
+    }
+}
+
+struct Solver {
+    nodes: u64,
+    duals: Vec<(Vec<String>, Vec<String>)>,
+}
+
+fn play(board: &BoardState, mv: &Move) -> BoardState {
+    let mut board = board.clone();
+    board.make_move(mv);
+    board
+}
+
+fn legal_moves(board: &BoardState) -> Vec<Move> {
+    gen_all_moves(board, board.active_color)
+}
+
+fn is_mated(board: &BoardState) -> bool {
+    board.is_in_check(board.active_color, None) && legal_moves(board).is_empty()
+}
+
+//The same position with the other side to move, None when the side to move is in check and can't pass
+fn pass(board: &BoardState) -> Option<BoardState> {
+    if board.is_in_check(board.active_color, None) {
+        return None;
+    }
+    let mut board = board.clone();
+    board.make_null_move();
+    Some(board)
+}
+
+impl Solver {
+    /* Directmate, after the attacker's move the defender is mated or every defence still loses with moves_left more attacker moves */
+    fn defender_loses(&mut self, board: &BoardState, moves_left: u8) -> bool {
+        self.nodes += 1;
+        let defences = legal_moves(board);
+        if defences.is_empty() {
+            return board.is_in_check(board.active_color, None);
+        }
+        moves_left > 0
+            && defences
+                .iter()
+                .all(|defence| self.attacker_mates(&play(board, defence), moves_left))
+    }
+
+    fn attacker_mates(&mut self, board: &BoardState, moves: u8) -> bool {
+        legal_moves(board)
+            .iter()
+            .any(|mv| self.defender_loses(&play(board, mv), moves - 1))
+    }
+
+    /* Selfmate, the defender is to move and has to mate the attacker, now or after moves_left more attacker moves */
+    fn defender_must_mate(&mut self, board: &BoardState, moves_left: u8) -> bool {
+        self.nodes += 1;
+        let defences = legal_moves(board);
+        !defences.is_empty()
+            && defences.iter().all(|defence| {
+                let board = play(board, defence);
+                if legal_moves(&board).is_empty() {
+                    board.is_in_check(board.active_color, None)
+                } else {
+                    moves_left > 0 && self.attacker_selfmates(&board, moves_left)
+                }
+            })
+    }
+
+    fn attacker_selfmates(&mut self, board: &BoardState, moves: u8) -> bool {
+        legal_moves(board)
+            .iter()
+            .any(|mv| self.defender_must_mate(&play(board, mv), moves - 1))
+    }
+
+    //Whether the attacker's move leaves the defender lost with moves_left more attacker moves
+    fn works(&mut self, stipulation: Stipulation, board: &BoardState, moves_left: u8) -> bool {
+        match stipulation {
+            Stipulation::SelfMate(_) => self.defender_must_mate(board, moves_left),
+            _ => self.defender_loses(board, moves_left),
+        }
+    }
+
+    /* Every attacker move that works with the moves given, with all the defences to it */
+    fn attacker_lines(
+        &mut self,
+        stipulation: Stipulation,
+        board: &BoardState,
+        moves: u8,
+        path: &mut Vec<String>,
+    ) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for mv in legal_moves(board) {
+            let after = play(board, &mv);
+            if !self.works(stipulation, &after, moves - 1) {
+                continue;
+            }
+            let san = to_san(board, &mv);
+            let threats = self.threats(stipulation, &after, moves - 1);
+            path.push(san.clone());
+            let mut replies = Vec::new();
+            for defence in legal_moves(&after) {
+                let reply_board = play(&after, &defence);
+                let reply_san = to_san(&after, &defence);
+                path.push(reply_san.clone());
+                let continuations = if legal_moves(&reply_board).is_empty() {
+                    Vec::new() //Only a selfmate gets here, the defence mated the attacker
+                } else {
+                    self.attacker_lines(stipulation, &reply_board, moves - 1, path)
+                };
+                if continuations.len() > 1 {
+                    self.duals.push((
+                        path.clone(),
+                        continuations.iter().map(|line| line.san.clone()).collect(),
+                    ));
+                }
+                path.pop();
+                replies.push(Line {
+                    mv: defence,
+                    san: reply_san,
+                    threats: Vec::new(),
+                    replies: continuations,
+                });
+            }
+            path.pop();
+            lines.push(Line {
+                mv,
+                san,
+                threats,
+                replies,
+            });
+        }
+        lines
+    }
+
+    //Attacker moves that would work if the defender passed
+    fn threats(
+        &mut self,
+        stipulation: Stipulation,
+        board: &BoardState,
+        moves_left: u8,
+    ) -> Vec<String> {
+        if moves_left == 0 {
+            return Vec::new();
+        }
+        let passed = match pass(board) {
+            Some(passed) if !legal_moves(board).is_empty() => passed,
+            _ => return Vec::new(),
+        };
+        legal_moves(&passed)
+            .iter()
+            .filter(|mv| self.works(stipulation, &play(&passed, mv), moves_left - 1))
+            .map(|mv| to_san(&passed, mv))
+            .collect()
+    }
+
+    /* Helpmate, the helper is to move and both sides play moves more moves, ending with the helper mated */
+    fn helpmate_lines(&mut self, board: &BoardState, moves: u8) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for help in legal_moves(board) {
+            self.nodes += 1;
+            let after_help = play(board, &help);
+            let mut replies = Vec::new();
+            for mate in legal_moves(&after_help) {
+                let after_mate = play(&after_help, &mate);
+                let follow_ups = if moves == 1 {
+                    if !is_mated(&after_mate) {
+                        continue;
+                    }
+                    Vec::new()
+                } else {
+                    let follow_ups = self.helpmate_lines(&after_mate, moves - 1);
+                    if follow_ups.is_empty() {
+                        continue;
+                    }
+                    follow_ups
+                };
+                replies.push(Line {
+                    mv: mate,
+                    san: to_san(&after_help, &mate),
+                    threats: Vec::new(),
+                    replies: follow_ups,
+                });
+            }
+            if !replies.is_empty() {
+                lines.push(Line {
+                    mv: help,
+                    san: to_san(board, &help),
+                    threats: Vec::new(),
+                    replies,
+                });
+            }
+        }
+        lines
+    }
+}
+
+pub fn solve(board: &BoardState, stipulation: Stipulation) -> ProblemReport {
+    let mut solver = Solver {
+        nodes: 0,
+        duals: Vec::new(),
+    };
+    let solutions = match stipulation {
+        Stipulation::DirectMate(moves) | Stipulation::SelfMate(moves) => {
+            solver.attacker_lines(stipulation, board, moves, &mut Vec::new())
+        }
+        Stipulation::HelpMate(moves) => solver.helpmate_lines(board, moves),
+    };
+    ProblemReport {
+        stipulation,
+        solutions,
+        duals: solver.duals,
+        nodes: solver.nodes,
+    }
+}
+
+/*
+ * Writes out a directmate or selfmate key with its variations, each defence on its own line
+ * 1.Qh5! (threat: 2.Qxf7#)
+ *   1...Nf6 2.Qxf7#
+ */
+fn write_attacker_line(
+    f: &mut fmt::Formatter,
+    line: &Line,
+    number: usize,
+    depth: usize,
+    key: bool,
+) -> fmt::Result {
+    write!(f, "{}.{}", number, line.san)?;
+    if key {
+        write!(f, "!")?;
+    }
+    if !line.threats.is_empty() {
+        let threats: Vec<String> = line
+            .threats
+            .iter()
+            .map(|threat| format!("{}.{}", number + 1, threat))
+            .collect();
+        write!(f, " (threat: {})", threats.join(" / "))?;
+    }
+    writeln!(f)?;
+    for reply in &line.replies {
+        write!(f, "{}{}...{}", "  ".repeat(depth + 1), number, reply.san)?;
+        match reply.replies.as_slice() {
+            [] => writeln!(f)?,
+            //Finishing moves stay on the defence's line, with duals split by a slash
+            continuations if continuations.iter().all(|line| line.replies.is_empty()) => {
+                let moves: Vec<String> = continuations
+                    .iter()
+                    .map(|line| format!("{}.{}", number + 1, line.san))
+                    .collect();
+                writeln!(f, " {}", moves.join(" / "))?;
+            }
+            continuations => {
+                writeln!(f)?;
+                for continuation in continuations {
+                    write!(f, "{}", "  ".repeat(depth + 2))?;
+                    write_attacker_line(f, continuation, number + 1, depth + 2, false)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+//Every helpmate solution on one line, 1.Kf5 Qd2 2.Ke4 Qd4#
+fn write_helpmate_lines(
+    f: &mut fmt::Formatter,
+    lines: &[Line],
+    number: usize,
+    so_far: &str,
+) -> fmt::Result {
+    for help in lines {
+        for mate in &help.replies {
+            let text = format!("{}{}.{} {}", so_far, number, help.san, mate.san);
+            if mate.replies.is_empty() {
+                writeln!(f, "{}", text)?;
+            } else {
+                write_helpmate_lines(f, &mate.replies, number + 1, &format!("{} ", text))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for ProblemReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.verdict() {
+            Verdict::NoSolution => {
+                return writeln!(
+                    f,
+                    "{}: no solution ({} nodes)",
+                    self.stipulation, self.nodes
+                )
+            }
+            Verdict::Sound => writeln!(
+                f,
+                "{}: sound, one solution ({} nodes)",
+                self.stipulation, self.nodes
+            )?,
+            Verdict::Cooked => writeln!(
+                f,
+                "{}: cooked, {} solutions ({} nodes)",
+                self.stipulation,
+                self.solution_count(),
+                self.nodes
+            )?,
+        }
+        match self.stipulation {
+            Stipulation::HelpMate(_) => write_helpmate_lines(f, &self.solutions, 1, "")?,
+            _ => {
+                for key in &self.solutions {
+                    write_attacker_line(f, key, 1, 0, true)?;
+                }
+            }
+        }
+        for (path, moves) in &self.duals {
+            let numbered: Vec<String> = path
+                .iter()
+                .enumerate()
+                .map(|(ply, san)| {
+                    if ply % 2 == 0 {
+                        format!("{}.{}", ply / 2 + 1, san)
+                    } else {
+                        san.clone()
+                    }
+                })
+                .collect();
+            writeln!(f, "Dual after {}: {}", numbered.join(" "), moves.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve_fen(fen: &str, stipulation: &str) -> ProblemReport {
+        solve(&BoardState::new(fen).unwrap(), stipulation.parse().unwrap())
+    }
+
+    fn keys(report: &ProblemReport) -> Vec<&str> {
+        report
+            .solutions
+            .iter()
+            .map(|line| line.san.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn parses_stipulations() {
+        assert_eq!("#2".parse(), Ok(Stipulation::DirectMate(2)));
+        assert_eq!("s#3".parse(), Ok(Stipulation::SelfMate(3)));
+        assert_eq!("H#1".parse(), Ok(Stipulation::HelpMate(1)));
+        assert!("#0".parse::<Stipulation>().is_err());
+        assert!("x#2".parse::<Stipulation>().is_err());
+    }
+
+    #[test]
+    fn solves_directmates() {
+        let report = solve_fen(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 0 1",
+            "#1",
+        );
+        assert_eq!(report.verdict(), Verdict::Sound);
+        assert_eq!(keys(&report), vec!["Qxf7#"]);
+
+        let report = solve_fen("7k/8/5K2/8/8/8/8/6R1 w - - 0 1", "#2");
+        assert_eq!(report.verdict(), Verdict::Sound);
+        assert_eq!(keys(&report), vec!["Kf7"]);
+        assert_eq!(report.solutions[0].threats, vec!["Rh1#"]);
+        assert_eq!(
+            report.to_string().lines().skip(1).collect::<Vec<_>>(),
+            vec!["1.Kf7! (threat: 2.Rh1#)", "  1...Kh7 2.Rh1#",]
+        );
+    }
+
+    #[test]
+    fn finds_cooks_and_duals() {
+        let report = solve_fen("6k1/5ppp/8/8/8/8/8/R3R1K1 w - - 0 1", "#1");
+        assert_eq!(report.verdict(), Verdict::Cooked);
+        assert_eq!(keys(&report), vec!["Ra8#", "Re8#"]);
+
+        let report = solve_fen("k7/8/1K6/8/8/8/8/7Q w - - 0 1", "#2");
+        assert_eq!(report.verdict(), Verdict::Cooked);
+        assert!(report
+            .duals
+            .iter()
+            .any(|(path, moves)| path == &["Qh4", "Kb8"] && moves == &["Qh8#", "Qd8#"]));
+    }
+
+    #[test]
+    fn reports_no_solution() {
+        let report = solve_fen("k7/8/1K6/8/8/8/8/7Q w - - 0 1", "h#1");
+        assert_eq!(report.verdict(), Verdict::NoSolution);
+        let report = solve_fen("7k/8/5K2/8/8/8/8/6R1 w - - 0 1", "#1");
+        assert_eq!(report.verdict(), Verdict::NoSolution);
+    }
+
+    #[test]
+    fn solves_selfmate() {
+        let report = solve_fen("b6k/6pp/8/1Q6/4r3/8/7P/6BK w - - 0 1", "s#1");
+        assert_eq!(report.verdict(), Verdict::Sound);
+        assert_eq!(keys(&report), vec!["Qe8+"]);
+        assert_eq!(report.solutions[0].replies[0].san, "Rxe8#");
+    }
+
+    #[test]
+    fn solves_helpmate() {
+        let report = solve_fen("7k/8/6K1/8/8/8/8/R7 b - - 0 1", "h#1");
+        assert_eq!(report.verdict(), Verdict::Sound);
+        assert_eq!(report.to_string().lines().nth(1), Some("1.Kg8 Ra8#"));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::board_state::BoardState;
+use crate::chess_move::{Move, MoveType, MoveV2, Position};
+use crate::move_gen::gen_all_moves;
+use crate::piece::PieceType;
+
+/* Standard algebraic notation for writing moves out, see https://en.wikipedia.org/wiki/Algebraic_notation_(chess) */
+
+fn piece_letter(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::King => "K",
+        PieceType::Queen => "Q",
+        PieceType::Rook => "R",
+        PieceType::Bishop => "B",
+        PieceType::Knight => "N",
+        PieceType::Pawn | PieceType::None => "",
+    }
+}
+
+fn file(pos: Position) -> String {
+    pos.to_string()[..1].to_string()
+}
+
+fn rank(pos: Position) -> String {
+    pos.to_string()[1..].to_string()
+}
+
+/* The move in SAN, mv has to be legal on the board */
+pub fn to_san(board: &BoardState, mv: &Move) -> String {
+    let moves = gen_all_moves(board, board.active_color);
+    let mut san = match mv.move_type {
+        MoveType::Castle(castle) => {
+            if castle.is_kingside {
+                "O-O".to_string()
+            } else {
+                "O-O-O".to_string()
+            }
+        }
+        MoveType::Standard(standard) if standard.piece_moved.piece_type != PieceType::Pawn => {
+            //Naming the file, the rank or both when another piece of the same kind can get there too
+            let rivals: Vec<Position> = moves
+                .iter()
+                .filter_map(|other| match other.move_type {
+                    MoveType::Standard(other)
+                        if other.after == standard.after
+                            && other.before != standard.before
+                            && other.piece_moved.piece_type == standard.piece_moved.piece_type =>
+                    {
+                        Some(other.before)
+                    }
+                    _ => None,
+                })
+                .collect();
+            let disambiguation = if rivals.is_empty() {
+                String::new()
+            } else if rivals.iter().all(|pos| pos.col != standard.before.col) {
+                file(standard.before)
+            } else if rivals.iter().all(|pos| pos.row != standard.before.row) {
+                rank(standard.before)
+            } else {
+                standard.before.to_string()
+            };
+            let capture = if mv.piece_captured.is_some() { "x" } else { "" };
+            format!(
+                "{}{}{}{}",
+                piece_letter(standard.piece_moved.piece_type),
+                disambiguation,
+                capture,
+                standard.after.to_string()
+            )
+        }
+        MoveType::Standard(standard) => pawn_move(standard.before, standard.after, mv),
+        MoveType::EnPassant(en_passant) => pawn_move(en_passant.before, en_passant.after, mv),
+        MoveType::Promotion(promotion) => format!(
+            "{}={}",
+            pawn_move(promotion.before, promotion.after, mv),
+            piece_letter(promotion.promote_to.piece_type)
+        ),
+    };
+
+    let mut board_after = board.clone();
+    board_after.make_move(mv);
+    if board_after.is_in_check(board_after.active_color, None) {
+        if gen_all_moves(&board_after, board_after.active_color).is_empty() {
+            san.push('#');
+        } else {
+            san.push('+');
+        }
+    }
+    san
+}
+
+/*
+ * Finds the legal move written in SAN, forgiving about check marks, annotations, 0-0 for castles and a missing = or x.
+ * Matches on the piece, the target square, the promotion and whatever file or rank is given, so extra disambiguation like Nge2 is fine.
+ * None if no legal move fits or more than one does
+ */
+pub fn from_san(board: &BoardState, text: &str) -> Option<Move> {
+    let wanted = normalize(text);
+    let moves = gen_all_moves(board, board.active_color);
+    let color = board.active_color;
+    if wanted == "O-O" || wanted == "O-O-O" {
+        return moves.into_iter().find(|mv| match mv.move_type {
+            MoveType::Castle(castle) => castle.is_kingside == (wanted == "O-O"),
+            _ => false,
+        });
+    }
+
+    let mut chars: Vec<char> = wanted.chars().collect();
+    let piece_type = match chars.first() {
+        Some('K') => PieceType::King,
+        Some('Q') => PieceType::Queen,
+        Some('R') => PieceType::Rook,
+        Some('B') => PieceType::Bishop,
+        Some('N') => PieceType::Knight,
+        _ => PieceType::Pawn,
+    };
+    if piece_type != PieceType::Pawn {
+        chars.remove(0);
+    }
+    let promotion = match chars.last().map(|c| c.to_ascii_uppercase()) {
+        Some('Q') => Some(PieceType::Queen),
+        Some('R') => Some(PieceType::Rook),
+        Some('B') => Some(PieceType::Bishop),
+        Some('N') => Some(PieceType::Knight),
+        _ => None,
+    };
+    if promotion.is_some() {
+        chars.pop();
+    }
+    if chars.len() < 2 {
+        return None;
+    }
+    let target: String = chars.split_off(chars.len() - 2).into_iter().collect();
+    //Whatever is left names the file, the rank or both of the square the piece comes from
+    let from_file = chars
+        .iter()
+        .find(|c| ('a'..='h').contains(c))
+        .map(|c| c.to_string());
+    let from_rank = chars
+        .iter()
+        .find(|c| ('1'..='8').contains(c))
+        .map(|c| c.to_string());
+
+    let mut matching = moves.into_iter().filter(|mv| {
+        let packed = MoveV2::from_move(mv, color);
+        !packed.is_castle()
+            && board.get_piece(packed.from()).map(|piece| piece.piece_type) == Some(piece_type)
+            && packed.to().to_string() == target
+            && packed.promotion() == promotion
+            && from_file
+                .as_ref()
+                .map_or(true, |name| file(packed.from()) == *name)
+            && from_rank
+                .as_ref()
+                .map_or(true, |name| rank(packed.from()) == *name)
+    });
+    match (matching.next(), matching.next()) {
+        (Some(mv), None) => Some(mv),
+        _ => None,
+    }
+}
+
+fn normalize(san: &str) -> String {
+    san.trim()
+        .replace('0', "O")
+        .chars()
+        .filter(|c| !matches!(c, '+' | '#' | '!' | '?' | '=' | 'x' | '-'))
+        .collect::<String>()
+        .replace("OOO", "O-O-O")
+        .replace("OO", "O-O")
+}
+
+fn pawn_move(before: Position, after: Position, mv: &Move) -> String {
+    if mv.piece_captured.is_some() {
+        format!("{}x{}", file(before), after.to_string())
+    } else {
+        after.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san_of(fen: &str, uci: &str) -> String {
+        let board = BoardState::new(fen).unwrap();
+        let mv = gen_all_moves(&board, board.active_color)
+            .into_iter()
+            .find(|mv| {
+                let name = match mv.move_type {
+                    MoveType::Promotion(promotion) => format!(
+                        "{}{}",
+                        mv.to_string(),
+                        piece_letter(promotion.promote_to.piece_type).to_lowercase()
+                    ),
+                    _ => mv.to_string(),
+                };
+                name == uci
+            })
+            .unwrap();
+        to_san(&board, &mv)
+    }
+
+    #[test]
+    fn writes_san() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san_of(start, "e2e4"), "e4");
+        assert_eq!(san_of(start, "g1f3"), "Nf3");
+
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san_of(fen, "0-0"), "O-O");
+        assert_eq!(san_of(fen, "0-0-0"), "O-O-O");
+        assert_eq!(san_of(fen, "e5f7"), "Nxf7");
+        assert_eq!(san_of(fen, "d5e6"), "dxe6");
+        assert_eq!(san_of(fen, "f3f6"), "Qxf6");
+        assert_eq!(san_of(fen, "e2a6"), "Bxa6");
+
+        //Disambiguation by file, by rank and by both
+        assert_eq!(san_of("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "a1d1"), "Rad1");
+        assert_eq!(san_of("4k3/R7/8/8/8/8/8/R3K3 w - - 0 1", "a1a4"), "R1a4");
+        assert_eq!(san_of("k7/8/8/8/8/2Q1Q3/8/2Q4K w - - 0 1", "c3d2"), "Qc3d2");
+
+        //Promotion, check and mate
+        assert_eq!(san_of("8/1P6/8/8/8/8/8/k1K5 w - - 0 1", "b7b8q"), "b8=Q");
+        assert_eq!(san_of("8/1P6/8/8/8/8/8/k1K5 w - - 0 1", "b7b8r"), "b8=R");
+        assert_eq!(san_of("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+        assert_eq!(san_of("6k1/5pp1/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"), "Ra8+");
+    }
+
+    #[test]
+    fn reads_san() {
+        let board =
+            BoardState::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        for mv in gen_all_moves(&board, board.active_color) {
+            assert_eq!(from_san(&board, &to_san(&board, &mv)), Some(mv));
+        }
+        let written = |text: &str| from_san(&board, text).map(|mv| to_san(&board, &mv));
+        assert_eq!(written("0-0"), Some("O-O".to_string()));
+        assert_eq!(written("Nf7!"), Some("Nxf7".to_string()));
+        assert_eq!(written("Qf6+?"), Some("Qxf6".to_string()));
+        assert_eq!(written("Ke3"), None);
+
+        //More disambiguation than needed, or too little
+        assert_eq!(written("Ne5f7"), Some("Nxf7".to_string()));
+        assert_eq!(written("N5xf7"), Some("Nxf7".to_string()));
+        assert_eq!(written("Nd2"), None);
+        assert_eq!(written("Ncd1"), Some("Nd1".to_string()));
+        assert_eq!(written("Ned3"), Some("Nd3".to_string()));
+        assert_eq!(written("exd5"), None);
+
+        let board = BoardState::new("8/1P6/8/8/8/8/8/k1K5 w - - 0 1").unwrap();
+        assert_eq!(
+            from_san(&board, "b8Q").map(|mv| to_san(&board, &mv)),
+            Some("b8=Q".to_string())
+        );
+    }
+}
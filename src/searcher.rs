@@ -0,0 +1,426 @@
+use crate::board_state::BoardState;
+use crate::chess_move::Move;
+use crate::engine::{search_position, SearchOptions, SearchResult, MATE_VALUE};
+use crate::evaluator::{make_evaluator, Evaluator};
+use crate::mcts::Mcts;
+use crate::move_gen::gen_all_moves;
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+/*
+    Move pickers the front ends can play with. The alpha-beta engine is the real one,
+    the others are baselines and sparring partners that are easy to reason about.
+*/
+
+const MINIMAX_DEFAULT_DEPTH: u16 = 3;
+
+/* How long a searcher may think for, None leaves it to the searcher */
+#[derive(Clone, Copy, Debug)]
+pub struct SearchLimits {
+    pub time_to_think: Duration,
+    pub max_depth: Option<u16>,
+    pub node_limit: Option<u64>,
+}
+
+pub trait Searcher {
+    fn name(&self) -> &'static str;
+
+    /* history holds the zobrist keys of the positions played before this one, oldest first */
+    fn search(
+        &mut self,
+        board: &BoardState,
+        history: &[u64],
+        limits: &SearchLimits,
+    ) -> SearchResult;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum SearcherKind {
+    /// The full engine, iterative deepening alpha-beta
+    #[default]
+    AlphaBeta,
+    /// Plays a random legal move
+    Random,
+    /// Plays the move with the best evaluation one ply ahead
+    Greedy,
+    /// Full width minimax without pruning, a reference for the engine
+    Minimax,
+    /// Monte Carlo tree search
+    Mcts,
+}
+
+/* Builds a searcher by name. options are used as they are by alpha-beta, the others only take their evaluator and MCTS its own options */
+pub fn make_searcher(kind: SearcherKind, options: &SearchOptions, seed: u64) -> Box<dyn Searcher> {
+    match kind {
+        SearcherKind::AlphaBeta => Box::new(AlphaBetaSearcher {
+            options: options.clone(),
+        }),
+        SearcherKind::Random => Box::new(RandomSearcher {
+            rng: StdRng::seed_from_u64(seed),
+        }),
+        SearcherKind::Greedy => Box::new(GreedySearcher {
+            evaluator: make_evaluator(options.evaluator, options.eval_params.clone()),
+        }),
+        SearcherKind::Minimax => Box::new(MinimaxSearcher {
+            evaluator: make_evaluator(options.evaluator, options.eval_params.clone()),
+        }),
+        SearcherKind::Mcts => Box::new(Mcts::new(
+            options.mcts,
+            make_evaluator(options.evaluator, options.eval_params.clone()),
+            seed,
+        )),
+    }
+}
+
+pub fn pv_to_string(pv: &[Move]) -> String {
+    pv.iter()
+        .map(|mv| mv.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn empty_result() -> SearchResult {
+    SearchResult {
+        score: 0,
+        move_found: None,
+        nodes_searched: 0,
+        pv: Vec::new(),
+        depth: 0,
+        elapsed: Duration::ZERO,
+    }
+}
+
+//Score of a position with no moves, or a forced draw, None if the game goes on
+fn game_over_score(board: &BoardState, no_moves: bool, ply: i32) -> Option<i32> {
+    if no_moves {
+        if board.is_in_check(board.active_color, None) {
+            Some(-(MATE_VALUE - ply))
+        } else {
+            Some(0)
+        }
+    } else if board.is_fifty_move_draw() || board.is_insufficient_material() {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+pub struct AlphaBetaSearcher {
+    pub options: SearchOptions,
+}
+
+impl Searcher for AlphaBetaSearcher {
+    fn name(&self) -> &'static str {
+        "alpha-beta"
+    }
+
+    fn search(
+        &mut self,
+        board: &BoardState,
+        history: &[u64],
+        limits: &SearchLimits,
+    ) -> SearchResult {
+        let mut options = self.options.clone();
+        if let Some(depth) = limits.max_depth {
+            options.max_depth = min(options.max_depth, depth);
+        }
+        if let Some(nodes) = limits.node_limit {
+            options.node_limit = Some(min(options.node_limit.unwrap_or(u64::MAX), nodes));
+        }
+        search_position(board, history, limits.time_to_think, &options)
+    }
+}
+
+pub struct RandomSearcher {
+    pub rng: StdRng,
+}
+
+impl Searcher for RandomSearcher {
+    fn name(&self) -> &'static str {
+        "random"
+    }
+
+    fn search(&mut self, board: &BoardState, _: &[u64], _: &SearchLimits) -> SearchResult {
+        let start = Instant::now();
+        let moves = gen_all_moves(board, board.active_color);
+        let mut result = empty_result();
+        if !moves.is_empty() {
+            let mv = moves[self.rng.gen_range(0..moves.len())];
+            result.move_found = Some(mv);
+            result.pv = vec![mv];
+            result.nodes_searched = 1;
+        }
+        result.elapsed = start.elapsed();
+        result
+    }
+}
+
+pub struct GreedySearcher {
+    pub evaluator: Box<dyn Evaluator>,
+}
+
+impl Searcher for GreedySearcher {
+    fn name(&self) -> &'static str {
+        "greedy"
+    }
+
+    fn search(&mut self, board: &BoardState, _: &[u64], _: &SearchLimits) -> SearchResult {
+        let start = Instant::now();
+        let mut result = empty_result();
+        result.score = -MATE_VALUE;
+        for mv in gen_all_moves(board, board.active_color) {
+            let mut board_copy = board.clone();
+            board_copy.make_move(&mv);
+            result.nodes_searched += 1;
+            let no_moves = gen_all_moves(&board_copy, board_copy.active_color).is_empty();
+            let score = match game_over_score(&board_copy, no_moves, 1) {
+                Some(score) => -score,
+                None => {
+                    self.evaluator.push(board, &board_copy);
+                    let score = -self.evaluator.evaluate(&board_copy);
+                    self.evaluator.pop();
+                    score
+                }
+            };
+            if result.move_found.is_none() || score > result.score {
+                result.move_found = Some(mv);
+                result.score = score;
+            }
+        }
+        match result.move_found {
+            Some(mv) => {
+                result.pv = vec![mv];
+                result.depth = 1;
+            }
+            None => result.score = 0,
+        }
+        result.elapsed = start.elapsed();
+        result
+    }
+}
+
+pub struct MinimaxSearcher {
+    pub evaluator: Box<dyn Evaluator>,
+}
+
+impl MinimaxSearcher {
+    //Score and line from the side to move's point of view, None once over the limits
+    fn minimax(
+        &mut self,
+        board: &BoardState,
+        depth: u16,
+        ply: i32,
+        nodes: &mut u64,
+        limits: &SearchLimits,
+        start: Instant,
+    ) -> Option<(i32, Vec<Move>)> {
+        if start.elapsed() > limits.time_to_think
+            || limits.node_limit.is_some_and(|limit| *nodes >= limit)
+        {
+            return None;
+        }
+        *nodes += 1;
+
+        let moves = gen_all_moves(board, board.active_color);
+        if let Some(score) = game_over_score(board, moves.is_empty(), ply) {
+            return Some((score, Vec::new()));
+        }
+        if depth == 0 {
+            return Some((self.evaluator.evaluate(board), Vec::new()));
+        }
+
+        let mut best: Option<(i32, Vec<Move>)> = None;
+        for mv in moves {
+            let mut board_copy = board.clone();
+            board_copy.make_move(&mv);
+            self.evaluator.push(board, &board_copy);
+            let child = self.minimax(&board_copy, depth - 1, ply + 1, nodes, limits, start);
+            self.evaluator.pop();
+            let (score, line) = child?;
+            if best
+                .as_ref()
+                .map_or(true, |(best_score, _)| -score > *best_score)
+            {
+                let mut pv = vec![mv];
+                pv.extend(line);
+                best = Some((-score, pv));
+            }
+        }
+        best
+    }
+}
+
+impl Searcher for MinimaxSearcher {
+    fn name(&self) -> &'static str {
+        "minimax"
+    }
+
+    /*
+     * Deepens one ply at a time and keeps the last depth that finished inside the limits.
+     * Depth 1 is only a node per legal move and always runs to the end, so there is a move whenever there are legal moves
+     */
+    fn search(&mut self, board: &BoardState, _: &[u64], limits: &SearchLimits) -> SearchResult {
+        let start = Instant::now();
+        let mut result = empty_result();
+        let max_depth = limits.max_depth.unwrap_or(MINIMAX_DEFAULT_DEPTH).max(1);
+        let unlimited = SearchLimits {
+            time_to_think: Duration::MAX,
+            max_depth: None,
+            node_limit: None,
+        };
+        let mut nodes = 0;
+        for depth in 1..=max_depth {
+            let depth_limits = if depth == 1 { &unlimited } else { limits };
+            match self.minimax(board, depth, 0, &mut nodes, depth_limits, start) {
+                Some((score, pv)) => {
+                    result.score = score;
+                    result.move_found = pv.first().copied();
+                    result.pv = pv;
+                    result.depth = depth;
+                }
+                None => break,
+            }
+        }
+        result.nodes_searched = nodes;
+        result.elapsed = start.elapsed();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PieceType;
+
+    fn limits(max_depth: u16) -> SearchLimits {
+        SearchLimits {
+            time_to_think: Duration::from_secs(10),
+            max_depth: Some(max_depth),
+            node_limit: None,
+        }
+    }
+
+    fn search_with(kind: SearcherKind, fen: &str, max_depth: u16) -> SearchResult {
+        let board = BoardState::new(fen).unwrap();
+        let mut options = SearchOptions::default();
+        options.mcts.iterations = 200;
+        let mut searcher = make_searcher(kind, &options, 1);
+        searcher.search(&board, &[], &limits(max_depth))
+    }
+
+    #[test]
+    fn every_searcher_plays_a_legal_move() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let board = BoardState::new(fen).unwrap();
+        let moves = gen_all_moves(&board, board.active_color);
+        for kind in [
+            SearcherKind::AlphaBeta,
+            SearcherKind::Random,
+            SearcherKind::Greedy,
+            SearcherKind::Minimax,
+            SearcherKind::Mcts,
+        ] {
+            let result = search_with(kind, fen, 2);
+            let mv = result.move_found.unwrap();
+            assert!(moves.contains(&mv));
+            assert_eq!(result.pv.first(), Some(&mv));
+            assert!(result.nodes_searched > 0);
+        }
+
+        //Checkmated, nothing to play
+        let mated = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+        assert!(search_with(SearcherKind::Greedy, mated, 2)
+            .move_found
+            .is_none());
+        assert!(search_with(SearcherKind::Random, mated, 2)
+            .move_found
+            .is_none());
+    }
+
+    #[test]
+    fn minimax_always_finishes_depth_one() {
+        let board =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let mut searcher = make_searcher(SearcherKind::Minimax, &SearchOptions::default(), 0);
+        for limits in [
+            SearchLimits {
+                node_limit: Some(1),
+                ..limits(3)
+            },
+            SearchLimits {
+                time_to_think: Duration::ZERO,
+                ..limits(3)
+            },
+        ] {
+            let result = searcher.search(&board, &[], &limits);
+            assert!(gen_all_moves(&board, board.active_color).contains(&result.move_found.unwrap()));
+            assert_eq!(result.depth, 1);
+        }
+    }
+
+    #[test]
+    fn random_searcher_is_seeded() {
+        let board =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let pick = |seed| {
+            let mut searcher = make_searcher(SearcherKind::Random, &SearchOptions::default(), seed);
+            searcher.search(&board, &[], &limits(1)).move_found
+        };
+        assert_eq!(pick(3), pick(3));
+        assert!((0..20).any(|seed| pick(seed) != pick(3)));
+    }
+
+    #[test]
+    fn greedy_takes_the_queen() {
+        let result = search_with(SearcherKind::Greedy, "k7/4r1q1/5P2/8/8/8/8/K7 w - - 0 1", 1);
+        let mv = result.move_found.unwrap();
+        assert_eq!(mv.piece_captured.unwrap().piece_type, PieceType::Queen);
+        assert_eq!(result.depth, 1);
+    }
+
+    #[test]
+    fn minimax_finds_mate() {
+        let result = search_with(
+            SearcherKind::Minimax,
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            3,
+        );
+        assert_eq!(result.move_found.unwrap().to_string(), "a1a8");
+        assert_eq!(result.score, MATE_VALUE - 1);
+        assert_eq!(result.pv.len(), 1);
+        assert_eq!(result.depth, 3);
+
+        //Full width, so the line is as long as the depth when nothing ends the game
+        let result = search_with(
+            SearcherKind::Minimax,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            2,
+        );
+        assert_eq!(result.pv.len(), 2);
+        //Summed over both iterations
+        assert_eq!(result.nodes_searched, (1 + 20) + (1 + 20 + 400));
+    }
+
+    #[test]
+    fn alpha_beta_reports_a_line() {
+        let result = search_with(
+            SearcherKind::AlphaBeta,
+            "r2qkb1r/1p1n1pp1/p2p1n2/3bp2p/4P3/1N2BP2/PPPQ2PP/R3KB1R w KQkq - 0 1",
+            3,
+        );
+        assert_eq!(result.depth, 3);
+        assert!(result.pv.len() > 1 && result.pv.len() <= 3);
+
+        //The line has to be playable from the position
+        let mut board =
+            BoardState::new("r2qkb1r/1p1n1pp1/p2p1n2/3bp2p/4P3/1N2BP2/PPPQ2PP/R3KB1R w KQkq - 0 1")
+                .unwrap();
+        for mv in &result.pv {
+            assert!(gen_all_moves(&board, board.active_color).contains(mv));
+            board.make_move(mv);
+        }
+    }
+}
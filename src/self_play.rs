@@ -0,0 +1,581 @@
+use crate::board_state::BoardState;
+use crate::color::Color;
+use crate::engine::{SearchOptions, SkillLevel};
+use crate::evaluation::EvalParams;
+use crate::evaluator::EvaluatorKind;
+use crate::move_gen::gen_all_moves;
+use crate::san::to_san;
+use crate::searcher::{make_searcher, SearchLimits, Searcher, SearcherKind};
+use clap::ValueEnum;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/*
+    Matches between two engine configurations inside one process, for checking whether a change gains strength.
+    Each side can evaluate with its own EvalParams, they go to its searcher with the rest of its SearchOptions.
+    Each opening is played twice with the colors swapped, so neither side profits from a lopsided opening.
+    Results go through an SPRT, see https://www.chessprogramming.org/Sequential_Probability_Ratio_Test
+*/
+
+//Games still going after this many plies are called a draw
+pub const MAX_GAME_PLIES: usize = 400;
+
+pub const DEFAULT_OPENINGS: [&str; 10] = [
+    "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkbnr/ppp1pppp/8/3p4/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkbnr/pppp1ppp/4p3/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkbnr/pp1ppppp/2p5/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkbnr/pppp1ppp/8/4p3/2P5/8/PP1PPPPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkbnr/ppp1pppp/8/3p4/8/5N2/PPPPPPPP/RNBQKB1R w KQkq - 0 2",
+    "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 3 3",
+    "rnbqkbnr/ppp2ppp/4p3/3p4/2PP4/8/PP2PPPP/RNBQKBNR w KQkq - 0 3",
+    "rnbqkb1r/pppppp1p/5np1/8/2PP4/8/PP2PPPP/RNBQKBNR w KQkq - 0 3",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    pub fn to_pgn(self) -> &'static str {
+        match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+}
+
+pub struct GameRecord {
+    pub start_fen: String,
+    pub moves: Vec<String>, //In SAN
+    pub result: GameResult,
+    pub reason: &'static str,
+}
+
+/* One side of a match */
+#[derive(Clone, Debug)]
+pub struct EngineConfig {
+    pub name: String,
+    pub searcher: SearcherKind,
+    pub options: SearchOptions,
+    pub limits: SearchLimits,
+}
+
+impl EngineConfig {
+    /*
+     * Applies comma separated settings like searcher=mcts,evaluator=material,depth=3 on top of base.
+     * Keys are name, searcher, evaluator, time, depth, nodes, contempt, skill, elo and eval_params, a TOML file like --eval-params takes
+     */
+    pub fn parse(spec: &str, base: &EngineConfig, seed: u64) -> Result<EngineConfig, String> {
+        let mut config = base.clone();
+        for setting in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|setting| !setting.is_empty())
+        {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or(format!("{} is not a key=value setting", setting))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("{} needs a number, not {}", key, value))
+            };
+            match key {
+                "name" => config.name = value.to_string(),
+                "searcher" => config.searcher = SearcherKind::from_str(value, true)?,
+                "evaluator" => config.options.evaluator = EvaluatorKind::from_str(value, true)?,
+                "time" => config.limits.time_to_think = Duration::from_secs(number()?),
+                "depth" => config.limits.max_depth = Some(number()? as u16),
+                "nodes" => config.limits.node_limit = Some(number()?),
+                "contempt" => {
+                    config.options.contempt = value
+                        .parse()
+                        .map_err(|_| format!("contempt needs a number, not {}", value))?
+                }
+                "skill" => config.options.skill = Some(SkillLevel::new(number()? as u8, seed)),
+                "elo" => config.options.skill = Some(SkillLevel::from_elo(number()? as u32, seed)),
+                "eval_params" => {
+                    let params = EvalParams::load(Path::new(value))?;
+                    config.options.eval_params = Some(Arc::new(params));
+                }
+                _ => return Err(format!("unknown setting {}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/* Reads openings one per line, either a full FEN or an EPD line whose operations are ignored */
+pub fn parse_openings(text: &str) -> Result<Vec<String>, String> {
+    let mut openings = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(';').next().unwrap().split_whitespace().collect();
+        let has_clocks = fields.len() >= 6
+            && fields[4..6]
+                .iter()
+                .all(|field| field.parse::<u16>().is_ok());
+        let fen = if has_clocks {
+            fields[..6].join(" ")
+        } else if fields.len() >= 4 {
+            format!("{} 0 1", fields[..4].join(" "))
+        } else {
+            return Err(format!("line {}: {} is not a position", index + 1, line));
+        };
+        BoardState::new(&fen).map_err(|e| format!("line {}: {}", index + 1, e))?;
+        openings.push(fen);
+    }
+    Ok(openings)
+}
+
+/* How the game stands, None while it goes on. history holds the keys of every position so far, this one included */
+pub fn game_over(board: &BoardState, history: &[u64]) -> Option<(GameResult, &'static str)> {
+    if gen_all_moves(board, board.active_color).is_empty() {
+        if !board.is_in_check(board.active_color, None) {
+            return Some((GameResult::Draw, "stalemate"));
+        }
+        let result = match board.active_color {
+            Color::White => GameResult::BlackWins,
+            Color::Black => GameResult::WhiteWins,
+        };
+        return Some((result, "checkmate"));
+    }
+    if board.is_fifty_move_draw() {
+        return Some((GameResult::Draw, "fifty move rule"));
+    }
+    if board.is_insufficient_material() {
+        return Some((GameResult::Draw, "insufficient material"));
+    }
+    if let Some(key) = history.last() {
+        if history.iter().filter(|other| *other == key).count() >= 3 {
+            return Some((GameResult::Draw, "threefold repetition"));
+        }
+    }
+    if history.len() > MAX_GAME_PLIES {
+        return Some((GameResult::Draw, "too long"));
+    }
+    None
+}
+
+/* Plays one game, each searcher with the limits and eval params of its config */
+pub fn play_game(
+    white: &mut dyn Searcher,
+    white_config: &EngineConfig,
+    black: &mut dyn Searcher,
+    black_config: &EngineConfig,
+    start_fen: &str,
+) -> GameRecord {
+    let mut board = BoardState::new(start_fen).unwrap();
+    let mut history = vec![board.zobrist_key()];
+    let mut moves = Vec::new();
+    loop {
+        if let Some((result, reason)) = game_over(&board, &history) {
+            return GameRecord {
+                start_fen: start_fen.to_string(),
+                moves,
+                result,
+                reason,
+            };
+        }
+
+        let config = match board.active_color {
+            Color::White => white_config,
+            Color::Black => black_config,
+        };
+
+        //The searchers want the positions before this one
+        let earlier = &history[..history.len() - 1];
+        let result = match board.active_color {
+            Color::White => white.search(&board, earlier, &config.limits),
+            Color::Black => black.search(&board, earlier, &config.limits),
+        };
+        let mv = result
+            .move_found
+            .expect("Searcher found no move in a game that isn't over");
+        moves.push(to_san(&board, &mv));
+        board.make_move(&mv);
+        history.push(board.zobrist_key());
+    }
+}
+
+/* Games from the first engine's point of view */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    //Average points per game
+    pub fn ratio(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    //Spread of the points of a single game
+    fn variance(&self) -> f64 {
+        let ratio = self.ratio();
+        let games = self.games().max(1) as f64;
+        (self.wins as f64 * (1.0 - ratio).powi(2)
+            + self.draws as f64 * (0.5 - ratio).powi(2)
+            + self.losses as f64 * ratio.powi(2))
+            / games
+    }
+
+    pub fn elo(&self) -> f64 {
+        elo_from_ratio(self.ratio())
+    }
+
+    //Half the width of the 95% confidence interval
+    pub fn elo_error(&self) -> f64 {
+        let margin = 1.96 * (self.variance() / self.games().max(1) as f64).sqrt();
+        (elo_from_ratio(self.ratio() + margin) - elo_from_ratio(self.ratio() - margin)) / 2.0
+    }
+
+    /* Log likelihood ratio of elo1 over elo0, using the normal approximation to the game results */
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance();
+        if variance == 0.0 {
+            return 0.0;
+        }
+        let (ratio0, ratio1) = (ratio_from_elo(elo0), ratio_from_elo(elo1));
+        self.games() as f64 * (ratio1 - ratio0) * (2.0 * self.ratio() - ratio0 - ratio1)
+            / (2.0 * variance)
+    }
+}
+
+fn elo_from_ratio(ratio: f64) -> f64 {
+    let ratio = ratio.clamp(1e-6, 1.0 - 1e-6);
+    -400.0 * (1.0 / ratio - 1.0).log10()
+}
+
+fn ratio_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/* Tests whether the first engine is elo1 stronger rather than elo0, with false positive rate alpha and false negative rate beta */
+#[derive(Clone, Copy, Debug)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SprtVerdict {
+    AcceptH0, //No better than elo0
+    AcceptH1, //At least elo1 better
+    Continue,
+}
+
+impl Sprt {
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    pub fn verdict(&self, score: &Score) -> SprtVerdict {
+        let llr = score.llr(self.elo0, self.elo1);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            SprtVerdict::AcceptH1
+        } else if llr <= lower {
+            SprtVerdict::AcceptH0
+        } else {
+            SprtVerdict::Continue
+        }
+    }
+}
+
+pub struct MatchSettings {
+    pub openings: Vec<String>,
+    pub pairs: usize,
+    pub concurrency: usize,
+    pub sprt: Sprt,
+    pub seed: u64,
+}
+
+/*
+ * Plays the games of a match on concurrency threads, stopping early at the end of a pair once the SPRT has a verdict.
+ * on_game gets each finished game with the first engine's color and the score so far.
+ */
+pub fn run_match(
+    first: &EngineConfig,
+    second: &EngineConfig,
+    settings: &MatchSettings,
+    on_game: impl Fn(usize, &GameRecord, bool, &Score) + Sync,
+) -> Score {
+    let score = Mutex::new(Score::default());
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+
+    //A thread plays both games of a pair, so the score only ever holds whole pairs
+    thread::scope(|scope| {
+        for _ in 0..settings.concurrency.max(1).min(settings.pairs) {
+            scope.spawn(|| loop {
+                let pair = next.fetch_add(1, Ordering::Relaxed);
+                if pair >= settings.pairs || stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                //Both games of a pair start from the same opening, the first engine is white in the even one
+                let opening = &settings.openings[pair % settings.openings.len()];
+                let records: Vec<(usize, GameRecord, bool)> = [pair * 2, pair * 2 + 1]
+                    .into_iter()
+                    .map(|index| {
+                        let first_is_white = index % 2 == 0;
+                        let seed = settings.seed.wrapping_add(index as u64);
+                        let mut first_searcher =
+                            make_searcher(first.searcher, &first.options, seed);
+                        let mut second_searcher =
+                            make_searcher(second.searcher, &second.options, seed);
+                        let record = if first_is_white {
+                            play_game(
+                                first_searcher.as_mut(),
+                                first,
+                                second_searcher.as_mut(),
+                                second,
+                                opening,
+                            )
+                        } else {
+                            play_game(
+                                second_searcher.as_mut(),
+                                second,
+                                first_searcher.as_mut(),
+                                first,
+                                opening,
+                            )
+                        };
+                        (index, record, first_is_white)
+                    })
+                    .collect();
+
+                let mut score = score.lock().unwrap();
+                for (index, record, first_is_white) in &records {
+                    match (record.result, first_is_white) {
+                        (GameResult::Draw, _) => score.draws += 1,
+                        (GameResult::WhiteWins, true) | (GameResult::BlackWins, false) => {
+                            score.wins += 1
+                        }
+                        _ => score.losses += 1,
+                    }
+                    on_game(*index, record, *first_is_white, &score);
+                }
+                if settings.sprt.verdict(&score) != SprtVerdict::Continue {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+
+    score.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprt() -> Sprt {
+        Sprt {
+            elo0: 0.0,
+            elo1: 10.0,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+
+    #[test]
+    fn elo_and_sprt() {
+        let even = Score {
+            wins: 10,
+            draws: 20,
+            losses: 10,
+        };
+        assert!(even.elo().abs() < 1e-9);
+        assert!(even.elo_error() > 0.0);
+        assert_eq!(sprt().verdict(&even), SprtVerdict::Continue);
+
+        let ahead = Score {
+            wins: 3,
+            draws: 0,
+            losses: 1,
+        };
+        assert!((ahead.elo() - 190.85).abs() < 0.01);
+
+        let crushing = Score {
+            wins: 300,
+            draws: 100,
+            losses: 100,
+        };
+        assert_eq!(sprt().verdict(&crushing), SprtVerdict::AcceptH1);
+        let crushed = Score {
+            wins: 100,
+            draws: 100,
+            losses: 300,
+        };
+        assert_eq!(sprt().verdict(&crushed), SprtVerdict::AcceptH0);
+    }
+
+    #[test]
+    fn parses_engine_settings() {
+        let base = EngineConfig {
+            name: "base".to_string(),
+            searcher: SearcherKind::AlphaBeta,
+            options: SearchOptions::default(),
+            limits: SearchLimits {
+                time_to_think: Duration::from_secs(10),
+                max_depth: None,
+                node_limit: None,
+            },
+        };
+        let config = EngineConfig::parse(
+            "name=mcts, searcher=mcts,evaluator=material,depth=3,time=1",
+            &base,
+            0,
+        )
+        .unwrap();
+        assert_eq!(config.name, "mcts");
+        assert_eq!(config.searcher, SearcherKind::Mcts);
+        assert_eq!(config.options.evaluator, EvaluatorKind::Material);
+        assert_eq!(config.limits.max_depth, Some(3));
+        assert_eq!(config.limits.time_to_think, Duration::from_secs(1));
+        assert!(EngineConfig::parse("depth=deep", &base, 0).is_err());
+        assert!(EngineConfig::parse("colour=white", &base, 0).is_err());
+
+        let openings = parse_openings(
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2\n\
+             rnbqkbnr/ppp1pppp/8/3p4/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - id \"d4 d5\";",
+        )
+        .unwrap();
+        assert_eq!(
+            openings[1],
+            "rnbqkbnr/ppp1pppp/8/3p4/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - 0 1"
+        );
+    }
+
+    #[test]
+    fn each_side_evaluates_with_its_own_params() {
+        let base = EngineConfig {
+            name: "greedy".to_string(),
+            searcher: SearcherKind::Greedy,
+            options: SearchOptions::default(),
+            limits: SearchLimits {
+                time_to_think: Duration::from_secs(10),
+                max_depth: None,
+                node_limit: None,
+            },
+        };
+        //A queen the side that has it would rather be rid of
+        let path =
+            std::env::temp_dir().join(format!("reese_bot_params_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "mg_piece_values = [82, 337, 365, 477, -5000, 0]\neg_piece_values = [94, 281, 297, 512, -5000, 0]",
+        )
+        .unwrap();
+        let odd =
+            EngineConfig::parse(&format!("eval_params={}", path.display()), &base, 0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            odd.options.eval_params.as_ref().unwrap().mg_piece_values[4],
+            -5000
+        );
+        assert!(EngineConfig::parse("eval_params=/no/such/file.toml", &base, 0).is_err());
+
+        //Taking the queen is the greedy move, unless a queen counts against its owner
+        let fen = "k7/8/8/3q4/4P3/8/8/K7 w - - 0 1";
+        let mut white = make_searcher(SearcherKind::Greedy, &base.options, 0);
+        let mut black = make_searcher(SearcherKind::Greedy, &base.options, 0);
+        let record = play_game(white.as_mut(), &base, black.as_mut(), &base, fen);
+        assert_eq!(record.moves[0], "exd5");
+        let mut odd_white = make_searcher(SearcherKind::Greedy, &odd.options, 0);
+        let record = play_game(odd_white.as_mut(), &odd, black.as_mut(), &base, fen);
+        assert_ne!(record.moves[0], "exd5");
+    }
+
+    #[test]
+    fn ends_games() {
+        let mated = BoardState::new("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        assert_eq!(
+            game_over(&mated, &[]),
+            Some((GameResult::WhiteWins, "checkmate"))
+        );
+        let stalemated = BoardState::new("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(
+            game_over(&stalemated, &[]),
+            Some((GameResult::Draw, "stalemate"))
+        );
+        let start = BoardState::new(DEFAULT_OPENINGS[0]).unwrap();
+        assert_eq!(
+            game_over(&start, &[1, 2, 1, 2, 1]),
+            Some((GameResult::Draw, "threefold repetition"))
+        );
+        assert_eq!(game_over(&start, &[1, 2, 1]), None);
+    }
+
+    #[test]
+    fn plays_paired_games() {
+        let base = EngineConfig {
+            name: "greedy".to_string(),
+            searcher: SearcherKind::Greedy,
+            options: SearchOptions::default(),
+            limits: SearchLimits {
+                time_to_think: Duration::from_secs(10),
+                max_depth: None,
+                node_limit: None,
+            },
+        };
+        let random = EngineConfig::parse("name=random,searcher=random", &base, 0).unwrap();
+        let settings = MatchSettings {
+            openings: DEFAULT_OPENINGS[..1]
+                .iter()
+                .map(|fen| fen.to_string())
+                .collect(),
+            pairs: 1,
+            concurrency: 2,
+            sprt: sprt(),
+            seed: 0,
+        };
+        let colors = Mutex::new(Vec::new());
+        let score = run_match(&base, &random, &settings, |_, record, first_is_white, _| {
+            assert_eq!(record.start_fen, DEFAULT_OPENINGS[0]);
+            colors.lock().unwrap().push(first_is_white);
+        });
+        assert_eq!(score.games(), 2);
+        let mut colors = colors.into_inner().unwrap();
+        colors.sort();
+        assert_eq!(colors, vec![false, true]);
+
+        //Both bounds at 0 give a verdict after any game, the match still finishes the pair
+        let settings = MatchSettings {
+            pairs: 3,
+            concurrency: 1,
+            sprt: Sprt {
+                alpha: 0.5,
+                beta: 0.5,
+                ..sprt()
+            },
+            ..settings
+        };
+        let score = run_match(&base, &random, &settings, |_, _, _, _| {});
+        assert_eq!(score.games(), 2);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::board_state::BoardState;
+use crate::color::Color;
+use crate::piece::PieceType;
+use crate::self_play::GameResult;
+use std::path::Path;
+
+/*
+    Syzygy endgame tablebases for adjudicating games, see https://www.chessprogramming.org/Syzygy_Bases
+    Probing is done by shakmaty-syzygy, which is GPL-3.0 licensed and so only built with the syzygy feature.
+    Without it opening tables always fails.
+*/
+
+#[derive(Debug)]
+pub struct Tablebases {
+    #[cfg(feature = "syzygy")]
+    tables: shakmaty_syzygy::Tablebase<shakmaty::Chess>,
+    max_pieces: u32,
+}
+
+impl Tablebases {
+    /* Adds every table in the directory, an error if it holds none */
+    #[cfg(feature = "syzygy")]
+    pub fn open(path: &Path) -> Result<Tablebases, String> {
+        let mut tables = shakmaty_syzygy::Tablebase::new();
+        let found = tables
+            .add_directory(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if found == 0 {
+            return Err(format!("{} holds no Syzygy tables", path.display()));
+        }
+        Ok(Tablebases {
+            max_pieces: tables.max_pieces() as u32,
+            tables,
+        })
+    }
+
+    #[cfg(not(feature = "syzygy"))]
+    pub fn open(path: &Path) -> Result<Tablebases, String> {
+        Err(format!(
+            "can't read tables from {}, ReeseBot was built without the syzygy feature",
+            path.display()
+        ))
+    }
+
+    //Kings included
+    pub fn max_pieces(&self) -> u32 {
+        self.max_pieces
+    }
+
+    /*
+     * The result with best play under the fifty move rule. Wins the fifty move rule turns into draws count as draws.
+     * None when the tables don't cover the position or can't tell because of rounding in them
+     */
+    #[cfg(feature = "syzygy")]
+    pub fn probe(&self, board: &BoardState) -> Option<GameResult> {
+        use shakmaty_syzygy::AmbiguousWdl;
+
+        let position = to_chess(board)?;
+        let side_to_move_wins = match self.tables.probe_wdl(&position).ok()? {
+            AmbiguousWdl::Win => true,
+            AmbiguousWdl::Loss => false,
+            AmbiguousWdl::CursedWin | AmbiguousWdl::Draw | AmbiguousWdl::BlessedLoss => {
+                return Some(GameResult::Draw)
+            }
+            AmbiguousWdl::MaybeWin | AmbiguousWdl::MaybeLoss => return None,
+        };
+        match (board.active_color, side_to_move_wins) {
+            (Color::White, true) | (Color::Black, false) => Some(GameResult::WhiteWins),
+            (Color::Black, true) | (Color::White, false) => Some(GameResult::BlackWins),
+        }
+    }
+
+    #[cfg(not(feature = "syzygy"))]
+    pub fn probe(&self, _board: &BoardState) -> Option<GameResult> {
+        None
+    }
+}
+
+/* The same position for shakmaty, None when it still has castling rights, which the tables leave out */
+#[cfg(feature = "syzygy")]
+fn to_chess(board: &BoardState) -> Option<shakmaty::Chess> {
+    use shakmaty::{CastlingMode, Chess, File, FromSetup, Piece, Rank, Role, Setup, Square};
+    use std::num::NonZeroU32;
+
+    let rights = board.castle_rights;
+    if rights.can_castle_white_kingside
+        || rights.can_castle_white_queenside
+        || rights.can_castle_black_kingside
+        || rights.can_castle_black_queenside
+    {
+        return None;
+    }
+
+    //Row 2 is the eighth rank and col 2 the a file
+    let square = |row: usize, col: usize| {
+        Square::from_coords(File::new(col as u32 - 2), Rank::new(9 - row as u32))
+    };
+    let to_color = |color: Color| match color {
+        Color::White => shakmaty::Color::White,
+        Color::Black => shakmaty::Color::Black,
+    };
+
+    let mut setup = Setup::empty();
+    for row in 2..10 {
+        for col in 2..10 {
+            if let Some(piece) = board.squares[row][col].piece {
+                let role = match piece.piece_type {
+                    PieceType::Pawn => Role::Pawn,
+                    PieceType::Knight => Role::Knight,
+                    PieceType::Bishop => Role::Bishop,
+                    PieceType::Rook => Role::Rook,
+                    PieceType::Queen => Role::Queen,
+                    PieceType::King => Role::King,
+                    PieceType::None => return None,
+                };
+                setup.board.set_piece_at(
+                    square(row, col),
+                    Piece {
+                        color: to_color(piece.color),
+                        role,
+                    },
+                );
+            }
+        }
+    }
+    setup.turn = to_color(board.active_color);
+    //The board keeps the square of the pawn that moved two, shakmaty the one it skipped over
+    setup.ep_square = board.en_passant.map(|pos| match board.active_color {
+        Color::White => square(pos.row - 1, pos.col),
+        Color::Black => square(pos.row + 1, pos.col),
+    });
+    setup.halfmoves = board.halfmove_clock as u32;
+    setup.fullmoves = NonZeroU32::MIN;
+    Chess::from_setup(setup, CastlingMode::Standard).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needs_a_directory_of_tables() {
+        let empty = std::env::temp_dir().join(format!("reese_bot_syzygy_{}", std::process::id()));
+        std::fs::create_dir_all(&empty).unwrap();
+        let opened = Tablebases::open(&empty);
+        std::fs::remove_dir(&empty).unwrap();
+        assert!(opened.is_err());
+        assert!(Tablebases::open(Path::new("/no/such/directory")).is_err());
+    }
+
+    #[cfg(feature = "syzygy")]
+    #[test]
+    fn hands_positions_to_shakmaty() {
+        use shakmaty::fen::Fen;
+        use shakmaty::{CastlingMode, Chess, EnPassantMode, Position};
+
+        for fen in [
+            "8/8/3k4/8/8/2BNK3/8/8 b - - 0 1",
+            "8/8/8/2k5/3pP3/8/8/4K3 b - e3 7 1",
+            "4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 1",
+            "8/5P2/8/8/1r6/8/2K5/6k1 w - - 23 1",
+        ] {
+            let expected: Chess = fen
+                .parse::<Fen>()
+                .unwrap()
+                .into_position(CastlingMode::Standard)
+                .unwrap();
+            let converted = to_chess(&BoardState::new(fen).unwrap()).unwrap();
+            assert_eq!(
+                Fen::from_position(converted, EnPassantMode::Legal).to_string(),
+                Fen::from_position(expected, EnPassantMode::Legal).to_string()
+            );
+        }
+        assert!(to_chess(&BoardState::new("4k2r/8/8/8/8/8/8/4K3 b k - 0 1").unwrap()).is_none());
+    }
+}
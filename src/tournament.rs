@@ -0,0 +1,1088 @@
+use crate::board_state::BoardState;
+use crate::color::Color;
+use crate::pgn::{pgn_date, to_pgn};
+use crate::pn_search::{prove, Goal, Outcome, PnOptions};
+use crate::san::to_san;
+use crate::self_play::{game_over, GameRecord, GameResult, Score};
+use crate::syzygy::Tablebases;
+use crate::uci::from_uci;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/*
+    Matches and gauntlets between UCI engines run as child processes, ReeseBot itself among them through its uci subcommand.
+    Every pairing plays each opening twice with the colors swapped. Engines are started afresh for every game,
+    so one that crashes or hangs only loses that game. Every game is appended to a PGN file as it finishes.
+*/
+
+//How long an engine gets to answer uci and isready
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+//How long an engine gets to exit after quit before it is killed
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+//How long an engine searching to a depth or node count gets for a move before it is taken to have hung
+const UNTIMED_MOVE_TIMEOUT: Duration = Duration::from_secs(300);
+//Centipawns a reported mate counts as for adjudication, less a move for every move to the mate
+const MATE_SCORE: i32 = 100_000;
+
+/* How long an engine may think, the same for every move of a game */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeControl {
+    //moves is how many have to be played in base before the clock gets base again, None for the whole game
+    Clock {
+        moves: Option<u32>,
+        base: Duration,
+        increment: Duration,
+    },
+    MoveTime(Duration),
+    Depth(u16),
+    Nodes(u64),
+}
+
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or(format!("{} is not a number of seconds", text))
+}
+
+fn format_seconds(duration: Duration) -> String {
+    format!("{}", duration.as_secs_f64())
+}
+
+impl TimeControl {
+    /* Reads a clock like 40/60 for 40 moves in 60 seconds, 10+0.1 for 10 seconds and 0.1 more a move, or 40/60+0.6 */
+    pub fn parse_clock(text: &str) -> Result<TimeControl, String> {
+        let (moves, rest) = match text.split_once('/') {
+            Some((moves, rest)) => (
+                Some(
+                    moves
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|moves| *moves > 0)
+                        .ok_or(format!("{} is not a number of moves", moves))?,
+                ),
+                rest,
+            ),
+            None => (None, text),
+        };
+        let (base, increment) = match rest.split_once('+') {
+            Some((base, increment)) => (parse_seconds(base)?, parse_seconds(increment)?),
+            None => (parse_seconds(rest)?, Duration::ZERO),
+        };
+        Ok(TimeControl::Clock {
+            moves,
+            base,
+            increment,
+        })
+    }
+
+    //For the TimeControl tag of a PGN, - when the limit isn't time
+    pub fn to_pgn(&self) -> String {
+        match *self {
+            TimeControl::Clock {
+                moves,
+                base,
+                increment,
+            } => {
+                let mut text = format_seconds(base);
+                if let Some(moves) = moves {
+                    text = format!("{}/{}", moves, text);
+                }
+                if !increment.is_zero() {
+                    text = format!("{}+{}", text, format_seconds(increment));
+                }
+                text
+            }
+            TimeControl::MoveTime(time) => format!("1/{}", format_seconds(time)),
+            TimeControl::Depth(_) | TimeControl::Nodes(_) => "-".to_string(),
+        }
+    }
+}
+
+/* One engine of a tournament and how to start it */
+#[derive(Clone, Debug)]
+pub struct EngineSpec {
+    pub name: String,
+    pub command: PathBuf,
+    pub args: Vec<String>,
+    pub options: Vec<(String, String)>, //Sent with setoption before the first game
+    pub time_control: TimeControl,
+}
+
+impl EngineSpec {
+    /*
+     * Reads comma separated settings like cmd=./stockfish,name=SF,option.Hash=16,tc=10+0.1
+     * cmd is the only one needed, args are split on spaces and the name defaults to the file name of cmd.
+     * The time control is one of tc= for a clock, st= for seconds a move, depth= or nodes=, and is 60+1 if none is given
+     */
+    pub fn parse(spec: &str) -> Result<EngineSpec, String> {
+        let (mut name, mut command, mut args, mut options) = (None, None, Vec::new(), Vec::new());
+        let mut time_control = TimeControl::Clock {
+            moves: None,
+            base: Duration::from_secs(60),
+            increment: Duration::from_secs(1),
+        };
+        for setting in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|setting| !setting.is_empty())
+        {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or(format!("{} is not a key=value setting", setting))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("{} needs a number, not {}", key, value))
+            };
+            match key {
+                "name" => name = Some(value.to_string()),
+                "cmd" => command = Some(PathBuf::from(value)),
+                "args" => args = value.split_whitespace().map(str::to_string).collect(),
+                "tc" => time_control = TimeControl::parse_clock(value)?,
+                "st" => time_control = TimeControl::MoveTime(parse_seconds(value)?),
+                "depth" => time_control = TimeControl::Depth(number()? as u16),
+                "nodes" => time_control = TimeControl::Nodes(number()?),
+                _ => match key.strip_prefix("option.") {
+                    Some(option) => options.push((option.to_string(), value.to_string())),
+                    None => return Err(format!("unknown setting {}", key)),
+                },
+            }
+        }
+        let command: PathBuf = command.ok_or(format!("{} has no cmd=", spec))?;
+        let name = name.unwrap_or_else(|| {
+            command
+                .file_stem()
+                .map_or(command.display().to_string(), |stem| {
+                    stem.to_string_lossy().to_string()
+                })
+        });
+        Ok(EngineSpec {
+            name,
+            command,
+            args,
+            options,
+            time_control,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EngineError {
+    TimedOut,
+    Disconnected,
+}
+
+/* A running engine and the lines it has printed, read on their own thread so they can be waited on with a timeout */
+pub struct UciEngine {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+/* What an engine answered go with */
+pub struct EngineReply {
+    pub best_move: String,
+    pub score: Option<i32>, //Centipawns from the engine's side, the last score it reported
+}
+
+impl UciEngine {
+    /* Starts the engine and waits until it is ready for a game */
+    pub fn start(spec: &EngineSpec) -> Result<UciEngine, String> {
+        let mut child = Command::new(&spec.command)
+            .args(&spec.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("could not start {}: {}", spec.command.display(), e))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = UciEngine {
+            child,
+            stdin,
+            lines,
+        };
+        let handshake = |engine: &mut UciEngine| -> Result<(), EngineError> {
+            engine.send("uci")?;
+            engine.wait_for("uciok", Some(HANDSHAKE_TIMEOUT), |_| {})?;
+            for (name, value) in &spec.options {
+                engine.send(&format!("setoption name {} value {}", name, value))?;
+            }
+            engine.send("ucinewgame")?;
+            engine.send("isready")?;
+            engine.wait_for("readyok", Some(HANDSHAKE_TIMEOUT), |_| {})?;
+            Ok(())
+        };
+        match handshake(&mut engine) {
+            Ok(()) => Ok(engine),
+            Err(EngineError::TimedOut) => Err(format!("{} did not answer uci", spec.name)),
+            Err(EngineError::Disconnected) => Err(format!("{} exited during uci", spec.name)),
+        }
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), EngineError> {
+        writeln!(self.stdin, "{}", command)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|_| EngineError::Disconnected)
+    }
+
+    /* Reads lines until one starts with token and returns it, handing the others to on_line */
+    fn wait_for(
+        &mut self,
+        token: &str,
+        timeout: Option<Duration>,
+        mut on_line: impl FnMut(&str),
+    ) -> Result<String, EngineError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let line = match deadline {
+                Some(deadline) => self
+                    .lines
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    .map_err(|e| match e {
+                        RecvTimeoutError::Timeout => EngineError::TimedOut,
+                        RecvTimeoutError::Disconnected => EngineError::Disconnected,
+                    })?,
+                None => self.lines.recv().map_err(|_| EngineError::Disconnected)?,
+            };
+            if line.split_whitespace().next() == Some(token) {
+                return Ok(line);
+            }
+            on_line(&line);
+        }
+    }
+
+    /* Sends the position and go commands and waits up to timeout for the move */
+    pub fn go(
+        &mut self,
+        position: &str,
+        go: &str,
+        timeout: Option<Duration>,
+    ) -> Result<EngineReply, EngineError> {
+        self.send(position)?;
+        self.send(go)?;
+        let mut score = None;
+        let line = self.wait_for("bestmove", timeout, |line| {
+            if let Some(reported) = parse_score(line) {
+                score = Some(reported);
+            }
+        })?;
+        let best_move = line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or("(none)")
+            .to_string();
+        Ok(EngineReply { best_move, score })
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let start = Instant::now();
+        while start.elapsed() < QUIT_TIMEOUT {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/* The score of an info line in centipawns, mates counted as MATE_SCORE less the moves to go */
+fn parse_score(line: &str) -> Option<i32> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.first() != Some(&"info") {
+        return None;
+    }
+    let index = tokens.iter().position(|token| *token == "score")?;
+    let value: i32 = tokens.get(index + 2)?.parse().ok()?;
+    match *tokens.get(index + 1)? {
+        "cp" => Some(value),
+        "mate" if value > 0 => Some(MATE_SCORE - value),
+        "mate" => Some(-MATE_SCORE - value),
+        _ => None,
+    }
+}
+
+/* Time an engine has left */
+struct Clock {
+    control: TimeControl,
+    left: Duration,
+    moves_left: Option<u32>, //Before the clock gets its base time again
+}
+
+impl Clock {
+    fn new(control: TimeControl) -> Clock {
+        match control {
+            TimeControl::Clock { moves, base, .. } => Clock {
+                control,
+                left: base,
+                moves_left: moves,
+            },
+            _ => Clock {
+                control,
+                left: Duration::ZERO,
+                moves_left: None,
+            },
+        }
+    }
+
+    //How long to wait for a move before it is lost on time
+    fn deadline(&self, margin: Duration) -> Duration {
+        match self.control {
+            TimeControl::Clock { .. } => self.left + margin,
+            TimeControl::MoveTime(time) => time + margin,
+            TimeControl::Depth(_) | TimeControl::Nodes(_) => UNTIMED_MOVE_TIMEOUT,
+        }
+    }
+
+    //Takes the time a move took off the clock, false if that ran it out
+    fn spend(&mut self, elapsed: Duration, margin: Duration) -> bool {
+        match self.control {
+            TimeControl::Clock {
+                moves,
+                base,
+                increment,
+            } => {
+                if elapsed > self.left + margin {
+                    return false;
+                }
+                self.left = self.left.saturating_sub(elapsed) + increment;
+                match self.moves_left {
+                    Some(1) => {
+                        self.left += base;
+                        self.moves_left = moves;
+                    }
+                    Some(left) => self.moves_left = Some(left - 1),
+                    None => {}
+                }
+                true
+            }
+            TimeControl::MoveTime(time) => elapsed <= time + margin,
+            TimeControl::Depth(_) | TimeControl::Nodes(_) => true,
+        }
+    }
+}
+
+/* The go command for the side to move, telling it both clocks the way UCI expects */
+fn go_command(mover: &Clock, clocks: &[Clock; 2]) -> String {
+    match mover.control {
+        TimeControl::MoveTime(time) => format!("go movetime {}", time.as_millis()),
+        TimeControl::Depth(depth) => format!("go depth {}", depth),
+        TimeControl::Nodes(nodes) => format!("go nodes {}", nodes),
+        TimeControl::Clock { .. } => {
+            let mut go = "go".to_string();
+            for (clock, side) in clocks.iter().zip(["w", "b"]) {
+                if let TimeControl::Clock { increment, .. } = clock.control {
+                    go.push_str(&format!(
+                        " {}time {} {}inc {}",
+                        side,
+                        clock.left.as_millis(),
+                        side,
+                        increment.as_millis()
+                    ));
+                }
+            }
+            if let Some(moves) = mover.moves_left {
+                go.push_str(&format!(" movestogo {}", moves));
+            }
+            go
+        }
+    }
+}
+
+/* A loss once an engine has reported a score of -score or worse for move_count of its moves in a row */
+#[derive(Clone, Copy, Debug)]
+pub struct ResignRule {
+    pub move_count: usize,
+    pub score: i32,
+}
+
+/* A draw once both engines have reported scores within score of 0 for move_count moves each, from move_number on */
+#[derive(Clone, Copy, Debug)]
+pub struct DrawRule {
+    pub move_number: u32,
+    pub move_count: usize,
+    pub score: i32,
+}
+
+/*
+ * A win once at most max_pieces are left, kings included, and proof-number search finds a forced mate for the side to move.
+ * A win the search can't prove in node_budget plays on and drawn endings are left to the other rules, see Adjudication::syzygy
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct MateProofRule {
+    pub max_pieces: u32,
+    pub node_budget: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Adjudication {
+    pub resign: Option<ResignRule>,
+    pub draw: Option<DrawRule>,
+    pub mate_proof: Option<MateProofRule>,
+    //Games end in the tables' result once they cover the position
+    pub syzygy: Option<Arc<Tablebases>>,
+}
+
+fn count_pieces(board: &BoardState) -> u32 {
+    (2..10)
+        .flat_map(|row| (2..10).map(move |col| (row, col)))
+        .filter(|&(row, col)| board.squares[row][col].piece.is_some())
+        .count() as u32
+}
+
+fn win_for(color: Color) -> GameResult {
+    match color {
+        Color::White => GameResult::WhiteWins,
+        Color::Black => GameResult::BlackWins,
+    }
+}
+
+impl Adjudication {
+    /*
+     * Called after every move of a game that isn't over. scores holds what each move's engine reported, with the color that played it.
+     * move_number is the number of the move about to be played
+     */
+    pub fn check(
+        &self,
+        board: &BoardState,
+        move_number: u32,
+        scores: &[(Color, Option<i32>)],
+    ) -> Option<(GameResult, &'static str)> {
+        //The last count scores of color's engine, None if it hasn't played that many moves or left some unscored
+        let last_scores = |color: Color, count: usize| -> Option<Vec<i32>> {
+            let reported: Vec<Option<i32>> = scores
+                .iter()
+                .rev()
+                .filter(|(mover, _)| *mover == color)
+                .take(count)
+                .map(|(_, score)| *score)
+                .collect();
+            if reported.len() < count.max(1) {
+                return None;
+            }
+            reported.into_iter().collect()
+        };
+
+        if let (Some(rule), Some(&(mover, _))) = (self.resign, scores.last()) {
+            if last_scores(mover, rule.move_count)
+                .is_some_and(|scores| scores.iter().all(|score| *score <= -rule.score))
+            {
+                return Some((win_for(mover.opposite()), "resign adjudication"));
+            }
+        }
+        if let Some(rule) = self.draw {
+            let level = |color| {
+                last_scores(color, rule.move_count)
+                    .is_some_and(|scores| scores.iter().all(|score| score.abs() <= rule.score))
+            };
+            if move_number >= rule.move_number && level(Color::White) && level(Color::Black) {
+                return Some((GameResult::Draw, "draw adjudication"));
+            }
+        }
+        if let Some(tables) = &self.syzygy {
+            if count_pieces(board) <= tables.max_pieces() {
+                if let Some(result) = tables.probe(board) {
+                    return Some((result, "tablebase adjudication"));
+                }
+            }
+        }
+        if let Some(rule) = self.mate_proof {
+            if count_pieces(board) <= rule.max_pieces {
+                let proof = prove(
+                    board,
+                    &PnOptions {
+                        goal: Goal::Mate,
+                        node_budget: rule.node_budget,
+                        ..PnOptions::default()
+                    },
+                );
+                if proof.outcome == Outcome::Proven {
+                    return Some((win_for(board.active_color), "mate proof adjudication"));
+                }
+            }
+        }
+        None
+    }
+}
+
+pub struct TournamentSettings {
+    pub engines: Vec<EngineSpec>,
+    //The first engine plays every other one, instead of everyone playing everyone
+    pub gauntlet: bool,
+    pub openings: Vec<String>,
+    //Times every pairing plays each opening with both colors
+    pub rounds: usize,
+    pub concurrency: usize,
+    //How far past its clock an engine may go before it loses on time
+    pub time_margin: Duration,
+    pub adjudication: Adjudication,
+    pub pgn: Option<PathBuf>, //Games are appended to it
+}
+
+/* A game of the schedule, the engines are indexes into the settings */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ScheduledGame {
+    pub round: usize,
+    pub white: usize,
+    pub black: usize,
+    pub opening: usize,
+}
+
+pub fn schedule(settings: &TournamentSettings) -> Vec<ScheduledGame> {
+    let count = settings.engines.len();
+    let pairings: Vec<(usize, usize)> = if settings.gauntlet {
+        (1..count).map(|other| (0, other)).collect()
+    } else {
+        (0..count)
+            .flat_map(|first| (first + 1..count).map(move |second| (first, second)))
+            .collect()
+    };
+
+    let mut games = Vec::new();
+    for round in 0..settings.rounds {
+        for &(first, second) in &pairings {
+            for opening in 0..settings.openings.len() {
+                for (white, black) in [(first, second), (second, first)] {
+                    games.push(ScheduledGame {
+                        round,
+                        white,
+                        black,
+                        opening,
+                    });
+                }
+            }
+        }
+    }
+    games
+}
+
+fn lost_by(record: GameRecord, loser: Color, reason: &'static str) -> GameRecord {
+    GameRecord {
+        result: win_for(loser.opposite()),
+        reason,
+        ..record
+    }
+}
+
+/* Plays one game, starting both engines for it and quitting them after */
+pub fn play_uci_game(
+    white: &EngineSpec,
+    black: &EngineSpec,
+    opening: &str,
+    time_margin: Duration,
+    adjudication: &Adjudication,
+) -> GameRecord {
+    let mut board = BoardState::new(opening).unwrap();
+    let mut record = GameRecord {
+        start_fen: opening.to_string(),
+        moves: Vec::new(),
+        result: GameResult::Draw,
+        reason: "",
+    };
+    let mut engines = match (UciEngine::start(white), UciEngine::start(black)) {
+        (Ok(white), Ok(black)) => [white, black],
+        (Err(_), _) => return lost_by(record, Color::White, "disconnected"),
+        (_, Err(_)) => return lost_by(record, Color::Black, "disconnected"),
+    };
+    let mut clocks = [
+        Clock::new(white.time_control),
+        Clock::new(black.time_control),
+    ];
+
+    let mut move_number: u32 = opening
+        .split_whitespace()
+        .nth(5)
+        .and_then(|field| field.parse().ok())
+        .unwrap_or(1);
+    let mut history = vec![board.zobrist_key()];
+    let mut uci_moves: Vec<String> = Vec::new();
+    let mut scores = Vec::new();
+    loop {
+        let ended = game_over(&board, &history)
+            .or_else(|| adjudication.check(&board, move_number, &scores));
+        if let Some((result, reason)) = ended {
+            record.result = result;
+            record.reason = reason;
+            return record;
+        }
+
+        let mover = board.active_color;
+        let side = match mover {
+            Color::White => 0,
+            Color::Black => 1,
+        };
+        let mut position = format!("position fen {}", opening);
+        if !uci_moves.is_empty() {
+            position.push_str(&format!(" moves {}", uci_moves.join(" ")));
+        }
+        let go = go_command(&clocks[side], &clocks);
+        let start = Instant::now();
+        let reply = engines[side].go(&position, &go, Some(clocks[side].deadline(time_margin)));
+        let elapsed = start.elapsed();
+
+        let reply = match reply {
+            Ok(reply) if clocks[side].spend(elapsed, time_margin) => reply,
+            Ok(_) | Err(EngineError::TimedOut) => return lost_by(record, mover, "time forfeit"),
+            Err(EngineError::Disconnected) => return lost_by(record, mover, "disconnected"),
+        };
+        let Some(mv) = from_uci(&board, &reply.best_move) else {
+            return lost_by(record, mover, "illegal move");
+        };
+
+        record.moves.push(to_san(&board, &mv));
+        uci_moves.push(reply.best_move);
+        scores.push((mover, reply.score));
+        board.make_move(&mv);
+        history.push(board.zobrist_key());
+        if mover == Color::Black {
+            move_number += 1;
+        }
+    }
+}
+
+//The Termination tag of a PGN for why a game ended
+fn termination(reason: &str) -> &'static str {
+    match reason {
+        "time forfeit" => "time forfeit",
+        "illegal move" => "rules infraction",
+        "disconnected" => "abandoned",
+        "resign adjudication"
+        | "draw adjudication"
+        | "tablebase adjudication"
+        | "mate proof adjudication"
+        | "too long" => "adjudication",
+        _ => "normal",
+    }
+}
+
+/* Every engine's score against every other one */
+pub struct Crosstable {
+    pub names: Vec<String>,
+    pub scores: Vec<Vec<Score>>, //scores[first][second] is from first's side
+}
+
+impl Crosstable {
+    pub fn new(names: Vec<String>) -> Crosstable {
+        let count = names.len();
+        Crosstable {
+            names,
+            scores: vec![vec![Score::default(); count]; count],
+        }
+    }
+
+    pub fn add(&mut self, white: usize, black: usize, result: GameResult) {
+        match result {
+            GameResult::WhiteWins => {
+                self.scores[white][black].wins += 1;
+                self.scores[black][white].losses += 1;
+            }
+            GameResult::BlackWins => {
+                self.scores[white][black].losses += 1;
+                self.scores[black][white].wins += 1;
+            }
+            GameResult::Draw => {
+                self.scores[white][black].draws += 1;
+                self.scores[black][white].draws += 1;
+            }
+        }
+    }
+
+    //An engine's results against everyone together
+    pub fn total(&self, engine: usize) -> Score {
+        self.scores[engine]
+            .iter()
+            .fold(Score::default(), |total, score| Score {
+                wins: total.wins + score.wins,
+                draws: total.draws + score.draws,
+                losses: total.losses + score.losses,
+            })
+    }
+}
+
+fn points(score: &Score) -> f64 {
+    score.wins as f64 + score.draws as f64 / 2.0
+}
+
+/* Engines from most points to fewest, with their Elo against the field and their points against each opponent */
+impl fmt::Display for Crosstable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .names
+            .iter()
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        let mut order: Vec<usize> = (0..self.names.len()).collect();
+        order.sort_by(|a, b| points(&self.total(*b)).total_cmp(&points(&self.total(*a))));
+
+        write!(
+            f,
+            "{:>4}  {:<width$}  {:>7}  {:>5}  {:>15}",
+            "Rank", "Name", "Points", "Games", "Elo"
+        )?;
+        for &engine in &order {
+            write!(f, "  {:>9}", abbreviate(&self.names[engine], 9))?;
+        }
+        writeln!(f)?;
+
+        for (rank, &engine) in order.iter().enumerate() {
+            let total = self.total(engine);
+            let elo = if total.games() == 0 {
+                "-".to_string()
+            } else {
+                //Adding zero turns an even score's -0 into 0
+                format!(
+                    "{:.0} +/- {:.0}",
+                    total.elo().round() + 0.0,
+                    total.elo_error()
+                )
+            };
+            write!(
+                f,
+                "{:>4}  {:<width$}  {:>7.1}  {:>5}  {:>15}",
+                rank + 1,
+                self.names[engine],
+                points(&total),
+                total.games(),
+                elo
+            )?;
+            for &opponent in &order {
+                let score = &self.scores[engine][opponent];
+                let cell = if opponent == engine || score.games() == 0 {
+                    "-".to_string()
+                } else {
+                    format!("{}/{}", points(score), score.games())
+                };
+                write!(f, "  {:>9}", cell)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn abbreviate(name: &str, width: usize) -> String {
+    name.chars().take(width).collect()
+}
+
+fn open_pgn(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("could not open {}: {}", path.display(), e))
+}
+
+/*
+ * Plays the whole schedule on concurrency threads. Every engine is started once first so a broken command stops the tournament
+ * before any game. on_game gets each finished game with its number in the schedule and the crosstable so far.
+ */
+pub fn run_tournament(
+    settings: &TournamentSettings,
+    on_game: impl Fn(usize, &ScheduledGame, &GameRecord, &Crosstable) + Sync,
+) -> Result<Crosstable, String> {
+    if settings.engines.len() < 2 {
+        return Err("a tournament needs at least two engines".to_string());
+    }
+    for (index, engine) in settings.engines.iter().enumerate() {
+        if settings.engines[..index]
+            .iter()
+            .any(|other| other.name == engine.name)
+        {
+            return Err(format!(
+                "two engines are named {}, give them name=",
+                engine.name
+            ));
+        }
+        UciEngine::start(engine)?;
+    }
+    let pgn = match &settings.pgn {
+        Some(path) => Some(Mutex::new(open_pgn(path)?)),
+        None => None,
+    };
+
+    let games = schedule(settings);
+    let event = if settings.gauntlet {
+        "ReeseBot gauntlet"
+    } else {
+        "ReeseBot tournament"
+    };
+    let crosstable = Mutex::new(Crosstable::new(
+        settings
+            .engines
+            .iter()
+            .map(|engine| engine.name.clone())
+            .collect(),
+    ));
+    let next = AtomicUsize::new(0);
+    let error = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..settings.concurrency.max(1).min(games.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(game) = games.get(index) else {
+                    break;
+                };
+                let (white, black) = (&settings.engines[game.white], &settings.engines[game.black]);
+                let record = play_uci_game(
+                    white,
+                    black,
+                    &settings.openings[game.opening],
+                    settings.time_margin,
+                    &settings.adjudication,
+                );
+
+                let mut crosstable = crosstable.lock().unwrap();
+                crosstable.add(game.white, game.black, record.result);
+                if let Some(pgn) = &pgn {
+                    let mut tags = vec![
+                        ("Event", event.to_string()),
+                        ("Site", "?".to_string()),
+                        ("Date", pgn_date(SystemTime::now())),
+                        ("Round", (game.round + 1).to_string()),
+                        ("White", white.name.clone()),
+                        ("Black", black.name.clone()),
+                        ("Result", record.result.to_pgn().to_string()),
+                    ];
+                    if white.time_control == black.time_control {
+                        tags.push(("TimeControl", white.time_control.to_pgn()));
+                    } else {
+                        tags.push(("WhiteTimeControl", white.time_control.to_pgn()));
+                        tags.push(("BlackTimeControl", black.time_control.to_pgn()));
+                    }
+                    tags.push(("PlyCount", record.moves.len().to_string()));
+                    tags.push(("Termination", termination(record.reason).to_string()));
+                    if let Err(e) = pgn
+                        .lock()
+                        .unwrap()
+                        .write_all(to_pgn(&tags, &record).as_bytes())
+                    {
+                        error.lock().unwrap().get_or_insert(e.to_string());
+                    }
+                }
+                on_game(index, game, &record, &crosstable);
+            });
+        }
+    });
+
+    match error.into_inner().unwrap() {
+        Some(e) => Err(format!("could not write the PGN: {}", e)),
+        None => Ok(crosstable.into_inner().unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_engines_and_clocks() {
+        let engine = EngineSpec::parse(
+            "cmd=/usr/bin/stockfish, args=--threads 1 uci,option.Hash=16,tc=40/60+0.5",
+        )
+        .unwrap();
+        assert_eq!(engine.name, "stockfish");
+        assert_eq!(engine.args, vec!["--threads", "1", "uci"]);
+        assert_eq!(engine.options, vec![("Hash".to_string(), "16".to_string())]);
+        assert_eq!(
+            engine.time_control,
+            TimeControl::Clock {
+                moves: Some(40),
+                base: Duration::from_secs(60),
+                increment: Duration::from_millis(500),
+            }
+        );
+        assert_eq!(engine.time_control.to_pgn(), "40/60+0.5");
+        assert_eq!(
+            EngineSpec::parse("cmd=sf,st=0.25")
+                .unwrap()
+                .time_control
+                .to_pgn(),
+            "1/0.25"
+        );
+        assert!(EngineSpec::parse("name=nothing to run").is_err());
+        assert!(EngineSpec::parse("cmd=sf,tc=0/60").is_err());
+        assert!(EngineSpec::parse("cmd=sf,ponder=on").is_err());
+        assert_eq!(parse_score("info depth 3 score cp -25 nodes 90"), Some(-25));
+        assert_eq!(
+            parse_score("info depth 5 score mate -2 pv"),
+            Some(-MATE_SCORE + 2)
+        );
+        assert_eq!(parse_score("info string score cp"), None);
+    }
+
+    #[test]
+    fn keeps_clocks() {
+        let mut clocks = [
+            Clock::new(TimeControl::parse_clock("2/10+1").unwrap()),
+            Clock::new(TimeControl::MoveTime(Duration::from_secs(1))),
+        ];
+        assert_eq!(
+            go_command(&clocks[0], &clocks),
+            "go wtime 10000 winc 1000 movestogo 2"
+        );
+        assert_eq!(go_command(&clocks[1], &clocks), "go movetime 1000");
+        let margin = Duration::from_millis(100);
+        assert_eq!(clocks[1].deadline(margin), Duration::from_millis(1100));
+        //A hung engine still loses the game when the limit isn't time
+        let depth = Clock::new(TimeControl::Depth(3));
+        assert_eq!(depth.deadline(margin), UNTIMED_MOVE_TIMEOUT);
+
+        assert!(clocks[0].spend(Duration::from_secs(4), margin));
+        assert_eq!(clocks[0].left, Duration::from_secs(7));
+        //The second move of the period gives the base time back
+        assert!(clocks[0].spend(Duration::from_secs(2), margin));
+        assert_eq!(clocks[0].left, Duration::from_secs(16));
+        assert_eq!(clocks[0].moves_left, Some(2));
+        assert!(!clocks[0].spend(Duration::from_secs(17), margin));
+        assert!(clocks[1].spend(Duration::from_millis(1050), margin));
+        assert!(!clocks[1].spend(Duration::from_millis(1200), margin));
+    }
+
+    #[test]
+    fn adjudicates() {
+        let board =
+            BoardState::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let adjudication = Adjudication {
+            resign: Some(ResignRule {
+                move_count: 2,
+                score: 500,
+            }),
+            draw: Some(DrawRule {
+                move_number: 10,
+                move_count: 2,
+                score: 10,
+            }),
+            ..Adjudication::default()
+        };
+        let losing = [
+            (Color::White, Some(-600)),
+            (Color::Black, Some(600)),
+            (Color::White, Some(-MATE_SCORE + 3)),
+        ];
+        assert_eq!(
+            adjudication.check(&board, 2, &losing),
+            Some((GameResult::BlackWins, "resign adjudication"))
+        );
+        assert_eq!(adjudication.check(&board, 2, &losing[..2]), None);
+        let unscored = [
+            (Color::White, None),
+            (Color::Black, Some(0)),
+            (Color::White, Some(-600)),
+        ];
+        assert_eq!(adjudication.check(&board, 2, &unscored), None);
+
+        let level = [
+            (Color::White, Some(5)),
+            (Color::Black, Some(-10)),
+            (Color::White, Some(0)),
+            (Color::Black, Some(3)),
+        ];
+        assert_eq!(
+            adjudication.check(&board, 12, &level),
+            Some((GameResult::Draw, "draw adjudication"))
+        );
+        assert_eq!(adjudication.check(&board, 9, &level), None);
+
+        let mate_proof = Adjudication {
+            mate_proof: Some(MateProofRule {
+                max_pieces: 3,
+                node_budget: 10_000,
+            }),
+            ..Adjudication::default()
+        };
+        let mate = BoardState::new("6k1/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap();
+        assert_eq!(
+            mate_proof.check(&mate, 1, &[]),
+            Some((GameResult::WhiteWins, "mate proof adjudication"))
+        );
+        assert_eq!(mate_proof.check(&board, 1, &[]), None);
+    }
+
+    #[test]
+    fn schedules_and_tallies() {
+        let engine = |name: &str| EngineSpec::parse(&format!("cmd=engine,name={}", name)).unwrap();
+        let mut settings = TournamentSettings {
+            engines: vec![engine("a"), engine("b"), engine("c")],
+            gauntlet: false,
+            openings: vec!["first".to_string(), "second".to_string()],
+            rounds: 2,
+            concurrency: 1,
+            time_margin: Duration::ZERO,
+            adjudication: Adjudication::default(),
+            pgn: None,
+        };
+        let games = schedule(&settings);
+        assert_eq!(games.len(), 2 * 3 * 2 * 2);
+        assert_eq!(
+            &games[..2],
+            &[
+                ScheduledGame {
+                    round: 0,
+                    white: 0,
+                    black: 1,
+                    opening: 0
+                },
+                ScheduledGame {
+                    round: 0,
+                    white: 1,
+                    black: 0,
+                    opening: 0
+                }
+            ]
+        );
+        settings.gauntlet = true;
+        let games = schedule(&settings);
+        assert_eq!(games.len(), 2 * 2 * 2 * 2);
+        assert!(games.iter().all(|game| game.white == 0 || game.black == 0));
+
+        let mut crosstable =
+            Crosstable::new(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        crosstable.add(0, 1, GameResult::WhiteWins);
+        crosstable.add(1, 0, GameResult::Draw);
+        crosstable.add(2, 0, GameResult::BlackWins);
+        assert_eq!(
+            crosstable.total(0),
+            Score {
+                wins: 2,
+                draws: 1,
+                losses: 0
+            }
+        );
+        let text = crosstable.to_string();
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert!(rows[1].starts_with("   1  a"), "{}", text);
+        assert!(rows[1].ends_with("-      1.5/2        1/1"), "{}", text);
+        assert!(rows[3].starts_with("   3  c"), "{}", text);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::chess_move::MoveV2;
+use std::mem::size_of;
+
+/*
+    Transposition table, see https://www.chessprogramming.org/Transposition_Table
+    Entries are indexed by the low bits of the zobrist key and always replaced.
+*/
+
+pub const DEFAULT_TABLE_SIZE: usize = 1 << 16;
+
+/* Whether the stored score is exact or only a bound from a cutoff */
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Bound {
+    Exact,
+    Lower, //Failed high, the real score is at least this
+    Upper, //Failed low, the real score is at most this
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub key: u64,
+    pub best_move: Option<MoveV2>,
+    pub depth: u16,
+    pub score: i32,
+    pub bound: Bound,
+}
+
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+}
+
+impl TranspositionTable {
+    //Size is rounded up to a power of two
+    pub fn new(size: usize) -> TranspositionTable {
+        TranspositionTable {
+            entries: vec![None; size.next_power_of_two()],
+        }
+    }
+
+    /* Most entries that fit in the given megabytes, kept a power of two so new doesn't round it past them */
+    pub fn entries_in_megabytes(megabytes: usize) -> usize {
+        let entries = ((megabytes << 20) / size_of::<Option<Entry>>()).max(1);
+        1 << entries.ilog2()
+    }
+
+    /* Megabytes taken by a table of this many entries, rounded up */
+    pub fn megabytes_for_entries(entries: usize) -> usize {
+        (entries.next_power_of_two() * size_of::<Option<Entry>>()).div_ceil(1 << 20)
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key as usize) & (self.entries.len() - 1)
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        match self.entries[self.index(key)] {
+            Some(entry) if entry.key == key => Some(entry),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, entry: Entry) {
+        let index = self.index(entry.key);
+        self.entries[index] = Some(entry);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::board_state::BoardState;
+use crate::color::Color;
+use crate::engine::quiescence_score;
+use crate::evaluation::{evaluate, linear_eval, EvalParams, LinearEval, NUM_EVAL_PARAMS};
+use log::info;
+
+/*
+    Texel tuning, see https://www.chessprogramming.org/Texel%27s_Tuning_Method
+    Fits the EvalParams values to game results by minimising the squared error between the result and a sigmoid of the evaluation.
+    Every position is broken into features once, see LinearEval, and every value in EvalParams is tuned.
+    The evaluation is linear in those values apart from the scale factors, which multiply the endgame score and get their gradient from it,
+    and the king attack weights, whose gradient comes from the slope of king_danger at the params the features were made with.
+*/
+
+const ADAM_BETA1: f64 = 0.9;
+const ADAM_BETA2: f64 = 0.999;
+const ADAM_EPSILON: f64 = 1e-8;
+
+/* A quiet position and the result of the game it came from, 1.0 for a white win */
+pub struct TuningPosition {
+    pub eval: LinearEval,
+    pub result: f64,
+}
+
+/*
+ * Reads one position per line, a full FEN followed by the result as [1.0], [0.5] or [0.0], or as "1-0", "1/2-1/2" or "0-1".
+ * Positions that are in check or not quiet are skipped, the rest are broken into features with params.
+ * Returns the positions and how many lines were skipped
+ */
+pub fn parse_dataset(
+    text: &str,
+    params: &EvalParams,
+) -> Result<(Vec<TuningPosition>, usize), String> {
+    let mut positions = Vec::new();
+    let mut skipped = 0;
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 7 {
+            return Err(format!(
+                "Line {}: expected a fen and a result",
+                line_number + 1
+            ));
+        }
+        let result = match fields[6].trim_matches(|c| c == '"' || c == ';') {
+            "[1.0]" | "1-0" => 1.0,
+            "[0.5]" | "1/2-1/2" => 0.5,
+            "[0.0]" | "0-1" => 0.0,
+            other => {
+                return Err(format!(
+                    "Line {}: unknown result {}",
+                    line_number + 1,
+                    other
+                ))
+            }
+        };
+        let board = BoardState::new(&fields[..6].join(" "))
+            .map_err(|e| format!("Line {}: {}", line_number + 1, e))?;
+
+        if !is_quiet(&board) {
+            skipped += 1;
+            continue;
+        }
+        positions.push(TuningPosition {
+            eval: linear_eval(&board, params),
+            result,
+        });
+    }
+
+    Ok((positions, skipped))
+}
+
+//No checks and nothing left to capture that changes the score
+fn is_quiet(board: &BoardState) -> bool {
+    !board.is_in_check(board.active_color, None) && quiescence_score(board) == evaluate(board)
+}
+
+fn sigmoid(scaling: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-scaling * eval / 400.0))
+}
+
+/* Mean squared error between the results and the predicted results */
+pub fn mean_squared_error(positions: &[TuningPosition], values: &[f64], scaling: f64) -> f64 {
+    let total: f64 = positions
+        .iter()
+        .map(|position| {
+            let error = position.result - sigmoid(scaling, position.eval.evaluate(values));
+            error * error
+        })
+        .sum();
+    total / positions.len() as f64
+}
+
+/* Scaling constant for the sigmoid that best fits the current values, golden section search */
+pub fn fit_scaling_constant(positions: &[TuningPosition], values: &[f64]) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (0.0, 10.0);
+    for _ in 0..60 {
+        let first = high - ratio * (high - low);
+        let second = low + ratio * (high - low);
+        if mean_squared_error(positions, values, first)
+            < mean_squared_error(positions, values, second)
+        {
+            high = second;
+        } else {
+            low = first;
+        }
+    }
+    (low + high) / 2.0
+}
+
+fn gradient(positions: &[TuningPosition], values: &[f64], scaling: f64) -> Vec<f64> {
+    let mut gradient = vec![0.0; values.len()];
+    let ln10 = 10f64.ln();
+    for position in positions {
+        let eval = &position.eval;
+        let predicted = sigmoid(scaling, eval.evaluate(values));
+        //Derivative of the squared error with respect to the evaluation
+        let error =
+            -2.0 * (position.result - predicted) * predicted * (1.0 - predicted) * ln10 * scaling
+                / 400.0;
+        let mg_weight = eval.game_phase as f64 / 24.0;
+        let eg_weight = (24 - eval.game_phase) as f64 / 24.0 * eval.scale(values) / 64.0;
+        for &(index, count) in &eval.mg_features {
+            gradient[index] += error * mg_weight * count as f64;
+        }
+        for &(index, count) in &eval.eg_features {
+            gradient[index] += error * eg_weight * count as f64;
+        }
+        //A scale factor that is one of the values multiplies the whole endgame score
+        if let Some(index) = eval.scale_feature {
+            let (_, eg) = eval.phase_scores(values);
+            gradient[index] += error * (24 - eval.game_phase) as f64 / 24.0 * eg / 64.0;
+        }
+    }
+    let total = positions.len() as f64;
+    gradient.iter_mut().for_each(|value| *value /= total);
+    gradient
+}
+
+/*
+ * Tunes every EvalParams value with Adam, starting from the params the positions were parsed with and keeping the sigmoid scaling fitted to them.
+ * Returns the tuned params, rounded to whole centipawns
+ */
+pub fn tune(
+    positions: &[TuningPosition],
+    start: &EvalParams,
+    iterations: u32,
+    learning_rate: f64,
+) -> EvalParams {
+    let mut values: Vec<f64> = start.to_vec().iter().map(|&value| value as f64).collect();
+    let scaling = fit_scaling_constant(positions, &values);
+    info!(
+        "{} positions, scaling constant {:.3}, starting error {:.6}, tuning {} values",
+        positions.len(),
+        scaling,
+        mean_squared_error(positions, &values, scaling),
+        NUM_EVAL_PARAMS
+    );
+
+    let mut momentum = vec![0.0; NUM_EVAL_PARAMS];
+    let mut velocity = vec![0.0; NUM_EVAL_PARAMS];
+    for iteration in 1..=iterations {
+        let gradient = gradient(positions, &values, scaling);
+        for index in 0..NUM_EVAL_PARAMS {
+            momentum[index] = ADAM_BETA1 * momentum[index] + (1.0 - ADAM_BETA1) * gradient[index];
+            velocity[index] =
+                ADAM_BETA2 * velocity[index] + (1.0 - ADAM_BETA2) * gradient[index].powi(2);
+            let momentum_hat = momentum[index] / (1.0 - ADAM_BETA1.powi(iteration as i32));
+            let velocity_hat = velocity[index] / (1.0 - ADAM_BETA2.powi(iteration as i32));
+            values[index] -= learning_rate * momentum_hat / (velocity_hat.sqrt() + ADAM_EPSILON);
+        }
+
+        if iteration % 100 == 0 || iteration == iterations {
+            info!(
+                "Iteration {}, error {:.6}",
+                iteration,
+                mean_squared_error(positions, &values, scaling)
+            );
+        }
+    }
+
+    let rounded: Vec<i32> = values.iter().map(|value| value.round() as i32).collect();
+    EvalParams::from_slice(&rounded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_DATASET: &str = include_str!("../data/tuning_positions.txt");
+
+    fn as_values(params: &EvalParams) -> Vec<f64> {
+        params.to_vec().iter().map(|&value| value as f64).collect()
+    }
+
+    #[test]
+    fn parses_sample_dataset() {
+        let (positions, skipped) = parse_dataset(SAMPLE_DATASET, &EvalParams::default()).unwrap();
+        assert!(positions.len() > 100);
+        assert!(skipped > 0);
+        assert!(positions.iter().any(|position| position.result == 1.0));
+        assert!(positions.iter().any(|position| position.result == 0.5));
+        assert!(positions.iter().any(|position| position.result == 0.0));
+
+        assert!(parse_dataset(
+            "8/8/4k3/8/8/3K4/8/8 w - - 0 1 [2.0]",
+            &EvalParams::default()
+        )
+        .is_err());
+        assert!(parse_dataset("8/8/4k3/8/8/3K4/8/8 w - - 0 1", &EvalParams::default()).is_err());
+    }
+
+    #[test]
+    fn tuning_lowers_the_error() {
+        let (positions, _) = parse_dataset(SAMPLE_DATASET, &EvalParams::default()).unwrap();
+        let start = EvalParams::default();
+        let scaling = fit_scaling_constant(&positions, &as_values(&start));
+        assert!(scaling > 0.0 && scaling < 10.0);
+
+        let tuned = tune(&positions, &start, 50, 1.0);
+        let before = mean_squared_error(&positions, &as_values(&start), scaling);
+        let after = mean_squared_error(&positions, &as_values(&tuned), scaling);
+        assert!(after < before);
+
+        //The output has to be a file the engine can load
+        assert_eq!(EvalParams::from_toml(&tuned.to_toml()), Ok(tuned));
+    }
+}
@@ -0,0 +1,408 @@
+use crate::board_state::BoardState;
+use crate::chess_move::Move;
+use crate::color::Color;
+use crate::engine::{
+    SearchOptions, SearchResult, SkillLevel, MATE_BOUND, MATE_VALUE, MAX_SKILL_LEVEL,
+};
+use crate::evaluation::EvalParams;
+use crate::move_gen::gen_all_moves;
+use crate::searcher::{make_searcher, SearchLimits, SearcherKind};
+use crate::transposition_table::TranspositionTable;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/*
+    The engine side of the Universal Chess Interface, so GUIs and tournament managers can run ReeseBot. See https://www.chessprogramming.org/UCI
+    A search runs to the end before the next command is read, so stop is ignored and go infinite thinks for the default time.
+    The options are Hash, Contempt, Skill Level and EvalParams, a path to weights saved by tune. Setting one starts a new searcher.
+*/
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+//Moves still to play when the GUI doesn't send movestogo
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+
+const MAX_HASH_MB: usize = 4096;
+const MAX_CONTEMPT: i32 = 1000;
+
+/* The legal move written in UCI notation like e2e4, e1g1 or e7e8q, None if there is none */
+pub fn from_uci(board: &BoardState, text: &str) -> Option<Move> {
+    gen_all_moves(board, board.active_color)
+        .into_iter()
+        .find(|mv| mv.to_uci(board.active_color) == text)
+}
+
+/* A score the way UCI wants it, cp 35 or mate -2 counting moves rather than plies */
+pub fn format_score(score: i32) -> String {
+    if score.abs() >= MATE_BOUND {
+        let moves = (MATE_VALUE - score.abs() + 1) / 2;
+        format!("mate {}", if score > 0 { moves } else { -moves })
+    } else {
+        format!("cp {}", score)
+    }
+}
+
+/* Reads the arguments of position, startpos or fen followed by the moves played since. Returns the board with the keys of the positions before it */
+fn parse_position(tokens: &[&str]) -> Result<(BoardState, Vec<u64>), String> {
+    let moves_at = tokens
+        .iter()
+        .position(|token| *token == "moves")
+        .unwrap_or(tokens.len());
+    let fen = match tokens.first() {
+        Some(&"startpos") => START_FEN.to_string(),
+        Some(&"fen") => {
+            let fields = &tokens[1..moves_at];
+            match fields.len() {
+                4 => format!("{} 0 1", fields.join(" ")),
+                6 => fields.join(" "),
+                _ => return Err(format!("{} is not a position", fields.join(" "))),
+            }
+        }
+        _ => return Err("position needs startpos or fen".to_string()),
+    };
+    let mut board = BoardState::new(&fen).map_err(|e| format!("{}: {}", fen, e))?;
+
+    let mut history = Vec::new();
+    for text in tokens.iter().skip(moves_at + 1) {
+        let mv = from_uci(&board, text).ok_or(format!("{} is not a legal move", text))?;
+        history.push(board.zobrist_key());
+        board.make_move(&mv);
+    }
+    Ok((board, history))
+}
+
+/* Turns the arguments of go into limits, anything the GUI leaves out comes from default */
+fn limits_for_go(tokens: &[&str], color: Color, default: &SearchLimits) -> SearchLimits {
+    let value = |name: &str| {
+        tokens
+            .iter()
+            .position(|token| *token == name)
+            .and_then(|index| tokens.get(index + 1))
+            .and_then(|value| value.parse::<i64>().ok())
+            .map(|value| value.max(0) as u64)
+    };
+    let (time, increment) = match color {
+        Color::White => (value("wtime"), value("winc")),
+        Color::Black => (value("btime"), value("binc")),
+    };
+
+    let milliseconds = match (value("movetime"), time) {
+        (Some(movetime), _) => Some(movetime),
+        (None, Some(time)) => {
+            let moves_to_go = value("movestogo").unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+            let share = time / moves_to_go + increment.unwrap_or(0) * 3 / 4;
+            Some(share.min(time / 2))
+        }
+        (None, None) => None,
+    };
+    SearchLimits {
+        time_to_think: milliseconds.map_or(default.time_to_think, Duration::from_millis),
+        max_depth: value("depth")
+            .map(|depth| depth as u16)
+            .or(default.max_depth),
+        node_limit: value("nodes").or(default.node_limit),
+    }
+}
+
+/* The option lines sent in answer to uci, defaults taken from the options the engine started with */
+fn write_options(output: &mut impl Write, options: &SearchOptions) -> io::Result<()> {
+    writeln!(
+        output,
+        "option name Hash type spin default {} min 1 max {}",
+        TranspositionTable::megabytes_for_entries(options.table_size),
+        MAX_HASH_MB
+    )?;
+    writeln!(
+        output,
+        "option name Contempt type spin default {} min {} max {}",
+        options.contempt, -MAX_CONTEMPT, MAX_CONTEMPT
+    )?;
+    writeln!(
+        output,
+        "option name Skill Level type spin default {} min 0 max {}",
+        options.skill.map_or(MAX_SKILL_LEVEL, |skill| skill.level),
+        MAX_SKILL_LEVEL
+    )?;
+    writeln!(output, "option name EvalParams type string default <empty>")
+}
+
+/* Applies the arguments of setoption, name followed by the option and value followed by what to set it to */
+fn set_option(options: &mut SearchOptions, seed: u64, tokens: &[&str]) -> Result<(), String> {
+    if tokens.first() != Some(&"name") {
+        return Err("setoption needs a name".to_string());
+    }
+    let value_at = tokens
+        .iter()
+        .position(|token| *token == "value")
+        .unwrap_or(tokens.len());
+    let name = tokens[1..value_at].join(" ");
+    let value = tokens.get(value_at + 1..).unwrap_or_default().join(" ");
+    let invalid = || format!("{} is not a value for {}", value, name);
+
+    //Option names aren't case sensitive
+    match name.to_lowercase().as_str() {
+        "hash" => {
+            let megabytes = value
+                .parse::<usize>()
+                .ok()
+                .filter(|megabytes| (1..=MAX_HASH_MB).contains(megabytes))
+                .ok_or_else(invalid)?;
+            options.table_size = TranspositionTable::entries_in_megabytes(megabytes);
+        }
+        "contempt" => {
+            let contempt = value.parse::<i32>().map_err(|_| invalid())?;
+            options.contempt = contempt.clamp(-MAX_CONTEMPT, MAX_CONTEMPT);
+        }
+        "skill level" => {
+            let level = value.parse::<u8>().map_err(|_| invalid())?;
+            let skill = SkillLevel::new(level, seed);
+            options.skill = (!skill.is_full_strength()).then_some(skill);
+        }
+        "evalparams" => {
+            options.eval_params = match value.as_str() {
+                "" | "<empty>" => None,
+                path => Some(Arc::new(EvalParams::load(Path::new(path))?)),
+            };
+        }
+        _ => return Err(format!("unknown option {}", name)),
+    }
+    Ok(())
+}
+
+fn write_result(
+    output: &mut impl Write,
+    board: &BoardState,
+    result: &SearchResult,
+) -> io::Result<()> {
+    //The moves of the line alternate colors, which UCI needs for castling
+    let mut color = board.active_color;
+    let pv: Vec<String> = result
+        .pv
+        .iter()
+        .map(|mv| {
+            let text = mv.to_uci(color);
+            color = color.opposite();
+            text
+        })
+        .collect();
+    let milliseconds = result.elapsed.as_millis() as u64;
+    writeln!(
+        output,
+        "info depth {} score {} nodes {} time {} nps {} pv {}",
+        result.depth,
+        format_score(result.score),
+        result.nodes_searched,
+        milliseconds,
+        result.nodes_searched * 1000 / milliseconds.max(1),
+        pv.join(" ")
+    )?;
+    match result.move_found {
+        Some(mv) => writeln!(output, "bestmove {}", mv.to_uci(board.active_color)),
+        None => writeln!(output, "bestmove 0000"),
+    }
+}
+
+/* Answers commands from input until quit or the end of input, searching with the kind of searcher given */
+pub fn run_uci(
+    kind: SearcherKind,
+    options: &SearchOptions,
+    seed: u64,
+    default_limits: &SearchLimits,
+    input: impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let mut options = options.clone();
+    let mut searcher = make_searcher(kind, &options, seed);
+    let mut board = BoardState::new(START_FEN).unwrap();
+    let mut history: Vec<u64> = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first().copied() {
+            Some("uci") => {
+                writeln!(output, "id name ReeseBot {}", env!("CARGO_PKG_VERSION"))?;
+                writeln!(output, "id author EvanSeven007")?;
+                write_options(output, &options)?;
+                writeln!(output, "uciok")?;
+            }
+            Some("isready") => writeln!(output, "readyok")?,
+            Some("setoption") => match set_option(&mut options, seed, &tokens[1..]) {
+                Ok(()) => searcher = make_searcher(kind, &options, seed),
+                Err(e) => writeln!(output, "info string {}", e)?,
+            },
+            Some("ucinewgame") => {
+                board = BoardState::new(START_FEN).unwrap();
+                history.clear();
+            }
+            Some("position") => match parse_position(&tokens[1..]) {
+                Ok((new_board, new_history)) => {
+                    board = new_board;
+                    history = new_history;
+                }
+                Err(e) => writeln!(output, "info string {}", e)?,
+            },
+            Some("go") => {
+                let limits = limits_for_go(&tokens[1..], board.active_color, default_limits);
+                let result = searcher.search(&board, &history, &limits);
+                write_result(output, &board, &result)?;
+            }
+            Some("quit") => break,
+            //stop, debug and anything unknown
+            _ => {}
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluation::NUM_EVAL_PARAMS;
+    use std::fs;
+    use std::time::Instant;
+
+    fn default_limits() -> SearchLimits {
+        SearchLimits {
+            time_to_think: Duration::from_secs(10),
+            max_depth: None,
+            node_limit: None,
+        }
+    }
+
+    #[test]
+    fn reads_go_and_position() {
+        let limits = limits_for_go(
+            &[
+                "wtime", "60000", "btime", "3000", "winc", "1000", "binc", "0",
+            ],
+            Color::White,
+            &default_limits(),
+        );
+        assert_eq!(limits.time_to_think, Duration::from_millis(2750));
+        let limits = limits_for_go(&["btime", "3000"], Color::Black, &default_limits());
+        assert_eq!(limits.time_to_think, Duration::from_millis(100));
+        let limits = limits_for_go(
+            &["depth", "4", "nodes", "500"],
+            Color::White,
+            &default_limits(),
+        );
+        assert_eq!(limits.time_to_think, Duration::from_secs(10));
+        assert_eq!(limits.max_depth, Some(4));
+        assert_eq!(limits.node_limit, Some(500));
+
+        let (board, history) =
+            parse_position(&["startpos", "moves", "e2e4", "e7e5", "g1f3"]).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(board.active_color, Color::Black);
+        assert!(parse_position(&["startpos", "moves", "e2e5"]).is_err());
+        let (board, _) = parse_position(&[
+            "fen",
+            "r3k2r/8/8/8/8/8/8/R3K2R",
+            "w",
+            "KQkq",
+            "-",
+            "moves",
+            "e1c1",
+        ])
+        .unwrap();
+        assert!(!board.castle_rights.can_castle_white_kingside);
+
+        assert_eq!(format_score(-35), "cp -35");
+        assert_eq!(format_score(MATE_VALUE - 3), "mate 2");
+        assert_eq!(format_score(-(MATE_VALUE - 2)), "mate -1");
+    }
+
+    #[test]
+    fn plays_over_uci() {
+        let input = "uci\nisready\nsetoption name Skill Level value 20\nsetoption name Ponder value true\nucinewgame\nposition fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1\ngo depth 2\nquit\ngo depth 2\n";
+        let mut output = Vec::new();
+        run_uci(
+            SearcherKind::AlphaBeta,
+            &SearchOptions::default(),
+            0,
+            &default_limits(),
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("id name ReeseBot"));
+        assert!(lines[2].starts_with("option name Hash type spin"));
+        assert_eq!(
+            lines[4],
+            "option name Skill Level type spin default 20 min 0 max 20"
+        );
+        assert_eq!(lines[6], "uciok");
+        assert_eq!(lines[7], "readyok");
+        assert_eq!(lines[8], "info string unknown option Ponder");
+        assert!(lines[9].contains("score mate 1"), "{}", lines[9]);
+        assert_eq!(lines[10], "bestmove a1a8");
+        assert_eq!(lines.len(), 11);
+    }
+
+    #[test]
+    fn sets_options() {
+        let mut options = SearchOptions::default();
+        set_option(&mut options, 7, &["name", "hash", "value", "16"]).unwrap();
+        //The table stays a power of two, so it can come out smaller than asked for
+        let megabytes = TranspositionTable::megabytes_for_entries(options.table_size);
+        assert!((9..=16).contains(&megabytes), "{}", megabytes);
+        set_option(&mut options, 7, &["name", "Contempt", "value", "-25"]).unwrap();
+        assert_eq!(options.contempt, -25);
+        set_option(&mut options, 7, &["name", "Skill", "Level", "value", "3"]).unwrap();
+        assert_eq!(
+            options.skill.map(|skill| (skill.level, skill.seed)),
+            Some((3, 7))
+        );
+        set_option(&mut options, 7, &["name", "Skill", "Level", "value", "20"]).unwrap();
+        assert!(options.skill.is_none());
+
+        let path = std::env::temp_dir().join(format!("reese_bot_uci_{}.toml", std::process::id()));
+        let params = EvalParams::from_slice(&vec![1; NUM_EVAL_PARAMS]);
+        fs::write(&path, params.to_toml()).unwrap();
+        let path_text = path.to_str().unwrap();
+        set_option(&mut options, 7, &["name", "EvalParams", "value", path_text]).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(options.eval_params.as_deref(), Some(&params));
+        set_option(&mut options, 7, &["name", "EvalParams", "value", "<empty>"]).unwrap();
+        assert!(options.eval_params.is_none());
+
+        assert!(set_option(&mut options, 7, &["name", "Hash", "value", "0"]).is_err());
+        assert!(set_option(&mut options, 7, &["name", "Contempt", "value", "lots"]).is_err());
+        assert!(set_option(
+            &mut options,
+            7,
+            &["name", "EvalParams", "value", "/no/such/file"]
+        )
+        .is_err());
+        assert!(set_option(&mut options, 7, &["name", "Threads", "value", "4"]).is_err());
+    }
+
+    #[test]
+    fn keeps_to_a_short_clock() {
+        //Three seconds on the clock leave a tenth of a second for the move
+        let input = "position startpos\ngo wtime 3000 btime 3000\nquit\n";
+        let mut output = Vec::new();
+        let start = Instant::now();
+        run_uci(
+            SearcherKind::AlphaBeta,
+            &SearchOptions::default(),
+            0,
+            &default_limits(),
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "{:?}",
+            start.elapsed()
+        );
+        assert!(String::from_utf8(output).unwrap().contains("bestmove "));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::color::Color;
+use crate::piece::{Piece, PieceType};
+
+/*
+    Zobrist keys used to hash board states, see https://www.chessprogramming.org/Zobrist_Hashing
+    The keys are generated at compile time from a fixed seed so hashes are stable between runs.
+*/
+
+const SEED: u64 = 0x5265_6573_6542_6f74; //"ReeseBot"
+
+//splitmix64, small and good enough for generating hash keys
+const fn next_key(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (state, z ^ (z >> 31))
+}
+
+const fn gen_piece_keys() -> [[u64; 64]; 12] {
+    let mut keys = [[0; 64]; 12];
+    let mut state = SEED;
+    let mut piece = 0;
+    while piece < 12 {
+        let mut square = 0;
+        while square < 64 {
+            let (next_state, key) = next_key(state);
+            state = next_state;
+            keys[piece][square] = key;
+            square += 1;
+        }
+        piece += 1;
+    }
+    keys
+}
+
+const fn gen_extra_keys() -> [u64; 13] {
+    let mut keys = [0; 13];
+    let mut state = !SEED;
+    let mut index = 0;
+    while index < 13 {
+        let (next_state, key) = next_key(state);
+        state = next_state;
+        keys[index] = key;
+        index += 1;
+    }
+    keys
+}
+
+const PIECE_KEYS: [[u64; 64]; 12] = gen_piece_keys();
+//Side to move, four castling rights and eight en passant files
+const EXTRA_KEYS: [u64; 13] = gen_extra_keys();
+
+pub const BLACK_TO_MOVE_KEY: u64 = EXTRA_KEYS[0];
+
+/* Index of a piece into the key table, white pieces first */
+fn piece_index(piece: Piece) -> usize {
+    let offset = match piece.color {
+        Color::White => 0,
+        Color::Black => 6,
+    };
+    let index = match piece.piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+        PieceType::None => panic!("something went wrong"),
+    };
+    offset + index
+}
+
+//Row and col are indices into the 12x12 board
+pub fn piece_key(piece: Piece, row: usize, col: usize) -> u64 {
+    PIECE_KEYS[piece_index(piece)][(row - 2) * 8 + (col - 2)]
+}
+
+//0 = white kingside, 1 = white queenside, 2 = black kingside, 3 = black queenside
+pub fn castle_key(right: usize) -> u64 {
+    EXTRA_KEYS[1 + right]
+}
+
+//Col is an index into the 12x12 board
+pub fn en_passant_key(col: usize) -> u64 {
+    EXTRA_KEYS[5 + (col - 2)]
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/*
+    Runs a small tournament with ReeseBot standing in for the UCI engines, started as child processes of another ReeseBot,
+    and checks that its uci mode keeps stdout to the protocol.
+*/
+
+#[test]
+fn plays_a_tournament_over_uci() {
+    let binary = env!("CARGO_BIN_EXE_reese_bot");
+    let dir = std::env::temp_dir().join(format!("reese_bot_tournament_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let openings = dir.join("openings.epd");
+    let pgn = dir.join("games.pgn");
+    fs::write(
+        &openings,
+        "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - id \"e4 e5\";\n",
+    )
+    .unwrap();
+
+    let output = Command::new(binary)
+        .arg("tournament")
+        .arg("--engine")
+        .arg(format!("cmd={},name=Reese,args=uci,depth=1", binary))
+        .arg("--engine")
+        .arg(format!(
+            "cmd={},name=Random,args=--searcher random uci",
+            binary
+        ))
+        .arg("--openings")
+        .arg(&openings)
+        .arg("--pgn")
+        .arg(&pgn)
+        .arg("--resign-score")
+        .arg("500")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Game 1: Reese vs Random"), "{}", stdout);
+    assert!(stdout.contains("Game 2: Random vs Reese"), "{}", stdout);
+    assert!(stdout.contains("Rank  Name"), "{}", stdout);
+
+    let games = fs::read_to_string(&pgn).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(games.matches("[Event \"ReeseBot tournament\"]").count(), 2);
+    //Both games are of the one round
+    assert_eq!(games.matches("[Round \"1\"]").count(), 2);
+    assert!(games.contains("[White \"Reese\"]\n[Black \"Random\"]"));
+    assert!(
+        games.contains("[FEN \"rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 1\"]")
+    );
+    assert!(games.contains("\n\n1. "));
+}
+
+#[test]
+fn writes_only_uci_to_stdout() {
+    //One node cuts the search off before its first iteration, so the engine falls back to any legal move
+    let mut child = Command::new(env!("CARGO_BIN_EXE_reese_bot"))
+        .arg("uci")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"position startpos\ngo nodes 1\nquit\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("bestmove "), "{}", stdout);
+    assert!(
+        stdout
+            .lines()
+            .all(|line| line.starts_with("info ") || line.starts_with("bestmove ")),
+        "{}",
+        stdout
+    );
+}